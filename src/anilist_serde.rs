@@ -1,24 +1,36 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::database::list_entry::{EntrySnapshot, FuzzyDate, ListEntry, ListStatus, ScoreFormat};
 
 #[derive(Clone, Debug)]
 pub enum Viewer {
-    Ok(u64),
-    Err(ViewerError),
+    /// Id of the user and how they rate
+    Ok(u64, ScoreFormat),
+    Err(AniListError),
 }
 
 #[derive(Clone, Debug)]
 pub enum MediaList {
     Ok(Box<[Collection]>),
-    Err(MediaListError),
+    Err(AniListError),
 }
 
 impl Viewer {
     pub fn deserialize_json(bytes: &[u8]) -> anyhow::Result<Self> {
         match serde_json::from_slice::<__Viewer>(bytes)? {
             __Viewer::Ok { data } => match data {
-                ViewerData::Viewer { id } => Ok(Self::Ok(id)),
+                ViewerData::Viewer {
+                    id,
+                    media_list_options,
+                } => {
+                    let score_format = media_list_options.and_then(|v| v.score_format);
+                    Ok(Self::Ok(id, score_format.unwrap_or_default()))
+                }
             },
-            __Viewer::Err(e) => Ok(Self::Err(e)),
+            __Viewer::Err(e) => Ok(Self::Err(AniListError::from_errors(&e))),
         }
     }
 }
@@ -27,48 +39,117 @@ impl MediaList {
     pub fn deserialize_json(bytes: &[u8]) -> anyhow::Result<Self> {
         match serde_json::from_slice::<__MediaList>(bytes)? {
             __MediaList::Ok { data } => Ok(Self::Ok(data.media_list_collection.lists)),
-            __MediaList::Err(e) => Ok(Self::Err(e)),
+            __MediaList::Err(e) => Ok(Self::Err(AniListError::from_errors(&e))),
         }
     }
 }
 
 impl MediaEntry {
+    /// Entries saved by `len` `SaveMediaListEntry` mutations aliased `m0`, `m1`, ..., in order.
+    /// A mutation that failed has the error whose `path` is its alias. `None` when there is no
+    /// `data`, so the whole request failed.
+    pub fn deserialize_batch(bytes: &[u8], len: usize) -> Option<Vec<Result<Self, AniListError>>> {
+        let mut batch = serde_json::from_slice::<MediaEntryBatch>(bytes).ok()?;
+        let mut data = batch.data?;
+        let entries = (0..len)
+            .map(|i| {
+                let alias = format!("m{i}");
+                if let Some(Some(entry)) = data.remove(&alias) {
+                    return Ok(entry);
+                }
+                let error = batch
+                    .errors
+                    .iter()
+                    .position(|v| v.path.first().and_then(|v| v.as_str()) == Some(&alias))
+                    .map(|pos| batch.errors.swap_remove(pos));
+                match error {
+                    Some(error) => Err(AniListError::classify(400, None, &[error])),
+                    None => Err(AniListError::Other(format!(
+                        "{alias} missing from response"
+                    ))),
+                }
+            })
+            .collect();
+        Some(entries)
+    }
+}
+
+impl MediaDetails {
     pub fn deserialize_json(bytes: &[u8]) -> anyhow::Result<Self> {
-        match serde_json::from_slice::<__MediaEntry>(bytes)? {
-            __MediaEntry::Ok { data } => Ok(data.entry),
-            __MediaEntry::Err(_e) => anyhow::bail!("Media entry request failed"),
+        match serde_json::from_slice::<__MediaDetails>(bytes)? {
+            __MediaDetails::Ok { data } => Ok(data.media),
+            __MediaDetails::Err(e) => Err(AniListError::from_errors(&e).into()),
+        }
+    }
+}
+
+impl MediaAiring {
+    /// Media of a `Page` of airing schedules.
+    pub fn deserialize_page(bytes: &[u8]) -> anyhow::Result<Vec<Self>> {
+        match serde_json::from_slice::<__MediaAiringPage>(bytes)? {
+            __MediaAiringPage::Ok { data } => {
+                let mut media = data.page.media;
+                for airing in media.iter_mut() {
+                    airing.airing_schedule.nodes.sort_by_key(|v| v.airing_at);
+                }
+                Ok(media)
+            }
+            __MediaAiringPage::Err(e) => Err(AniListError::from_errors(&e).into()),
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
-enum __MediaEntry {
-    Ok { data: SaveMediaListEntry },
-    Err(MediaEntryError),
+enum __MediaAiringPage {
+    Ok { data: MediaAiringPageData },
+    Err(GraphQlErrors),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-struct SaveMediaListEntry {
-    #[serde(rename = "SaveMediaListEntry")]
-    entry: MediaEntry,
+struct MediaAiringPageData {
+    #[serde(rename = "Page")]
+    page: MediaAiringPage,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-enum MediaEntryError {}
+struct MediaAiringPage {
+    media: Vec<MediaAiring>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum __MediaDetails {
+    Ok { data: MediaDetailsData },
+    Err(GraphQlErrors),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct MediaDetailsData {
+    #[serde(rename = "Media")]
+    media: MediaDetails,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct MediaEntryBatch {
+    #[serde(default)]
+    data: Option<HashMap<String, Option<MediaEntry>>>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 enum __Viewer {
     Ok { data: ViewerData },
-    Err(ViewerError),
+    Err(GraphQlErrors),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 enum __MediaList {
     Ok { data: MediaListCollection },
-    Err(MediaListError),
+    Err(GraphQlErrors),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -85,7 +166,9 @@ struct MediaCollectionData {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Collection {
     name: String,
-    status: String,
+    /// `None` for custom lists
+    #[serde(default)]
+    status: Option<String>,
     entries: Box<[MediaEntry]>,
 }
 
@@ -98,17 +181,31 @@ impl Collection {
         &self.name
     }
 
-    pub fn status(&self) -> &str {
-        &self.status
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_deref()
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MediaEntry {
     progress: u32,
-    #[serde(rename = "updatedAt")]
+    /// Not in list exports
+    #[serde(default, rename = "updatedAt")]
     updated_at: u64,
     media: Media,
+    #[serde(default)]
+    status: Option<ListStatus>,
+    /// Requested as `POINT_100`
+    #[serde(default)]
+    score: Option<f64>,
+    #[serde(default, rename = "startedAt")]
+    started_at: Option<FuzzyDate>,
+    #[serde(default, rename = "completedAt")]
+    completed_at: Option<FuzzyDate>,
+    #[serde(default)]
+    repeat: Option<u32>,
+    #[serde(default)]
+    notes: Option<String>,
 }
 
 impl MediaEntry {
@@ -123,23 +220,250 @@ impl MediaEntry {
     pub fn id(&self) -> u32 {
         self.media.id
     }
+
+    pub fn title(&self) -> Option<&str> {
+        self.media.title.as_ref()?.user_preferred.as_deref()
+    }
+
+    pub fn cover_image(&self) -> Option<&str> {
+        self.media.cover_image.as_ref()?.large.as_deref()
+    }
+
+    pub fn list_entry(&self) -> ListEntry {
+        ListEntry {
+            status: self.status,
+            score: self.score.unwrap_or(0.0).round() as u32,
+            started_at: self.started_at.unwrap_or_default(),
+            completed_at: self.completed_at.unwrap_or_default(),
+            repeat: self.repeat.unwrap_or(0),
+            notes: self.notes.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn snapshot(&self) -> EntrySnapshot {
+        EntrySnapshot {
+            progress: self.progress,
+            entry: self.list_entry(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Media {
     id: u32,
+    /// Only requested with the list, to show entries that are not on disk
+    #[serde(default)]
+    title: Option<MediaTitle>,
+    #[serde(default, rename = "coverImage")]
+    cover_image: Option<CoverImage>,
 }
 
-// TODO: Proper errors
-//
-// Notes: Will typically be an incorrect token error
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ViewerError {}
+struct MediaTitle {
+    #[serde(rename = "userPreferred")]
+    user_preferred: Option<String>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct MediaListError {}
+struct CoverImage {
+    large: Option<String>,
+}
+
+/// Details of a media shown on the episode screen, cached in the database.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MediaDetails {
+    id: u32,
+    description: Option<String>,
+    #[serde(default)]
+    genres: Box<[String]>,
+    #[serde(default)]
+    studios: Studios,
+    #[serde(rename = "averageScore")]
+    average_score: Option<u32>,
+    format: Option<String>,
+    episodes: Option<u32>,
+    #[serde(rename = "bannerImage")]
+    banner_image: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct Studios {
+    nodes: Box<[Studio]>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Studio {
+    name: String,
+}
+
+impl MediaDetails {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn set_description(&mut self, description: Option<String>) {
+        self.description = description;
+    }
+
+    pub fn genres(&self) -> &[String] {
+        &self.genres
+    }
+
+    pub fn studios(&self) -> impl Iterator<Item = &str> {
+        self.studios.nodes.iter().map(|v| v.name.as_str())
+    }
+
+    /// Score out of 100
+    pub fn average_score(&self) -> Option<u32> {
+        self.average_score
+    }
+
+    /// ie: "TV", "MOVIE", "OVA"
+    pub fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+
+    pub fn episodes(&self) -> Option<u32> {
+        self.episodes
+    }
+
+    pub fn banner_image(&self) -> Option<&str> {
+        self.banner_image.as_deref()
+    }
+}
+
+/// Episodes of a media that had not aired when fetched, cached in the database.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MediaAiring {
+    id: u32,
+    episodes: Option<u32>,
+    #[serde(rename = "airingSchedule")]
+    airing_schedule: AiringScheduleConnection,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct AiringScheduleConnection {
+    nodes: Box<[Airing]>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Airing {
+    pub episode: u32,
+    /// Unix time
+    #[serde(rename = "airingAt")]
+    pub airing_at: u64,
+}
+
+impl MediaAiring {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Episodes airing after `time`, soonest first.
+    pub fn upcoming(&self, time: u64) -> impl Iterator<Item = &Airing> {
+        self.airing_schedule
+            .nodes
+            .iter()
+            .filter(move |v| v.airing_at > time)
+    }
+
+    /// Latest episode aired by `time`. Episodes fetched as not aired yet count once their time
+    /// has passed, so the cache stays right while offline.
+    pub fn aired(&self, time: u64) -> Option<u32> {
+        let nodes = &self.airing_schedule.nodes;
+        match nodes.iter().rev().find(|v| v.airing_at <= time) {
+            Some(airing) => Some(airing.episode),
+            None => match nodes.first() {
+                Some(next) => Some(next.episode.saturating_sub(1)).filter(|n| *n > 0),
+                // Finished airing
+                None => self.episodes,
+            },
+        }
+    }
+}
+
+/// Error of an AniList request, told apart by the HTTP status and the GraphQL `errors`.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AniListError {
+    /// Revoked or expired, the user has to log in again
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("not found")]
+    NotFound,
+    /// Seconds until requests are accepted again
+    #[error("rate limited for {0}s")]
+    RateLimited(u64),
+    #[error("server error: {0}")]
+    ServerError(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Body of a failed request, ie: `{"errors": [{"message": "Invalid token", "status": 400}]}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GraphQlErrors {
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct GraphQlError {
+    message: String,
+    #[serde(default)]
+    status: Option<u16>,
+    /// Alias or field the error is of, ie: `["m1"]`
+    #[serde(default)]
+    path: Vec<serde_json::Value>,
+}
+
+impl AniListError {
+    /// Error of a response with an unsuccessful `status`. `retry_after` is the seconds in its
+    /// `Retry-After` header.
+    pub fn new(status: u16, retry_after: Option<u64>, body: &[u8]) -> Self {
+        let errors = serde_json::from_slice::<GraphQlErrors>(body)
+            .map(|v| v.errors)
+            .unwrap_or_default();
+        Self::classify(status, retry_after, &errors)
+    }
+
+    /// Error of a response that came back with `errors` instead of `data`.
+    pub fn from_errors(errors: &GraphQlErrors) -> Self {
+        Self::classify(400, None, &errors.errors)
+    }
+
+    fn classify(status: u16, retry_after: Option<u64>, errors: &[GraphQlError]) -> Self {
+        let error = errors.first();
+        let status = error.and_then(|v| v.status).unwrap_or(status);
+        let message = match error {
+            Some(error) => error.message.clone(),
+            None => format!("status {status}"),
+        };
+        match status {
+            429 => Self::RateLimited(retry_after.unwrap_or(60)),
+            // AniList answers an invalid token with a bad request
+            401 => Self::InvalidToken,
+            _ if message.eq_ignore_ascii_case("invalid token") => Self::InvalidToken,
+            404 => Self::NotFound,
+            500.. => Self::ServerError(message),
+            _ => Self::Other(message),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 enum ViewerData {
-    Viewer { id: u64 },
+    Viewer {
+        id: u64,
+        #[serde(default, rename = "mediaListOptions")]
+        media_list_options: Option<MediaListOptions>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct MediaListOptions {
+    #[serde(default, rename = "scoreFormat")]
+    score_format: Option<ScoreFormat>,
 }
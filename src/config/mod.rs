@@ -1,5 +1,7 @@
 use std::path::{Path, PathBuf};
 
+use crate::database::profile::DEFAULT_PROFILE;
+
 mod parser;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -7,6 +9,19 @@ pub struct Config {
     thumbnail_path: PathBuf,
     database_path: PathBuf,
     video_paths: Vec<PathBuf>,
+    romanize_kana: bool,
+    artwork_patterns: Vec<String>,
+    episode_thumbnails: bool,
+    episode_thumbnail_offset: u64,
+    watched_threshold: u64,
+    mal_client_id: Option<String>,
+    anilist_client_id: u64,
+    anilist_redirect_uri: Option<String>,
+    anilist_api_url: Option<String>,
+    http_proxy: Option<String>,
+    user_agent: Option<String>,
+    connect_timeout: u64,
+    request_timeout: u64,
 }
 struct EnvVars {
     home: String,
@@ -81,7 +96,41 @@ impl Config {
         #[cfg(debug_assertions)]
         let database_path = PathBuf::from("./aniki.db");
 
-        Self::parse(config_path, thumbnail_path, database_path, video_paths)
+        Self::parse(
+            config_path,
+            Self::with_paths(thumbnail_path, database_path, video_paths),
+        )
+    }
+
+    /// Config with the given paths and every other option left at its default.
+    pub fn with_paths(
+        thumbnail_path: PathBuf,
+        database_path: PathBuf,
+        video_paths: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            thumbnail_path,
+            database_path,
+            video_paths,
+            romanize_kana: true,
+            artwork_patterns: [
+                "cover", "folder", "poster", "*-poster", "fanart", "*-fanart",
+            ]
+            .map(String::from)
+            .to_vec(),
+            episode_thumbnails: false,
+            episode_thumbnail_offset: 90,
+            watched_threshold: 85,
+            mal_client_id: None,
+            // Redirects to a page showing the token
+            anilist_client_id: 15365,
+            anilist_redirect_uri: None,
+            anilist_api_url: None,
+            http_proxy: None,
+            user_agent: None,
+            connect_timeout: 10,
+            request_timeout: 10,
+        }
     }
 
     pub fn thumbnail_path(&self) -> &PathBuf {
@@ -96,15 +145,93 @@ impl Config {
         &self.video_paths
     }
 
+    pub fn romanize_kana(&self) -> bool {
+        self.romanize_kana
+    }
+
+    pub fn artwork_patterns(&self) -> &[String] {
+        &self.artwork_patterns
+    }
+
+    pub fn episode_thumbnails(&self) -> bool {
+        self.episode_thumbnails
+    }
+
+    /// Seconds into an episode to grab its thumbnail from.
+    pub fn episode_thumbnail_offset(&self) -> u64 {
+        self.episode_thumbnail_offset
+    }
+
+    /// Percent of an episode to play in mpv before it counts as watched, 0 to count it as
+    /// soon as it is opened.
+    pub fn watched_threshold(&self) -> u64 {
+        self.watched_threshold.min(100)
+    }
+
+    /// Client id of the MyAnimeList API application to log in with.
+    pub fn mal_client_id(&self) -> Option<&str> {
+        self.mal_client_id.as_deref()
+    }
+
+    /// Client id of the AniList API client to log in with.
+    pub fn anilist_client_id(&self) -> u64 {
+        self.anilist_client_id
+    }
+
+    /// Redirect URL registered for `anilist_client_id`. When it is a `http://localhost` url,
+    /// the token is taken from the redirect instead of being pasted.
+    pub fn anilist_redirect_uri(&self) -> Option<&str> {
+        self.anilist_redirect_uri.as_deref()
+    }
+
+    /// GraphQL endpoint to send AniList requests to instead, ie: a local mock server.
+    pub fn anilist_api_url(&self) -> Option<&str> {
+        self.anilist_api_url.as_deref()
+    }
+
+    /// `http://`, `https://` or `socks5://` proxy to send every request through.
+    pub fn http_proxy(&self) -> Option<&str> {
+        self.http_proxy.as_deref()
+    }
+
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    /// Seconds to wait for a connection.
+    pub fn connect_timeout(&self) -> u64 {
+        self.connect_timeout
+    }
+
+    /// Seconds a request may take in total.
+    pub fn request_timeout(&self) -> u64 {
+        self.request_timeout
+    }
+
+    /// Encrypted tracker credentials of `profile`, in the config directory so they are not
+    /// copied along with the database.
+    pub fn credentials_path(profile: &str) -> PathBuf {
+        let default = profile == DEFAULT_PROFILE;
+        #[cfg(debug_assertions)]
+        if true {
+            return match default {
+                true => PathBuf::from("./aniki.credentials"),
+                false => PathBuf::from(format!("./aniki-{profile}.credentials")),
+            };
+        }
+        match default {
+            true => EnvVars::new().xdg_config().join("credentials"),
+            false => EnvVars::new()
+                .xdg_config()
+                .join(format!("credentials-{profile}")),
+        }
+    }
+
     fn default_config(env_vars: &EnvVars) -> Self {
         let base_dir_path = Path::new(&env_vars.home).join("aniki");
         let database_path = base_dir_path.join("aniki.db");
         let thumbnail_path = base_dir_path.join("thumbnails");
         let video_paths = vec![];
-        Self {
-            thumbnail_path,
-            database_path,
-            video_paths,
-        }
+        Self::with_paths(thumbnail_path, database_path, video_paths)
     }
 }
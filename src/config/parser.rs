@@ -10,11 +10,26 @@ use super::Config;
 enum TokenKind {
     Ident(String),
     StringLiteral(String),
+    Boolean(bool),
+    Number(u64),
 
     // Keywords
     ThumbnailPath,
     DatabasePath,
     VideoPaths,
+    RomanizeKana,
+    ArtworkPatterns,
+    EpisodeThumbnails,
+    EpisodeThumbnailOffset,
+    WatchedThreshold,
+    MalClientId,
+    AniListClientId,
+    AniListRedirectUri,
+    AniListApiUrl,
+    HttpProxy,
+    UserAgent,
+    ConnectTimeout,
+    RequestTimeout,
 
     Newline,
     OpenBracket,
@@ -42,6 +57,19 @@ pub enum Node {
     ThumbnailPath(PathBuf),
     DatabasePath(PathBuf),
     VideoPaths(Vec<PathBuf>),
+    RomanizeKana(bool),
+    ArtworkPatterns(Vec<String>),
+    EpisodeThumbnails(bool),
+    EpisodeThumbnailOffset(u64),
+    WatchedThreshold(u64),
+    MalClientId(String),
+    AniListClientId(u64),
+    AniListRedirectUri(String),
+    AniListApiUrl(String),
+    HttpProxy(String),
+    UserAgent(String),
+    ConnectTimeout(u64),
+    RequestTimeout(u64),
 }
 
 fn expect_token(Token { kind }: &Token, expected: TokenKind) -> Result<()> {
@@ -74,6 +102,23 @@ fn parse_path_array(lexer: &mut ConfigLexer<'_>) -> Result<Vec<PathBuf>> {
     Ok(vec)
 }
 
+fn parse_string_array(lexer: &mut ConfigLexer<'_>) -> Result<Vec<String>> {
+    let mut vec = vec![];
+    loop {
+        match lexer.next_token().kind {
+            TokenKind::StringLiteral(s) => vec.push(s),
+            TokenKind::CloseBracket if vec.is_empty() => break,
+            kind => return Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
+        }
+        match lexer.next_token().kind {
+            TokenKind::Comma => (),
+            TokenKind::CloseBracket => break,
+            kind => return Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
+        }
+    }
+    Ok(vec)
+}
+
 fn cook_string(s: String) -> PathBuf {
     let p = Path::new(&s);
     match p.strip_prefix("~").ok() {
@@ -82,6 +127,32 @@ fn cook_string(s: String) -> PathBuf {
     }
 }
 
+fn next_bool(lexer: &mut ConfigLexer<'_>) -> Result<bool> {
+    match lexer.next_token().kind {
+        TokenKind::Boolean(b) => Ok(b),
+        kind => Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
+    }
+}
+
+fn next_number(lexer: &mut ConfigLexer<'_>) -> Result<u64> {
+    match lexer.next_token().kind {
+        TokenKind::Number(n) => Ok(n),
+        kind => Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
+    }
+}
+
+fn next_string(lexer: &mut ConfigLexer<'_>) -> Result<String> {
+    match lexer.next_token().kind {
+        TokenKind::StringLiteral(s) => Ok(s),
+        kind => Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
+    }
+}
+
+fn expect_line_end(lexer: &mut ConfigLexer<'_>) -> Result<()> {
+    let next_token = lexer.next_token();
+    expect_token(&next_token, TokenKind::Newline).or(expect_token(&next_token, TokenKind::Eof))
+}
+
 fn next_path(lexer: &mut ConfigLexer<'_>) -> Result<PathBuf> {
     match lexer.next_token().kind {
         TokenKind::StringLiteral(s) => Ok(cook_string(s)),
@@ -123,6 +194,94 @@ pub(super) fn next_node(lexer: &mut ConfigLexer<'_>) -> Result<Option<Node>> {
                 .or(expect_token(&next_token, TokenKind::Eof))?;
             Ok(Some(Node::VideoPaths(paths)))
         }
+        TokenKind::RomanizeKana => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let romanize_kana = next_bool(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::RomanizeKana(romanize_kana)))
+        }
+        TokenKind::ArtworkPatterns => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let patterns = match lexer.next_token().kind {
+                TokenKind::StringLiteral(s) => vec![s],
+                TokenKind::OpenBracket => parse_string_array(lexer)?,
+                kind => return Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
+            };
+            expect_line_end(lexer)?;
+            Ok(Some(Node::ArtworkPatterns(patterns)))
+        }
+        TokenKind::EpisodeThumbnails => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let episode_thumbnails = next_bool(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::EpisodeThumbnails(episode_thumbnails)))
+        }
+        TokenKind::EpisodeThumbnailOffset => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let offset = next_number(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::EpisodeThumbnailOffset(offset)))
+        }
+        TokenKind::WatchedThreshold => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let threshold = next_number(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::WatchedThreshold(threshold)))
+        }
+        TokenKind::MalClientId => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let client_id = match lexer.next_token().kind {
+                TokenKind::StringLiteral(s) => s,
+                kind => return Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
+            };
+            expect_line_end(lexer)?;
+            Ok(Some(Node::MalClientId(client_id)))
+        }
+        TokenKind::AniListClientId => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let client_id = next_number(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::AniListClientId(client_id)))
+        }
+        TokenKind::AniListRedirectUri => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let redirect_uri = match lexer.next_token().kind {
+                TokenKind::StringLiteral(s) => s,
+                kind => return Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
+            };
+            expect_line_end(lexer)?;
+            Ok(Some(Node::AniListRedirectUri(redirect_uri)))
+        }
+        TokenKind::AniListApiUrl => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let api_url = next_string(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::AniListApiUrl(api_url)))
+        }
+        TokenKind::HttpProxy => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let proxy = next_string(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::HttpProxy(proxy)))
+        }
+        TokenKind::UserAgent => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let user_agent = next_string(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::UserAgent(user_agent)))
+        }
+        TokenKind::ConnectTimeout => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let timeout = next_number(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::ConnectTimeout(timeout)))
+        }
+        TokenKind::RequestTimeout => {
+            expect_token(&lexer.next_token(), TokenKind::Assignment)?;
+            let timeout = next_number(lexer)?;
+            expect_line_end(lexer)?;
+            Ok(Some(Node::RequestTimeout(timeout)))
+        }
         TokenKind::Newline => return next_node(lexer),
         TokenKind::Eof => Ok(None),
         kind => Err(anyhow::anyhow!("Unexpected token: {kind:?}")),
@@ -130,36 +289,37 @@ pub(super) fn next_node(lexer: &mut ConfigLexer<'_>) -> Result<Option<Node>> {
 }
 
 impl Config {
-    pub fn parse(
-        config_path: PathBuf,
-        thumbnail_path: PathBuf,
-        database_path: PathBuf,
-        video_paths: Vec<PathBuf>,
-    ) -> Self {
+    pub fn parse(config_path: PathBuf, base: Config) -> Self {
         let src = std::fs::read_to_string(config_path).unwrap();
-        Self::parse_str(&src, thumbnail_path, database_path, video_paths)
+        Self::parse_str(&src, base)
     }
 
-    pub fn parse_str(
-        src: &str,
-        mut thumbnail_path: PathBuf,
-        mut database_path: PathBuf,
-        mut video_paths: Vec<PathBuf>,
-    ) -> Self {
+    /// Parses `src`, overriding options of `base` that are set.
+    pub fn parse_str(src: &str, mut base: Config) -> Self {
         let mut lexer = ConfigLexer::new(src);
         while let Some(node) = next_node(&mut lexer).unwrap() {
             match node {
-                Node::ThumbnailPath(path) => thumbnail_path = path,
-                Node::DatabasePath(path) => database_path = path,
-                Node::VideoPaths(paths) => video_paths = paths,
+                Node::ThumbnailPath(path) => base.thumbnail_path = path,
+                Node::DatabasePath(path) => base.database_path = path,
+                Node::VideoPaths(paths) => base.video_paths = paths,
+                Node::RomanizeKana(romanize_kana) => base.romanize_kana = romanize_kana,
+                Node::ArtworkPatterns(patterns) => base.artwork_patterns = patterns,
+                Node::EpisodeThumbnails(enabled) => base.episode_thumbnails = enabled,
+                Node::EpisodeThumbnailOffset(offset) => base.episode_thumbnail_offset = offset,
+                Node::WatchedThreshold(threshold) => base.watched_threshold = threshold,
+                Node::MalClientId(client_id) => base.mal_client_id = Some(client_id),
+                Node::AniListClientId(client_id) => base.anilist_client_id = client_id,
+                Node::AniListRedirectUri(redirect_uri) => {
+                    base.anilist_redirect_uri = Some(redirect_uri)
+                }
+                Node::AniListApiUrl(api_url) => base.anilist_api_url = Some(api_url),
+                Node::HttpProxy(proxy) => base.http_proxy = Some(proxy),
+                Node::UserAgent(user_agent) => base.user_agent = Some(user_agent),
+                Node::ConnectTimeout(timeout) => base.connect_timeout = timeout,
+                Node::RequestTimeout(timeout) => base.request_timeout = timeout,
             }
         }
-
-        Self {
-            thumbnail_path,
-            database_path,
-            video_paths,
-        }
+        base
     }
 }
 
@@ -204,10 +364,36 @@ impl<'a> ConfigLexer<'a> {
             "thumbnail_path" => TokenKind::ThumbnailPath,
             "database_path" => TokenKind::DatabasePath,
             "video_paths" => TokenKind::VideoPaths,
+            "romanize_kana" => TokenKind::RomanizeKana,
+            "artwork_patterns" => TokenKind::ArtworkPatterns,
+            "episode_thumbnails" => TokenKind::EpisodeThumbnails,
+            "episode_thumbnail_offset" => TokenKind::EpisodeThumbnailOffset,
+            "watched_threshold" => TokenKind::WatchedThreshold,
+            "mal_client_id" => TokenKind::MalClientId,
+            "anilist_client_id" => TokenKind::AniListClientId,
+            "anilist_redirect_uri" => TokenKind::AniListRedirectUri,
+            "anilist_api_url" => TokenKind::AniListApiUrl,
+            "http_proxy" => TokenKind::HttpProxy,
+            "user_agent" => TokenKind::UserAgent,
+            "connect_timeout" => TokenKind::ConnectTimeout,
+            "request_timeout" => TokenKind::RequestTimeout,
+            "true" => TokenKind::Boolean(true),
+            "false" => TokenKind::Boolean(false),
             _ => TokenKind::Ident(buf),
         }
     }
 
+    fn consume_number(&mut self, c: char) -> TokenKind {
+        let mut buf = String::from(c);
+        while self.peak().is_ascii_digit() {
+            buf.push(self.bump());
+        }
+        match buf.parse() {
+            Ok(n) => TokenKind::Number(n),
+            Err(_) => TokenKind::Illegal,
+        }
+    }
+
     fn consume_string_literal(&mut self, quote: char) -> TokenKind {
         let mut buf = String::new();
         loop {
@@ -253,6 +439,7 @@ impl<'a> ConfigLexer<'a> {
             '\n' | '\r' => TokenKind::Newline,
             c @ '"' | c @ '\'' => self.consume_string_literal(c),
             'a'..='z' | 'A'..='Z' => self.consume_ident(c),
+            '0'..='9' => self.consume_number(c),
             k => {
                 eprintln!("Illegal Token: {k}");
                 TokenKind::Illegal
@@ -271,7 +458,10 @@ fn parser_test_0() {
         let database_path = base_dir_path.join("aniki.db");
         let thumbnail_path = base_dir_path.join("thumbnails");
         let video_paths = vec![];
-        Config::parse_str(&src, thumbnail_path, database_path, video_paths)
+        Config::parse_str(
+            &src,
+            Config::with_paths(thumbnail_path, database_path, video_paths),
+        )
     };
 
     let database_path = base_dir_path.join("aniki.db");
@@ -280,11 +470,7 @@ fn parser_test_0() {
 
     assert_eq!(
         cfg,
-        Config {
-            thumbnail_path: PathBuf::from(path),
-            database_path,
-            video_paths
-        }
+        Config::with_paths(PathBuf::from(path), database_path, video_paths)
     );
 }
 
@@ -297,7 +483,10 @@ fn parser_test_1() {
         let database_path = base_dir_path.join("aniki.db");
         let thumbnail_path = base_dir_path.join("thumbnails");
         let video_paths = vec![];
-        Config::parse_str(&src, thumbnail_path, database_path, video_paths)
+        Config::parse_str(
+            &src,
+            Config::with_paths(thumbnail_path, database_path, video_paths),
+        )
     };
 
     let _database_path = base_dir_path.join("aniki.db");
@@ -306,11 +495,7 @@ fn parser_test_1() {
 
     assert_eq!(
         cfg,
-        Config {
-            thumbnail_path,
-            database_path: PathBuf::from(path),
-            video_paths
-        }
+        Config::with_paths(thumbnail_path, PathBuf::from(path), video_paths)
     );
 }
 
@@ -324,7 +509,10 @@ fn parser_test_2() {
         let database_path = base_dir_path.join("aniki.db");
         let thumbnail_path = base_dir_path.join("thumbnails");
         let video_paths = vec![];
-        Config::parse_str(&src, thumbnail_path, database_path, video_paths)
+        Config::parse_str(
+            &src,
+            Config::with_paths(thumbnail_path, database_path, video_paths),
+        )
     };
 
     let database_path = base_dir_path.join("aniki.db");
@@ -333,10 +521,131 @@ fn parser_test_2() {
 
     assert_eq!(
         cfg,
-        Config {
-            thumbnail_path,
-            database_path,
-            video_paths
-        }
+        Config::with_paths(thumbnail_path, database_path, video_paths)
+    );
+}
+
+#[test]
+fn parser_test_3() {
+    let src = "romanize_kana = false\nvideo_paths = \"/path\"";
+    let base_dir_path = Path::new("/");
+    let database_path = base_dir_path.join("aniki.db");
+    let thumbnail_path = base_dir_path.join("thumbnails");
+    let cfg = Config::parse_str(
+        src,
+        Config::with_paths(thumbnail_path.clone(), database_path.clone(), vec![]),
+    );
+
+    let mut expected =
+        Config::with_paths(thumbnail_path, database_path, vec![PathBuf::from("/path")]);
+    expected.romanize_kana = false;
+    assert_eq!(cfg, expected);
+}
+
+#[test]
+fn parser_test_4() {
+    let src = "artwork_patterns = [\"cover\", '*-poster']\n";
+    let base_dir_path = Path::new("/");
+    let database_path = base_dir_path.join("aniki.db");
+    let thumbnail_path = base_dir_path.join("thumbnails");
+    let cfg = Config::parse_str(
+        src,
+        Config::with_paths(thumbnail_path.clone(), database_path.clone(), vec![]),
+    );
+
+    let mut expected = Config::with_paths(thumbnail_path, database_path, vec![]);
+    expected.artwork_patterns = vec![String::from("cover"), String::from("*-poster")];
+    assert_eq!(cfg, expected);
+}
+
+#[test]
+fn parser_test_5() {
+    let src = "episode_thumbnails = true\nepisode_thumbnail_offset = 120\n";
+    let base_dir_path = Path::new("/");
+    let database_path = base_dir_path.join("aniki.db");
+    let thumbnail_path = base_dir_path.join("thumbnails");
+    let cfg = Config::parse_str(
+        src,
+        Config::with_paths(thumbnail_path.clone(), database_path.clone(), vec![]),
+    );
+
+    let mut expected = Config::with_paths(thumbnail_path, database_path, vec![]);
+    expected.episode_thumbnails = true;
+    expected.episode_thumbnail_offset = 120;
+    assert_eq!(cfg, expected);
+}
+
+#[test]
+fn parser_test_6() {
+    let src = "mal_client_id = \"0123abcd\"\n";
+    let base_dir_path = Path::new("/");
+    let database_path = base_dir_path.join("aniki.db");
+    let thumbnail_path = base_dir_path.join("thumbnails");
+    let cfg = Config::parse_str(
+        src,
+        Config::with_paths(thumbnail_path.clone(), database_path.clone(), vec![]),
+    );
+
+    let mut expected = Config::with_paths(thumbnail_path, database_path, vec![]);
+    expected.mal_client_id = Some(String::from("0123abcd"));
+    assert_eq!(cfg, expected);
+}
+
+#[test]
+fn parser_test_7() {
+    let src =
+        "anilist_client_id = 1234\nanilist_redirect_uri = \"http://localhost:8765/callback\"\n";
+    let base_dir_path = Path::new("/");
+    let database_path = base_dir_path.join("aniki.db");
+    let thumbnail_path = base_dir_path.join("thumbnails");
+    let cfg = Config::parse_str(
+        src,
+        Config::with_paths(thumbnail_path.clone(), database_path.clone(), vec![]),
+    );
+
+    let mut expected = Config::with_paths(thumbnail_path, database_path, vec![]);
+    expected.anilist_client_id = 1234;
+    expected.anilist_redirect_uri = Some(String::from("http://localhost:8765/callback"));
+    assert_eq!(cfg, expected);
+}
+
+#[test]
+fn parser_test_8() {
+    let src = r#"anilist_api_url = "http://127.0.0.1:8080"
+http_proxy = "socks5://127.0.0.1:1080"
+user_agent = "aniki"
+connect_timeout = 5
+request_timeout = 30
+"#;
+    let base_dir_path = Path::new("/");
+    let database_path = base_dir_path.join("aniki.db");
+    let thumbnail_path = base_dir_path.join("thumbnails");
+    let cfg = Config::parse_str(
+        src,
+        Config::with_paths(thumbnail_path.clone(), database_path.clone(), vec![]),
+    );
+
+    let mut expected = Config::with_paths(thumbnail_path, database_path, vec![]);
+    expected.anilist_api_url = Some(String::from("http://127.0.0.1:8080"));
+    expected.http_proxy = Some(String::from("socks5://127.0.0.1:1080"));
+    expected.user_agent = Some(String::from("aniki"));
+    expected.connect_timeout = 5;
+    expected.request_timeout = 30;
+    assert_eq!(cfg, expected);
+}
+
+#[test]
+fn parser_test_9() {
+    let src = "watched_threshold = 90\n";
+    let base_dir_path = Path::new("/");
+    let database_path = base_dir_path.join("aniki.db");
+    let thumbnail_path = base_dir_path.join("thumbnails");
+    let cfg = Config::parse_str(
+        src,
+        Config::with_paths(thumbnail_path.clone(), database_path.clone(), vec![]),
     );
+
+    let mut expected = Config::with_paths(thumbnail_path, database_path, vec![]);
+    expected.watched_threshold = 90;
+    assert_eq!(cfg, expected);
 }
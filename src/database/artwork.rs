@@ -0,0 +1,70 @@
+use std::path::Path;
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp"];
+
+/// Matches `name` against `pattern`, where `*` matches any run of characters. Case-insensitive.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match name.strip_prefix(first) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    let (last, middle) = match parts.split_last() {
+        Some(v) => v,
+        // No `*` in pattern
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Images directly inside `directory` whose name (without extension) matches one of
+/// `patterns`, ordered by the first pattern they match.
+pub fn find_artwork(directory: impl AsRef<Path>, patterns: &[String]) -> Vec<String> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(v) => v,
+        Err(_) => return vec![],
+    };
+    let mut found = entries
+        .filter_map(|v| v.ok())
+        .map(|v| v.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        })
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?;
+            let rank = patterns.iter().position(|p| glob_match(p, stem))?;
+            Some((rank, path.to_str()?.to_owned()))
+        })
+        .collect::<Vec<_>>();
+    found.sort();
+    found.into_iter().map(|(_, path)| path).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_test() {
+        assert!(glob_match("cover", "Cover"));
+        assert!(!glob_match("cover", "cover-2"));
+        assert!(glob_match("*-poster", "Made in Abyss-poster"));
+        assert!(!glob_match("*-poster", "poster"));
+        assert!(glob_match("fan*art", "fan-art"));
+        assert!(glob_match("*", "anything"));
+    }
+}
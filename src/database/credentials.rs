@@ -0,0 +1,453 @@
+//! Tracker credentials, kept out of the library database so copying the database does not leak
+//! tokens.
+//!
+//! Credentials are encrypted with XChaCha20-Poly1305. The key is kept in the Secret Service
+//! keyring when there is one, otherwise it is derived from a passphrase with Argon2.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use secret_service::{EncryptionType, SecretService};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::kitsu_serde::KitsuToken;
+use crate::mal_serde::MalToken;
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const FORMAT_VERSION: u8 = 1;
+
+/// Attributes of the keyring item holding the key.
+const KEYRING_ATTRIBUTES: [(&str, &str); 2] =
+    [("application", "aniki"), ("type", "credentials-key")];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AniListCred {
+    user_id: u64,
+    access_token: Box<str>,
+    /// Unix time `access_token` expires at, `None` if unknown
+    #[serde(default)]
+    expires_at: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MalCred {
+    user_name: Box<str>,
+    access_token: Box<str>,
+    refresh_token: Box<str>,
+    /// Unix time `access_token` expires at
+    expires_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KitsuCred {
+    user_id: u64,
+    access_token: Box<str>,
+    refresh_token: Box<str>,
+    /// Unix time `access_token` expires at
+    expires_at: u64,
+}
+
+/// Credentials of every tracker, what is stored encrypted.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Credentials {
+    pub anilist: Option<AniListCred>,
+    pub mal: Option<MalCred>,
+    pub kitsu: Option<KitsuCred>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum KeySource {
+    Keyring,
+    Passphrase,
+}
+
+/// What is written to the credentials file. Only `ciphertext` is secret.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoreFile {
+    version: u8,
+    key_source: KeySource,
+    /// Salt of the passphrase, empty when the key is in the keyring
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+/// Encrypted file holding the `Credentials`.
+pub struct CredentialStore {
+    path: PathBuf,
+    /// `None` until unlocked with a passphrase
+    key: Option<(KeySource, Zeroizing<[u8; KEY_LEN]>)>,
+    salt: [u8; SALT_LEN],
+}
+
+impl AniListCred {
+    pub fn new(user_id: u64, access_token: String, expires_at: Option<u64>) -> Self {
+        Self {
+            user_id,
+            access_token: access_token.into(),
+            expires_at,
+        }
+    }
+
+    pub fn user_id(&self) -> u64 {
+        self.user_id
+    }
+
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+
+    /// Whether the access token expires within a week of `time`. AniList tokens can not be
+    /// refreshed, so the user has to log in again.
+    pub fn expires_soon(&self, time: u64) -> bool {
+        self.expires_at
+            .is_some_and(|v| v <= time + 7 * 24 * 60 * 60)
+    }
+
+    pub fn expired(&self, time: u64) -> bool {
+        self.expires_at.is_some_and(|v| v <= time)
+    }
+
+    /// Expires the token at `time`, ie: when AniList no longer accepts it.
+    pub fn expire(&mut self, time: u64) {
+        self.expires_at = Some(self.expires_at.map_or(time, |v| v.min(time)));
+    }
+}
+
+impl MalCred {
+    pub fn new(user_name: String, token: MalToken, time: u64) -> Self {
+        Self {
+            user_name: user_name.into(),
+            access_token: token.access_token.into(),
+            refresh_token: token.refresh_token.into(),
+            expires_at: time + token.expires_in,
+        }
+    }
+
+    pub fn user_name(&self) -> &str {
+        &self.user_name
+    }
+
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+
+    pub fn refresh_token(&self) -> &str {
+        &self.refresh_token
+    }
+
+    /// Whether the access token expires within a day of `time`, so it should be refreshed.
+    pub fn expires_soon(&self, time: u64) -> bool {
+        self.expires_at <= time + 24 * 60 * 60
+    }
+}
+
+impl KitsuCred {
+    pub fn new(user_id: u64, token: KitsuToken, time: u64) -> Self {
+        Self {
+            user_id,
+            access_token: token.access_token.into(),
+            refresh_token: token.refresh_token.into(),
+            expires_at: time + token.expires_in,
+        }
+    }
+
+    pub fn user_id(&self) -> u64 {
+        self.user_id
+    }
+
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+
+    pub fn refresh_token(&self) -> &str {
+        &self.refresh_token
+    }
+
+    /// Whether the access token expires within a day of `time`, so it should be refreshed.
+    pub fn expires_soon(&self, time: u64) -> bool {
+        self.expires_at <= time + 24 * 60 * 60
+    }
+}
+
+// Tokens are wiped from memory once logged out
+impl Drop for AniListCred {
+    fn drop(&mut self) {
+        self.access_token.zeroize();
+    }
+}
+
+impl Drop for MalCred {
+    fn drop(&mut self) {
+        self.access_token.zeroize();
+        self.refresh_token.zeroize();
+    }
+}
+
+impl Drop for KitsuCred {
+    fn drop(&mut self) {
+        self.access_token.zeroize();
+        self.refresh_token.zeroize();
+    }
+}
+
+impl Credentials {
+    pub fn is_empty(&self) -> bool {
+        self.anilist.is_none() && self.mal.is_none() && self.kitsu.is_none()
+    }
+
+    /// Takes the credentials of trackers not logged in to from `other`.
+    pub fn or(self, other: Credentials) -> Credentials {
+        Credentials {
+            anilist: self.anilist.or(other.anilist),
+            mal: self.mal.or(other.mal),
+            kitsu: self.kitsu.or(other.kitsu),
+        }
+    }
+}
+
+impl CredentialStore {
+    /// Opens the store at `path`, taking the key from the keyring when there is one. Otherwise
+    /// the store stays locked until `unlock` is called with the passphrase.
+    pub async fn open(path: impl AsRef<Path>) -> Self {
+        let mut store = Self {
+            path: path.as_ref().to_path_buf(),
+            key: None,
+            salt: [0; SALT_LEN],
+        };
+        let key_source = match store.read_file() {
+            Ok(Some(file)) => file.key_source,
+            Ok(None) => KeySource::Keyring,
+            Err(e) => {
+                eprintln!("ERROR:Failed to read credentials:{e}");
+                return store;
+            }
+        };
+        if key_source == KeySource::Keyring {
+            match keyring_key().await {
+                Ok(key) => store.key = Some((KeySource::Keyring, key)),
+                Err(e) => eprintln!("ERROR:Secret Service keyring is unavailable:{e}"),
+            }
+        }
+        store
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.key.is_none()
+    }
+
+    /// Whether there are stored credentials, ie: whether `unlock` checks the passphrase or sets
+    /// a new one.
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Derives the key from `passphrase` and reads the credentials. When there is nothing stored
+    /// yet, the passphrase is used for the next `save`.
+    pub fn unlock(&mut self, passphrase: &str) -> anyhow::Result<Credentials> {
+        let (salt, file) = match self.read_file()? {
+            Some(file) if file.key_source == KeySource::Keyring => {
+                anyhow::bail!("credentials are encrypted with the key in the keyring")
+            }
+            Some(file) => {
+                let salt = file.salt.as_slice().try_into()?;
+                (salt, Some(file))
+            }
+            None => {
+                let mut salt = [0; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                (salt, None)
+            }
+        };
+        let mut key = Zeroizing::new([0; KEY_LEN]);
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut())
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        let credentials = match file {
+            Some(file) => decrypt(&key, &file).map_err(|_| anyhow::anyhow!("wrong passphrase"))?,
+            None => Credentials::default(),
+        };
+        self.key = Some((KeySource::Passphrase, key));
+        self.salt = salt;
+        Ok(credentials)
+    }
+
+    /// Reads the credentials, nothing if the store is locked or empty.
+    pub fn load(&self) -> anyhow::Result<Credentials> {
+        match (&self.key, self.read_file()?) {
+            (Some((_, key)), Some(file)) => decrypt(key, &file),
+            _ => Ok(Credentials::default()),
+        }
+    }
+
+    /// Writes `credentials` encrypted, wiping the file instead when there are none left.
+    pub fn save(&self, credentials: &Credentials) -> anyhow::Result<()> {
+        if credentials.is_empty() {
+            return self.wipe();
+        }
+        let Some((key_source, key)) = &self.key else {
+            anyhow::bail!("credential store is locked");
+        };
+        let plaintext = {
+            let mut s = flexbuffers::FlexbufferSerializer::new();
+            credentials.serialize(&mut s)?;
+            Zeroizing::new(s.take_buffer())
+        };
+        let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| anyhow::anyhow!("failed to encrypt credentials"))?;
+        let file = StoreFile {
+            version: FORMAT_VERSION,
+            key_source: *key_source,
+            salt: match key_source {
+                KeySource::Keyring => vec![],
+                KeySource::Passphrase => self.salt.to_vec(),
+            },
+            nonce: nonce.to_vec(),
+            ciphertext,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Written next to the store then moved, so a crash does not lose the credentials
+        let tmp_path = self.path.with_extension("tmp");
+        let mut f = create_private(&tmp_path)?;
+        f.write_all(&flexbuffers::to_vec(&file)?)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Overwrites the credentials file before removing it.
+    pub fn wipe(&self) -> anyhow::Result<()> {
+        let len = match std::fs::metadata(&self.path) {
+            Ok(v) => v.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut f = std::fs::OpenOptions::new().write(true).open(&self.path)?;
+        f.write_all(&vec![0; len as usize])?;
+        f.sync_all()?;
+        std::fs::remove_file(&self.path)?;
+        Ok(())
+    }
+
+    fn read_file(&self) -> anyhow::Result<Option<StoreFile>> {
+        match std::fs::read(&self.path) {
+            Ok(v) => {
+                let file = flexbuffers::from_slice::<StoreFile>(&v)?;
+                if file.version != FORMAT_VERSION {
+                    anyhow::bail!("unknown credentials format version {}", file.version);
+                }
+                Ok(Some(file))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn decrypt(key: &[u8; KEY_LEN], file: &StoreFile) -> anyhow::Result<Credentials> {
+    if file.nonce.len() != 24 {
+        anyhow::bail!("malformed credentials file");
+    }
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let plaintext = cipher
+        .decrypt(XNonce::from_slice(&file.nonce), file.ciphertext.as_slice())
+        .map(Zeroizing::new)
+        .map_err(|_| anyhow::anyhow!("failed to decrypt credentials"))?;
+    Ok(flexbuffers::from_slice(&plaintext)?)
+}
+
+/// Key held in the default collection of the keyring, created on first use.
+async fn keyring_key() -> anyhow::Result<Zeroizing<[u8; KEY_LEN]>> {
+    let ss = SecretService::connect(EncryptionType::Dh).await?;
+    let collection = ss.get_default_collection().await?;
+    collection.ensure_unlocked().await?;
+    let attributes = HashMap::from(KEYRING_ATTRIBUTES);
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    match collection.search_items(attributes.clone()).await?.first() {
+        Some(item) => {
+            item.ensure_unlocked().await?;
+            let secret = Zeroizing::new(item.get_secret().await?);
+            if secret.len() != KEY_LEN {
+                anyhow::bail!("keyring item holds no key");
+            }
+            key.copy_from_slice(&secret);
+        }
+        None => {
+            key.copy_from_slice(&XChaCha20Poly1305::generate_key(&mut OsRng));
+            collection
+                .create_item(
+                    "Aniki credentials key",
+                    attributes,
+                    key.as_ref(),
+                    true,
+                    "application/octet-stream",
+                )
+                .await?;
+        }
+    }
+    Ok(key)
+}
+
+/// Creates `path` readable by the user only.
+fn create_private(path: &Path) -> std::io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passphrase_store_test() {
+        let path = std::env::temp_dir().join(format!("aniki-credentials-{}", std::process::id()));
+        let mut store = CredentialStore {
+            path: path.clone(),
+            key: None,
+            salt: [0; SALT_LEN],
+        };
+        assert!(store.is_locked());
+        assert!(store.unlock("hunter2").unwrap().is_empty());
+
+        let credentials = Credentials {
+            anilist: Some(AniListCred::new(1, String::from("token"), None)),
+            ..Default::default()
+        };
+        store.save(&credentials).unwrap();
+        let contents = std::fs::read(&path).unwrap();
+        assert!(!contents.windows(5).any(|v| v == b"token"));
+
+        store.key = None;
+        assert!(store.unlock("wrong").is_err());
+        let loaded = store.unlock("hunter2").unwrap();
+        assert_eq!("token", loaded.anilist.as_ref().unwrap().access_token());
+        assert_eq!(
+            "token",
+            store.load().unwrap().anilist.unwrap().access_token()
+        );
+
+        // Logging out of every tracker removes the file
+        store.save(&Credentials::default()).unwrap();
+        assert!(!path.exists());
+    }
+}
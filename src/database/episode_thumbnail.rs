@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+
+use anyhow::Context;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+use super::thumbnail::fnv1a;
+
+const MAX_CONCURRENT_GRABS: usize = 2;
+const THUMBNAIL_WIDTH: u32 = 320;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EpisodeThumbnail<'a> {
+    Ready(&'a str),
+    Pending,
+    Failed,
+}
+
+#[derive(Debug)]
+struct GrabResult {
+    video: String,
+    path: String,
+    result: anyhow::Result<()>,
+}
+
+/// Grabs a frame of each episode with `ffmpegthumbnailer` or `ffmpeg`, in the background.
+#[derive(Debug)]
+pub struct EpisodeThumbnails {
+    directory: PathBuf,
+    offset: u64,
+    enabled: bool,
+    tx: mpsc::Sender<GrabResult>,
+    rx: mpsc::Receiver<GrabResult>,
+    semaphore: Arc<Semaphore>,
+    ready: BTreeMap<String, String>,
+    pending: BTreeSet<String>,
+    failed: BTreeSet<String>,
+}
+
+impl Default for EpisodeThumbnails {
+    fn default() -> Self {
+        Self::new(PathBuf::new(), 0, false)
+    }
+}
+
+impl EpisodeThumbnails {
+    pub fn new(directory: PathBuf, offset: u64, enabled: bool) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            directory,
+            offset,
+            enabled,
+            tx,
+            rx,
+            semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT_GRABS)),
+            ready: BTreeMap::new(),
+            pending: BTreeSet::new(),
+            failed: BTreeSet::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Thumbnail of `video`, queueing a grab if there is none yet.
+    pub fn get(&mut self, video: &str) -> EpisodeThumbnail<'_> {
+        if self.ready.contains_key(video) {
+            return EpisodeThumbnail::Ready(&self.ready[video]);
+        }
+        if self.pending.contains(video) {
+            return EpisodeThumbnail::Pending;
+        }
+        if self.failed.contains(video) {
+            return EpisodeThumbnail::Failed;
+        }
+
+        let path = self.directory.join(format!("{:016x}.jpg", fnv1a(video)));
+        let path = path.to_string_lossy().to_string();
+        if Path::new(&path).exists() {
+            self.ready.insert(video.to_owned(), path);
+            return EpisodeThumbnail::Ready(&self.ready[video]);
+        }
+
+        self.pending.insert(video.to_owned());
+        let tx = self.tx.clone();
+        let semaphore = Arc::clone(&self.semaphore);
+        let video = video.to_owned();
+        let offset = self.offset;
+        tokio::spawn(async move {
+            let result = match semaphore.acquire().await {
+                Ok(_permit) => grab(&video, &path, offset).await,
+                Err(e) => Err(e.into()),
+            };
+            let _ = tx.send(GrabResult {
+                video,
+                path,
+                result,
+            });
+        });
+        EpisodeThumbnail::Pending
+    }
+
+    /// Collects finished grabs. Returns whether any finished.
+    pub fn poll(&mut self) -> bool {
+        let mut any = false;
+        for GrabResult {
+            video,
+            path,
+            result,
+        } in self.rx.try_iter()
+        {
+            any = true;
+            self.pending.remove(&video);
+            match result {
+                Ok(()) => {
+                    self.ready.insert(video, path);
+                }
+                Err(e) => {
+                    eprintln!("ERROR:failed to grab thumbnail of \"{video}\":{e:#}");
+                    self.failed.insert(video);
+                }
+            }
+        }
+        any
+    }
+}
+
+/// `seconds` as `hh:mm:ss`.
+fn timestamp(seconds: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+async fn grab(video: &str, path: &str, offset: u64) -> anyhow::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // Keep the extension, `ffmpeg` picks the output format from it.
+    let tmp_path = format!("{path}.part.jpg");
+    let offset = timestamp(offset);
+    let width = THUMBNAIL_WIDTH.to_string();
+
+    let status = match Command::new("ffmpegthumbnailer")
+        .args(["-i", video, "-o", &tmp_path, "-s", &width, "-t", &offset])
+        .kill_on_drop(true)
+        .output()
+        .await
+    {
+        Err(e) if e.kind() == ErrorKind::NotFound => Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-ss", &offset, "-i", video])
+            .args([
+                "-frames:v",
+                "1",
+                "-vf",
+                &format!("scale={width}:-2"),
+                &tmp_path,
+            ])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Neither ffmpegthumbnailer nor ffmpeg could be run")?,
+        v => v?,
+    };
+    if !status.status.success() || !Path::new(&tmp_path).exists() {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        anyhow::bail!(
+            "Thumbnailer exited with {}: {}",
+            status.status,
+            String::from_utf8_lossy(&status.stderr).trim()
+        );
+    }
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_test() {
+        assert_eq!("00:01:30", timestamp(90));
+        assert_eq!("01:00:05", timestamp(3605));
+    }
+}
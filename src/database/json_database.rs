@@ -1,20 +1,29 @@
-use super::sanitize::sanitize_name;
+use super::normalize::{self, compact, normalize_title};
+use super::sanitize::{name_hints, sanitize_name, NameHints};
 use crate::database::Database;
 use fuzzy_matcher::skim::{SkimMatcherV2, SkimScoreConfig};
 use fuzzy_matcher::FuzzyMatcher;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BinaryHeap};
 use std::path::Path;
-use std::str::Chars;
 
 const JSON_RAW: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/anime-offline-database.json"));
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
 pub struct AnimeSeason {
     season: String,
     year: Option<u32>,
 }
 
+/// Episode count of the local directory and hints from its name.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchHints {
+    pub name: NameHints,
+    pub episodes: Option<u32>,
+}
+
+const HINT_WEIGHT: i64 = 40;
+
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AnimeDatabaseData {
@@ -24,6 +33,12 @@ pub struct AnimeDatabaseData {
     #[serde(rename = "picture")]
     pub thumbnail: String,
     pub tags: Box<[String]>,
+    #[serde(default)]
+    pub episodes: u32,
+    #[serde(default)]
+    pub anime_season: AnimeSeason,
+    #[serde(default)]
+    pub related_anime: Box<[String]>,
 }
 
 impl AnimeDatabaseData {
@@ -42,6 +57,50 @@ impl AnimeDatabaseData {
     pub fn tags(&self) -> &[String] {
         &self.tags
     }
+    pub fn related_anime(&self) -> &[String] {
+        &self.related_anime
+    }
+    pub fn episodes(&self) -> Option<u32> {
+        Some(self.episodes).filter(|n| *n > 0)
+    }
+    pub fn year(&self) -> Option<u32> {
+        self.anime_season.year
+    }
+
+    /// Season number from the title, assuming the first season when it is not mentioned.
+    pub fn season(&self) -> u32 {
+        self.synonyms
+            .iter()
+            .chain([&self.title])
+            .find_map(|title| name_hints(title).season)
+            .unwrap_or(1)
+    }
+
+    /// Adjusts a fuzzy match score by how well this entry fits the local directory.
+    pub fn hint_weight(&self, hints: &MatchHints) -> i64 {
+        let mut weight = 0;
+        match (hints.name.year, self.year()) {
+            (Some(a), Some(b)) if a == b => weight += HINT_WEIGHT,
+            (Some(_), Some(_)) => weight -= HINT_WEIGHT / 2,
+            _ => (),
+        }
+
+        let season = self.season();
+        match hints.name.season {
+            Some(n) if n == season => weight += HINT_WEIGHT,
+            Some(_) => weight -= HINT_WEIGHT / 2,
+            None if season > 1 => weight -= HINT_WEIGHT / 4,
+            None => (),
+        }
+
+        match (hints.episodes, self.episodes()) {
+            (Some(a), Some(b)) if a == b => weight += HINT_WEIGHT,
+            (Some(a), Some(b)) if a < b => weight += HINT_WEIGHT / 4,
+            (Some(_), Some(_)) => weight -= HINT_WEIGHT / 2,
+            _ => (),
+        }
+        weight
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,10 +114,13 @@ pub type OptimizedMap<'a> = BTreeMap<OptimizedKey, OptimizedValue<'a>>;
 type OptimizedKey = (char, char, char);
 type OptimizedValue<'a> = Vec<&'a AnimeDatabaseData>;
 
+type SourceMap<'a> = BTreeMap<&'a str, &'a AnimeDatabaseData>;
+
 #[derive(Debug)]
 struct OptimizedDatabase<'a> {
     map: Option<BTreeMap<OptimizedKey, OptimizedValue<'a>>>,
     search_map: Option<BTreeMap<OptimizedKey, OptimizedValue<'a>>>,
+    source_map: Option<SourceMap<'a>>,
 }
 
 #[derive(Debug)]
@@ -84,74 +146,40 @@ impl<'b> JsonIndexed<'b> {
     }
 
     pub fn find<'a>(&self, map: &'a OptimizedMap, key: &str) -> &'a AnimeDatabaseData {
-        let (i, j, k) = str_idx(key);
-        map.get(&(i, j, k))
+        map.get(&str_idx(&normalize_title(key)))
             .unwrap()
             .iter()
             .find(|v| v.title == key || v.synonyms.iter().any(|title| title == key))
             .unwrap()
     }
 
-    pub fn match_names<'a>(
-        &self,
-        map: &'a OptimizedMap,
-        sanitized_names: &[String],
-    ) -> Box<[Option<&'a AnimeDatabaseData>]> {
-        let matcher = skim_matcher();
-        let mut name_matches = Vec::with_capacity(sanitized_names.len());
-        let mut name_heap = BinaryHeap::new();
-        for name in sanitized_names {
-            let (i, j, k) = str_idx(name);
-            let set = match map.get(&(i, j, k)) {
-                Some(m) => m,
-                None => continue,
-            };
-            for anime in set.iter() {
-                let title = &anime.title;
-                if let Some(weight) = matcher.fuzzy_match(name, title) {
-                    name_heap.push((weight, title));
-                }
-
-                for synonym in anime.synonyms.iter() {
-                    if let Some(weight) = matcher.fuzzy_match(name, synonym) {
-                        name_heap.push((weight, synonym));
-                    }
-                }
-            }
-            match name_heap.pop() {
-                Some((_, k)) => name_matches.push(Some(self.find(map, k))),
-                None => name_matches.push(None),
-            }
-            name_heap.clear();
-        }
-        name_matches.into()
-    }
-
+    /// Finds the best match for a directory name.
+    ///
+    /// Candidates are ranked by their best title score, adjusted by `hints` so that sequels and
+    /// remakes sharing a title are told apart by year, season and episode count.
     pub fn match_name<'a>(
         &self,
         map: &'a OptimizedMap,
         sanitized_name: &str,
+        hints: &MatchHints,
     ) -> Option<&'a AnimeDatabaseData> {
         let matcher = skim_matcher();
         let mut name_heap = BinaryHeap::new();
-        let (i, j, k) = str_idx(sanitized_name);
-        let set = match map.get(&(i, j, k)) {
-            Some(m) => m,
-            None => return None,
-        };
-        for anime in set.iter() {
-            let title = &anime.title;
-            if let Some(weight) = matcher.fuzzy_match(sanitized_name, title) {
-                name_heap.push((weight, title));
-            }
-
-            for synonym in anime.synonyms.iter() {
-                if let Some(weight) = matcher.fuzzy_match(sanitized_name, synonym) {
-                    name_heap.push((weight, synonym));
-                }
+        let name = normalize_title(sanitized_name);
+        for anime in candidates(map, &name) {
+            let weight = anime
+                .synonyms
+                .iter()
+                .chain([&anime.title])
+                .filter_map(|title| {
+                    fuzzy_match_normalized(&matcher, &name, &normalize_title(title))
+                })
+                .max();
+            if let Some(weight) = weight {
+                name_heap.push((weight + anime.hint_weight(hints), anime));
             }
         }
-        name_heap.pop().map(|(_, k)| self.find(map, k))
+        name_heap.pop().map(|(_, anime)| anime)
     }
 
     pub fn map(&mut self) -> &'b OptimizedMap {
@@ -162,31 +190,37 @@ impl<'b> JsonIndexed<'b> {
         self.optimized.optimize_json_db_search(&self.json_database)
     }
 
+    /// Finds the entry listing `url` (ie: "https://anilist.co/anime/1") in its sources.
+    pub fn find_source(&mut self, url: &str) -> Option<&'b AnimeDatabaseData> {
+        self.optimized
+            .optimize_json_db_sources(&self.json_database)
+            .get(url)
+            .copied()
+    }
+
     // TODO: Some queries are extremely slow (ie: "dragon ball")
     pub fn fuzzy_find_anime(&mut self, input: &str) -> Box<[&'b AnimeDatabaseData]> {
-        let mut chars = input.trim().chars();
+        let input = normalize_title(input.trim());
+        if input.is_empty() {
+            return Box::new([]);
+        }
         let indexed_db: &mut JsonIndexed = unsafe { &mut *(self as *mut _) };
         let map = indexed_db.search_map();
         let matcher = skim_matcher();
-        let c1 = chars.next().unwrap_or('\0').to_ascii_uppercase();
-        let c2 = chars.next().unwrap_or('\0').to_ascii_uppercase();
-        let c3 = chars.next().unwrap_or('\0').to_ascii_uppercase();
-        let titles = match map.get(&(c1, c2, c3)) {
-            Some(v) => v,
-            None => return Box::new([]),
-        };
+        let titles = candidates(map, &input);
 
-        let mut name_heap: BinaryHeap<(Option<i64>, &&AnimeDatabaseData)> =
+        let mut name_heap: BinaryHeap<(Option<i64>, &AnimeDatabaseData)> =
             BinaryHeap::with_capacity(titles.len());
 
-        for anime in titles.iter() {
+        for anime in titles.into_iter() {
             for title in anime
                 .synonyms()
                 .iter()
                 .map(|v| v.as_str())
                 .chain([anime.title()].into_iter())
             {
-                match matcher.fuzzy_match(&title, input) {
+                let title = normalize_title(title);
+                match fuzzy_match_normalized(&matcher, &title, &input) {
                     Some(weight) => {
                         name_heap.push((
                             Some(weight - title.len().abs_diff(input.len()) as i64),
@@ -202,7 +236,7 @@ impl<'b> JsonIndexed<'b> {
 
         let mut vec: Vec<&AnimeDatabaseData> = Vec::with_capacity(16);
         for (_score, i) in name_heap.iter() {
-            if vec.iter().find(|v| std::ptr::eq(**i, **v)).is_none() {
+            if vec.iter().find(|v| std::ptr::eq(*i, **v)).is_none() {
                 vec.push(i);
             }
 
@@ -215,19 +249,57 @@ impl<'b> JsonIndexed<'b> {
     }
 }
 
+/// Collects every entry indexed under any of the keys of `normalized`.
+fn candidates<'a>(map: &'a OptimizedMap, normalized: &str) -> Vec<&'a AnimeDatabaseData> {
+    let mut set: Vec<&AnimeDatabaseData> = vec![];
+    for key in normalize::index_keys(normalized) {
+        if key.is_empty() {
+            continue;
+        }
+        if let Some(animes) = map.get(&str_idx(&key)) {
+            for anime in animes {
+                if !set.iter().any(|v| std::ptr::eq(*v, *anime)) {
+                    set.push(anime);
+                }
+            }
+        }
+    }
+    set
+}
+
+/// Fuzzy matches two normalized strings.
+///
+/// When either side contains kana, their romaji readings are compared as well, with whitespace
+/// removed as romanized kana has no word breaks.
+fn fuzzy_match_normalized(matcher: &SkimMatcherV2, choice: &str, pattern: &str) -> Option<i64> {
+    let direct = matcher.fuzzy_match(choice, pattern);
+    if !normalize::romanize_enabled() {
+        return direct;
+    }
+
+    let choice_romaji = normalize::romanize_kana(choice);
+    let pattern_romaji = normalize::romanize_kana(pattern);
+    if choice_romaji.is_none() && pattern_romaji.is_none() {
+        return direct;
+    }
+    let choice = compact(choice_romaji.as_deref().unwrap_or(choice));
+    let pattern = compact(pattern_romaji.as_deref().unwrap_or(pattern));
+    direct.max(matcher.fuzzy_match(&choice, &pattern))
+}
+
 fn open_json_db(_path: impl AsRef<Path>) -> AnimeDatabaseJson {
     serde_json::from_slice(JSON_RAW).unwrap()
 }
 
 macro_rules! c_idx {
     ($c: expr) => {
-        $c.to_ascii_uppercase()
+        $c.to_uppercase().next().unwrap_or($c)
     };
 }
 
 #[inline]
 fn c_filter(c: char) -> bool {
-    c.is_ascii()
+    c == '\0' || !c.is_control()
 }
 
 #[inline]
@@ -259,7 +331,7 @@ fn tokenize(s: &str) -> Box<[&str]> {
 }
 pub fn sanitize_cache_name() -> Box<[String]> {
     let mut database =
-        Database::new("./anime-cache.db", vec!["/home/bruh/Videos/not-anime"]).unwrap();
+        Database::new("./anime-cache.db", vec!["/home/bruh/Videos/not-anime"], vec![]).unwrap();
     let animes = database.animes();
     let mut sanitized_names = vec![];
     for anime in animes.iter() {
@@ -271,32 +343,23 @@ pub fn sanitize_cache_name() -> Box<[String]> {
     sanitized_names.into()
 }
 
-fn str_idx(s: &str) -> (char, char, char) {
+fn str_idx(s: &str) -> OptimizedKey {
     let mut chars = s.chars();
-    (
-        chars.next().unwrap().to_ascii_uppercase(),
-        chars.next().unwrap_or('\0').to_ascii_uppercase(),
-        chars.next().unwrap_or('\0').to_ascii_uppercase(),
-    )
-}
-
-fn insert_index_map<'a>(
-    map: &mut OptimizedMap<'a>,
-    mut chars: Chars,
-    anime: &'a AnimeDatabaseData,
-) {
-    let c = chars.next().unwrap();
+    let c = chars.next().unwrap_or('\0');
     let c2 = chars.next().unwrap_or('\0');
     let c3 = chars.next().unwrap_or('\0');
-    if c_filter(c) && c_filter(c2) && c_filter(c3) {
-        match map.get_mut(&(c_idx!(c), c_idx!(c2), c_idx!(c3))) {
-            Some(v) => {
-                v.push(anime);
-            }
-            None => {
-                map.insert((c_idx!(c), c_idx!(c2), c_idx!(c3)), Vec::new());
-            }
-        };
+    (c_idx!(c), c_idx!(c2), c_idx!(c3))
+}
+
+fn insert_index_map<'a>(map: &mut OptimizedMap<'a>, name: &str, anime: &'a AnimeDatabaseData) {
+    for key in normalize::index_keys(name) {
+        if key.is_empty() || !key.chars().take(3).all(c_filter) {
+            continue;
+        }
+        let value = map.entry(str_idx(&key)).or_default();
+        if !value.iter().any(|v| std::ptr::eq(*v, anime)) {
+            value.push(anime);
+        }
     }
 }
 
@@ -305,6 +368,7 @@ impl<'a> OptimizedDatabase<'a> {
         Self {
             map: None,
             search_map: None,
+            source_map: None,
         }
     }
 
@@ -319,8 +383,7 @@ impl<'a> OptimizedDatabase<'a> {
 
             for anime in json_database.data.iter() {
                 for name in anime.synonyms.iter().chain([&anime.title]) {
-                    let chars = name.chars();
-                    insert_index_map(&mut map, chars, anime);
+                    insert_index_map(&mut map, &normalize_title(name), anime);
                 }
             }
             map
@@ -338,13 +401,77 @@ impl<'a> OptimizedDatabase<'a> {
 
             for anime in json_database.data.iter() {
                 for name in anime.synonyms.iter().chain([&anime.title]) {
-                    for token in tokenize(&name).iter() {
-                        let chars = token.chars();
-                        insert_index_map(&mut map, chars, anime);
+                    for token in tokenize(&normalize_title(name)).iter() {
+                        insert_index_map(&mut map, token, anime);
                     }
                 }
             }
             map
         })
     }
+
+    fn optimize_json_db_sources(&mut self, json_database: &AnimeDatabaseJson) -> &'a SourceMap<'a> {
+        // See comments about unsafe use in `optimized_json_db`.
+        let json_database: &'a AnimeDatabaseJson = unsafe { &*(json_database as *const _) };
+        let source_map = self.source_map.get_or_insert_with(|| {
+            let mut map = SourceMap::new();
+            for anime in json_database.data.iter() {
+                for source in anime.sources.iter() {
+                    map.insert(source.as_str(), anime);
+                }
+            }
+            map
+        });
+        unsafe { &*(source_map as *const _) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(title: &str, year: u32, episodes: u32) -> AnimeDatabaseData {
+        AnimeDatabaseData {
+            sources: Box::new([]),
+            title: title.to_string(),
+            synonyms: Box::new([]),
+            thumbnail: String::new(),
+            tags: Box::new([]),
+            related_anime: Box::new([]),
+            episodes,
+            anime_season: AnimeSeason {
+                season: String::from("FALL"),
+                year: Some(year),
+            },
+        }
+    }
+
+    #[test]
+    fn hint_weight_year() {
+        let hints = MatchHints {
+            name: name_hints("Hunter x Hunter (2011)"),
+            episodes: Some(148),
+        };
+        let original = data("Hunter x Hunter", 1999, 62);
+        let remake = data("Hunter x Hunter (2011)", 2011, 148);
+        assert!(remake.hint_weight(&hints) > original.hint_weight(&hints));
+    }
+
+    #[test]
+    fn hint_weight_season() {
+        let first = data("Shingeki no Kyojin", 2013, 25);
+        let second = data("Shingeki no Kyojin Season 2", 2017, 12);
+
+        let hints = MatchHints {
+            name: name_hints("Shingeki no Kyojin Season 2"),
+            episodes: Some(12),
+        };
+        assert!(second.hint_weight(&hints) > first.hint_weight(&hints));
+
+        let hints = MatchHints {
+            name: name_hints("Shingeki no Kyojin"),
+            episodes: Some(25),
+        };
+        assert!(first.hint_weight(&hints) > second.hint_weight(&hints));
+    }
 }
@@ -0,0 +1,405 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// Status of an entry in the user's list, named like AniList's `MediaListStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ListStatus {
+    Current,
+    Planning,
+    Completed,
+    Dropped,
+    Paused,
+    Repeating,
+}
+
+impl ListStatus {
+    pub const ALL: [Self; 6] = [
+        Self::Current,
+        Self::Planning,
+        Self::Completed,
+        Self::Paused,
+        Self::Dropped,
+        Self::Repeating,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Current => "Watching",
+            Self::Planning => "Planning",
+            Self::Completed => "Completed",
+            Self::Dropped => "Dropped",
+            Self::Paused => "Paused",
+            Self::Repeating => "Rewatching",
+        }
+    }
+}
+
+/// How the user rates on AniList. Scores are kept out of 100 whichever it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScoreFormat {
+    #[serde(rename = "POINT_100")]
+    Point100,
+    #[serde(rename = "POINT_10_DECIMAL")]
+    Point10Decimal,
+    /// AniList's default
+    #[default]
+    #[serde(rename = "POINT_10")]
+    Point10,
+    #[serde(rename = "POINT_5")]
+    Point5,
+    #[serde(rename = "POINT_3")]
+    Point3,
+}
+
+impl ScoreFormat {
+    /// Scores out of 100 that can be picked, lowest first. The 100 point formats step by 5.
+    pub fn scores(&self) -> Vec<u32> {
+        match self {
+            Self::Point100 | Self::Point10Decimal => (5..=100).step_by(5).collect(),
+            Self::Point10 => (10..=100).step_by(10).collect(),
+            Self::Point5 => vec![20, 40, 60, 80, 100],
+            Self::Point3 => vec![35, 60, 85],
+        }
+    }
+
+    /// Next score that can be picked after `score`, staying at the highest.
+    pub fn increment(&self, score: u32) -> u32 {
+        let scores = self.scores();
+        let highest = scores.last().copied().unwrap_or(score);
+        scores.into_iter().find(|v| *v > score).unwrap_or(highest)
+    }
+
+    /// Previous score that can be picked before `score`, 0 (not scored) below the lowest.
+    pub fn decrement(&self, score: u32) -> u32 {
+        self.scores()
+            .into_iter()
+            .rev()
+            .find(|v| *v < score)
+            .unwrap_or(0)
+    }
+
+    /// `score` as AniList shows it in this format, ie: "85", "8.5", "9", "4/5" or ":)".
+    pub fn format(&self, score: u32) -> String {
+        if score == 0 {
+            return "-".to_string();
+        }
+        match self {
+            Self::Point100 => score.to_string(),
+            Self::Point10Decimal => (score as f32 / 10.0).to_string(),
+            Self::Point10 => ((score + 5) / 10).max(1).to_string(),
+            Self::Point5 => format!("{}/5", ((score + 10) / 20).clamp(1, 5)),
+            Self::Point3 if score <= 35 => ":(".to_string(),
+            Self::Point3 if score <= 60 => ":|".to_string(),
+            Self::Point3 => ":)".to_string(),
+        }
+    }
+}
+
+/// Date where any part may be unknown, like AniList's `FuzzyDate`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuzzyDate {
+    pub year: Option<u32>,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+impl FuzzyDate {
+    pub fn is_empty(&self) -> bool {
+        self.year.is_none()
+    }
+
+    /// Date (UTC) of a unix timestamp.
+    pub fn from_unix(secs: u64) -> Self {
+        let date = DateTime::from_timestamp(secs as i64, 0)
+            .unwrap_or_default()
+            .date_naive();
+        Self {
+            year: Some(date.year() as u32),
+            month: Some(date.month()),
+            day: Some(date.day()),
+        }
+    }
+
+    /// Unix timestamp of the start of the day (UTC), `None` unless the date is complete.
+    pub fn to_unix(self) -> Option<u64> {
+        let date = NaiveDate::from_ymd_opt(self.year? as i32, self.month?, self.day?)?;
+        u64::try_from(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp()).ok()
+    }
+}
+
+/// Parses the format of `Display`, ie: "2024-02-29", "2024-02" or "2024".
+impl FromStr for FuzzyDate {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '-').map(str::parse::<u32>);
+        Ok(Self {
+            year: parts.next().transpose()?,
+            month: parts.next().transpose()?,
+            day: parts.next().transpose()?,
+        })
+    }
+}
+
+impl Display for FuzzyDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.year, self.month, self.day) {
+            (Some(year), Some(month), Some(day)) => write!(f, "{year}-{month:02}-{day:02}"),
+            (Some(year), Some(month), None) => write!(f, "{year}-{month:02}"),
+            (Some(year), ..) => write!(f, "{year}"),
+            (None, ..) => write!(f, "-"),
+        }
+    }
+}
+
+/// Everything about an anime that is synced with the tracker, except progress which is derived
+/// from the current episode.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ListEntry {
+    pub status: Option<ListStatus>,
+    /// Out of 100, 0 when not scored
+    pub score: u32,
+    pub started_at: FuzzyDate,
+    pub completed_at: FuzzyDate,
+    /// Number of rewatches
+    pub repeat: u32,
+    /// Free text, only AniList stores it
+    #[serde(default)]
+    pub notes: String,
+}
+
+impl ListEntry {
+    /// Status after an episode has been played. `finished` is whether it was the last one.
+    pub fn on_watched(&mut self, finished: bool, time: u64) {
+        let today = FuzzyDate::from_unix(time);
+        self.status = match (self.status, finished) {
+            (Some(ListStatus::Repeating), true) => {
+                self.repeat += 1;
+                Some(ListStatus::Completed)
+            }
+            (Some(ListStatus::Completed), true) => Some(ListStatus::Completed),
+            (Some(ListStatus::Completed | ListStatus::Repeating), false) => {
+                Some(ListStatus::Repeating)
+            }
+            (_, true) => Some(ListStatus::Completed),
+            (_, false) => Some(ListStatus::Current),
+        };
+        if self.started_at.is_empty() {
+            self.started_at = today;
+        }
+        if finished && self.completed_at.is_empty() {
+            self.completed_at = today;
+        }
+    }
+}
+
+/// Progress and list entry as one side of a sync has them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EntrySnapshot {
+    pub progress: u32,
+    pub entry: ListEntry,
+}
+
+impl Display for EntrySnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Episode {}", self.progress)?;
+        if let Some(status) = self.entry.status {
+            write!(f, " · {}", status.as_str())?;
+        }
+        if self.entry.score > 0 {
+            write!(f, " · Score {}", self.entry.score)?;
+        }
+        if self.entry.repeat > 0 {
+            write!(f, " · Rewatched {}", self.entry.repeat)?;
+        }
+        if let Some(line) = self.entry.notes.lines().find(|v| !v.trim().is_empty()) {
+            write!(f, " · \"{}\"", line.trim())?;
+        }
+        Ok(())
+    }
+}
+
+/// State of both sides after the last successful sync, the base of a three-way merge.
+///
+/// Both are kept as the local progress can differ from the tracker's when not every episode is
+/// on disk.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncedState {
+    pub local: EntrySnapshot,
+    pub remote: EntrySnapshot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Merge {
+    Unchanged,
+    /// Only the local side changed, send it
+    Push,
+    /// Only the remote side changed, take it
+    Pull,
+    /// Both changed differently
+    Conflict,
+}
+
+/// Three-way merge of `local` and `remote` against the last synced state.
+///
+/// Without a synced state (entries from before it was stored, or never synced) the side
+/// changed last wins, going by `local_newer`.
+pub fn merge(
+    synced: Option<&SyncedState>,
+    local: &EntrySnapshot,
+    remote: &EntrySnapshot,
+    local_newer: bool,
+) -> Merge {
+    let synced = match synced {
+        Some(v) => v,
+        None if local == remote => return Merge::Unchanged,
+        None if local_newer => return Merge::Push,
+        None => return Merge::Pull,
+    };
+    let local_changed = *local != synced.local;
+    let remote_changed = *remote != synced.remote;
+    match (local_changed, remote_changed) {
+        (false, false) => Merge::Unchanged,
+        (true, false) => Merge::Push,
+        (false, true) => Merge::Pull,
+        (true, true) if local == remote => Merge::Unchanged,
+        (true, true) => Merge::Conflict,
+    }
+}
+
+/// Parses an RFC 3339 timestamp as trackers send them, ie: "2017-11-11T19:51:22+00:00".
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+    let time = DateTime::parse_from_rfc3339(s).ok()?;
+    u64::try_from(time.timestamp()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_date_test() {
+        assert_eq!("1970-01-01", FuzzyDate::from_unix(0).to_string());
+        assert_eq!("2024-02-29", FuzzyDate::from_unix(1709208000).to_string());
+        assert_eq!("-", FuzzyDate::default().to_string());
+
+        for date in ["2024-02-29", "2024-02", "2024"] {
+            assert_eq!(date, date.parse::<FuzzyDate>().unwrap().to_string());
+        }
+        assert!("2024-Feb".parse::<FuzzyDate>().is_err());
+        assert_eq!(Some(1709164800), FuzzyDate::from_unix(1709208000).to_unix());
+        assert_eq!(Some(0), FuzzyDate::from_unix(0).to_unix());
+        assert_eq!(None, "2024-02".parse::<FuzzyDate>().unwrap().to_unix());
+    }
+
+    #[test]
+    fn score_format_test() {
+        assert_eq!("-", ScoreFormat::Point10.format(0));
+        assert_eq!("85", ScoreFormat::Point100.format(85));
+        assert_eq!("8.5", ScoreFormat::Point10Decimal.format(85));
+        assert_eq!("8", ScoreFormat::Point10Decimal.format(80));
+        assert_eq!("9", ScoreFormat::Point10.format(85));
+        assert_eq!("1", ScoreFormat::Point10.format(3));
+        assert_eq!("4/5", ScoreFormat::Point5.format(75));
+        assert_eq!(":(", ScoreFormat::Point3.format(20));
+        assert_eq!(":)", ScoreFormat::Point3.format(85));
+
+        assert_eq!(90, ScoreFormat::Point10.increment(85));
+        assert_eq!(100, ScoreFormat::Point10.increment(100));
+        assert_eq!(35, ScoreFormat::Point3.increment(0));
+        assert_eq!(80, ScoreFormat::Point5.decrement(85));
+        assert_eq!(0, ScoreFormat::Point5.decrement(20));
+
+        let format = serde_json::from_str::<ScoreFormat>(r#""POINT_10_DECIMAL""#).unwrap();
+        assert_eq!(ScoreFormat::Point10Decimal, format);
+    }
+
+    #[test]
+    fn on_watched_test() {
+        let mut entry = ListEntry::default();
+        entry.on_watched(false, 0);
+        assert_eq!(Some(ListStatus::Current), entry.status);
+        assert_eq!(FuzzyDate::from_unix(0), entry.started_at);
+        assert!(entry.completed_at.is_empty());
+
+        entry.on_watched(true, 86400);
+        assert_eq!(Some(ListStatus::Completed), entry.status);
+        assert_eq!(FuzzyDate::from_unix(0), entry.started_at);
+        assert_eq!(FuzzyDate::from_unix(86400), entry.completed_at);
+
+        entry.on_watched(false, 2 * 86400);
+        assert_eq!(Some(ListStatus::Repeating), entry.status);
+        entry.on_watched(true, 3 * 86400);
+        assert_eq!(Some(ListStatus::Completed), entry.status);
+        assert_eq!(1, entry.repeat);
+        assert_eq!(FuzzyDate::from_unix(86400), entry.completed_at);
+    }
+
+    #[test]
+    fn merge_test() {
+        let snapshot = |progress| EntrySnapshot {
+            progress,
+            entry: ListEntry::default(),
+        };
+        let synced = SyncedState {
+            local: snapshot(12),
+            remote: snapshot(13),
+        };
+        let synced = Some(&synced);
+        assert_eq!(
+            Merge::Unchanged,
+            merge(synced, &snapshot(12), &snapshot(13), true)
+        );
+        assert_eq!(
+            Merge::Push,
+            merge(synced, &snapshot(10), &snapshot(13), false)
+        );
+        assert_eq!(
+            Merge::Pull,
+            merge(synced, &snapshot(12), &snapshot(14), true)
+        );
+        assert_eq!(
+            Merge::Conflict,
+            merge(synced, &snapshot(10), &snapshot(14), true)
+        );
+        assert_eq!(
+            Merge::Unchanged,
+            merge(synced, &snapshot(14), &snapshot(14), true)
+        );
+
+        assert_eq!(Merge::Push, merge(None, &snapshot(2), &snapshot(1), true));
+        assert_eq!(Merge::Pull, merge(None, &snapshot(2), &snapshot(1), false));
+        assert_eq!(
+            Merge::Unchanged,
+            merge(None, &snapshot(1), &snapshot(1), false)
+        );
+    }
+
+    #[test]
+    fn parse_timestamp_test() {
+        assert_eq!(Some(0), parse_timestamp("1970-01-01T00:00:00+00:00"));
+        assert_eq!(
+            Some(1510429882),
+            parse_timestamp("2017-11-11T19:51:22+00:00")
+        );
+        assert_eq!(
+            Some(1510429882),
+            parse_timestamp("2017-11-11T21:51:22+02:00")
+        );
+        assert_eq!(
+            Some(1510429882),
+            parse_timestamp("2017-11-11T14:51:22-05:00")
+        );
+        assert_eq!(Some(1510429882), parse_timestamp("2017-11-11T19:51:22Z"));
+        assert_eq!(
+            Some(1510429882),
+            parse_timestamp("2017-11-11T19:51:22.123Z")
+        );
+        assert_eq!(None, parse_timestamp("2017-11-11"));
+        assert_eq!(None, parse_timestamp("1969-12-31T23:59:59Z"));
+    }
+}
@@ -0,0 +1,234 @@
+//! Imports list exports, so history kept on a tracker before using aniki is not lost.
+//!
+//! Supported are MyAnimeList's XML export (also what AniList exports as "MAL XML") and
+//! AniList's `MediaListCollection` as JSON, with scores as `POINT_100`.
+
+use std::fmt::Display;
+
+use crate::anilist_serde::MediaList;
+
+use super::list_entry::{EntrySnapshot, FuzzyDate, ListEntry, ListStatus};
+use super::sync_queue::Tracker;
+
+/// Entry of a list export. Ids are of the tracker that exported the list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedEntry {
+    pub tracker: Tracker,
+    pub media_id: u32,
+    /// Only in MyAnimeList exports
+    pub title: Option<String>,
+    pub snapshot: EntrySnapshot,
+}
+
+/// Outcome of `Database::import_list`.
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    /// Number of entries that matched an anime
+    pub matched: usize,
+    pub unmatched: Vec<ImportedEntry>,
+}
+
+impl Display for ImportedEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.title {
+            Some(title) => write!(f, "{title} ({} {})", self.tracker.name(), self.media_id),
+            None => write!(f, "{} {}", self.tracker.name(), self.media_id),
+        }
+    }
+}
+
+/// Parses a list export, telling the format apart by its first character.
+pub fn parse_list(contents: &str) -> anyhow::Result<Vec<ImportedEntry>> {
+    match contents.trim_start().chars().next() {
+        Some('<') => parse_mal_xml(contents),
+        Some('{') => parse_anilist_json(contents),
+        _ => anyhow::bail!("not a MyAnimeList XML or AniList JSON export"),
+    }
+}
+
+fn parse_mal_xml(xml: &str) -> anyhow::Result<Vec<ImportedEntry>> {
+    if !xml.contains("<myanimelist>") {
+        anyhow::bail!("not a MyAnimeList XML export");
+    }
+    let mut entries = vec![];
+    for anime in xml.split("<anime>").skip(1) {
+        let anime = anime.split("</anime>").next().unwrap_or(anime);
+        let field = |tag| xml_field(anime, tag);
+        let number = |tag| field(tag).and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+        let Some(media_id) = field("series_animedb_id").and_then(|v| v.parse().ok()) else {
+            anyhow::bail!("anime without series_animedb_id");
+        };
+        let rewatching = matches!(field("my_rewatching").as_deref(), Some("1" | "YES"));
+        // Older exports number the statuses
+        let status = field("my_status").and_then(|status| match status.as_str() {
+            "Watching" | "1" if rewatching => Some(ListStatus::Repeating),
+            "Completed" | "2" if rewatching => Some(ListStatus::Repeating),
+            "Watching" | "1" => Some(ListStatus::Current),
+            "Completed" | "2" => Some(ListStatus::Completed),
+            "On-Hold" | "3" => Some(ListStatus::Paused),
+            "Dropped" | "4" => Some(ListStatus::Dropped),
+            "Plan to Watch" | "6" => Some(ListStatus::Planning),
+            _ => None,
+        });
+        entries.push(ImportedEntry {
+            tracker: Tracker::MyAnimeList,
+            media_id,
+            title: field("series_title"),
+            snapshot: EntrySnapshot {
+                progress: number("my_watched_episodes"),
+                entry: ListEntry {
+                    status,
+                    score: number("my_score").min(10) * 10,
+                    started_at: parse_mal_date(field("my_start_date")),
+                    completed_at: parse_mal_date(field("my_finish_date")),
+                    repeat: number("my_times_watched"),
+                    notes: field("my_comments").unwrap_or_default(),
+                },
+            },
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_anilist_json(json: &str) -> anyhow::Result<Vec<ImportedEntry>> {
+    let collections = match MediaList::deserialize_json(json.as_bytes()) {
+        Ok(MediaList::Ok(collections)) => collections,
+        _ => anyhow::bail!("not an AniList JSON export"),
+    };
+    // An entry is in both its status list and any custom list
+    let mut entries: Vec<ImportedEntry> = vec![];
+    for entry in collections.iter().flat_map(|v| v.entries().iter()) {
+        if entries.iter().all(|v| v.media_id != entry.id()) {
+            entries.push(ImportedEntry {
+                tracker: Tracker::AniList,
+                media_id: entry.id(),
+                title: None,
+                snapshot: entry.snapshot(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Text of the first `<tag>` in `xml`, `None` if missing or empty.
+fn xml_field(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{tag}>");
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    let text = xml[start..end].trim();
+    let text = match text
+        .strip_prefix("<![CDATA[")
+        .and_then(|v| v.strip_suffix("]]>"))
+    {
+        Some(cdata) => cdata.to_string(),
+        None => text
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+/// MyAnimeList dates have zeros for unknown parts, ie: "2017-11-00" or "0000-00-00".
+fn parse_mal_date(date: Option<String>) -> FuzzyDate {
+    let date = date
+        .and_then(|v| v.parse::<FuzzyDate>().ok())
+        .unwrap_or_default();
+    let known = |part: Option<u32>| part.filter(|v| *v > 0);
+    match known(date.year) {
+        Some(year) => FuzzyDate {
+            year: Some(year),
+            month: known(date.month),
+            day: known(date.month).and(known(date.day)),
+        },
+        None => FuzzyDate::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mal_xml_test() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<myanimelist>
+    <myinfo>
+        <user_export_type>1</user_export_type>
+    </myinfo>
+    <anime>
+        <series_animedb_id>5114</series_animedb_id>
+        <series_title><![CDATA[Fullmetal Alchemist: Brotherhood]]></series_title>
+        <my_watched_episodes>64</my_watched_episodes>
+        <my_start_date>2017-11-00</my_start_date>
+        <my_finish_date>0000-00-00</my_finish_date>
+        <my_score>9</my_score>
+        <my_status>Completed</my_status>
+        <my_times_watched>1</my_times_watched>
+        <my_rewatching>0</my_rewatching>
+        <my_comments><![CDATA[watch the dub]]></my_comments>
+    </anime>
+    <anime>
+        <series_animedb_id>1</series_animedb_id>
+        <series_title>Cowboy Bebop &amp; Friends</series_title>
+        <my_watched_episodes>3</my_watched_episodes>
+        <my_score>0</my_score>
+        <my_status>Watching</my_status>
+        <my_rewatching>1</my_rewatching>
+    </anime>
+</myanimelist>"#;
+        let entries = parse_list(xml).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(Tracker::MyAnimeList, entries[0].tracker);
+        assert_eq!(5114, entries[0].media_id);
+        assert_eq!(
+            "Fullmetal Alchemist: Brotherhood (MyAnimeList 5114)",
+            entries[0].to_string()
+        );
+        let snapshot = &entries[0].snapshot;
+        assert_eq!(64, snapshot.progress);
+        assert_eq!(Some(ListStatus::Completed), snapshot.entry.status);
+        assert_eq!(90, snapshot.entry.score);
+        assert_eq!("2017-11", snapshot.entry.started_at.to_string());
+        assert!(snapshot.entry.completed_at.is_empty());
+        assert_eq!(1, snapshot.entry.repeat);
+        assert_eq!("watch the dub", snapshot.entry.notes);
+
+        assert_eq!(Some("Cowboy Bebop & Friends"), entries[1].title.as_deref());
+        assert_eq!(
+            Some(ListStatus::Repeating),
+            entries[1].snapshot.entry.status
+        );
+        assert_eq!(0, entries[1].snapshot.entry.score);
+        assert_eq!("", entries[1].snapshot.entry.notes);
+
+        assert!(parse_list("<html></html>").is_err());
+        assert!(parse_list("name,score").is_err());
+    }
+
+    #[test]
+    fn parse_anilist_json_test() {
+        let json = r#"{"data": {"MediaListCollection": {"lists": [
+            {"name": "Watching", "status": "CURRENT", "entries": [
+                {"progress": 3, "updatedAt": 1577836800, "status": "CURRENT", "score": 85,
+                 "startedAt": {"year": 2020, "month": 1, "day": null},
+                 "completedAt": {"year": null, "month": null, "day": null},
+                 "repeat": 0, "media": {"id": 1}}
+            ]},
+            {"name": "Favourites", "status": null, "entries": [
+                {"progress": 3, "status": "CURRENT", "media": {"id": 1}}
+            ]}
+        ]}}}"#;
+        let entries = parse_list(json).unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!(Tracker::AniList, entries[0].tracker);
+        assert_eq!("AniList 1", entries[0].to_string());
+        assert_eq!(3, entries[0].snapshot.progress);
+        assert_eq!(85, entries[0].snapshot.entry.score);
+        assert_eq!("2020-01", entries[0].snapshot.entry.started_at.to_string());
+
+        assert!(parse_list(r#"{"errors": [{"message": "Invalid token"}]}"#).is_err());
+    }
+}
@@ -1,10 +1,20 @@
+pub mod artwork;
+pub mod credentials;
 pub mod episode;
+pub mod episode_thumbnail;
 pub mod json_database;
+pub mod list_entry;
+pub mod list_import;
+pub mod normalize;
+pub mod profile;
 pub mod sanitize;
+pub mod sync_queue;
+pub mod thumbnail;
 
-use anyhow::Context;
 use episode::Episode;
 use flexbuffers::{DeserializationError, SerializationError};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{metadata, read_dir, DirEntry, File};
 use std::io::Write;
 use std::path::PathBuf;
@@ -15,9 +25,17 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use walkdir::WalkDir;
 
-use crate::anilist_serde::{Collection, Media, MediaEntry};
+use crate::anilist_serde::{Airing, Collection, MediaAiring, MediaDetails, MediaEntry};
+use crate::kitsu_serde::LibraryEntry;
+use crate::mal_serde::MalEntry;
 
-use self::json_database::{AnimeDatabaseData, JsonIndexed};
+pub use self::credentials::{AniListCred, Credentials, KitsuCred, MalCred};
+use self::json_database::{AnimeDatabaseData, JsonIndexed, MatchHints};
+use self::list_entry::{EntrySnapshot, ListEntry, ListStatus, Merge, ScoreFormat, SyncedState};
+use self::list_import::{ImportReport, ImportedEntry};
+use self::profile::{ProfileState, DEFAULT_PROFILE};
+use self::sync_queue::{PendingChange, SyncQueue, Tracker};
+use self::thumbnail::{ThumbnailCache, ThumbnailResult, BANNER_MAX_SIZE, THUMBNAIL_MAX_SIZE};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SingleVideoPlayerFlag {
@@ -53,6 +71,12 @@ pub struct Anime {
 
     thumbnail: Option<String>,
     alias: Option<String>,
+    /// Set when the thumbnail was picked by the user, so it is not replaced automatically.
+    #[serde(default)]
+    thumbnail_pinned: bool,
+    /// Images in the anime's directories matching `artwork_patterns`, most preferred first.
+    #[serde(default)]
+    local_artwork: Box<[String]>,
 
     pub video_player: Option<String>,
     pub single_flags: Vec<SingleVideoPlayerFlag>,
@@ -60,12 +84,102 @@ pub struct Anime {
 
     // From JSON Database
     metadata: Option<AnimeDatabaseData>,
+
+    // From AniList
+    #[serde(default)]
+    details: Option<MediaDetails>,
+
+    /// Synced with the tracker
+    #[serde(default)]
+    list_entry: ListEntry,
+    /// Last time `list_entry` was changed locally or by the tracker
+    #[serde(default)]
+    list_updated: u64,
+    /// Both sides as of the last sync with AniList
+    #[serde(default)]
+    synced: Option<SyncedState>,
+    /// Both sides as of the last sync with MyAnimeList
+    #[serde(default)]
+    mal_synced: Option<SyncedState>,
+    /// Both sides as of the last sync with Kitsu
+    #[serde(default)]
+    kitsu_synced: Option<SyncedState>,
+    /// Upcoming episodes from AniList, fetched while watching
+    #[serde(default)]
+    airing: Option<MediaAiring>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AniListCred {
-    user_id: u64,
-    access_token: Box<str>,
+/// Entry changed both locally and on the tracker since the last sync.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub tracker: Tracker,
+    pub media_id: u32,
+    pub title: String,
+    pub local: EntrySnapshot,
+    pub remote: EntrySnapshot,
+    updated_at: u64,
+}
+
+/// Entry on the user's AniList list that no folder on disk is linked to, shown as a
+/// placeholder card until one is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteEntry {
+    pub media_id: u32,
+    pub title: String,
+    cover: Option<String>,
+    /// `cover` in the thumbnail cache, once downloaded
+    thumbnail: Option<String>,
+    pub snapshot: EntrySnapshot,
+    updated_at: u64,
+}
+
+impl RemoteEntry {
+    pub fn thumbnail(&self) -> Option<&str> {
+        self.thumbnail.as_deref()
+    }
+
+    /// Status shown on the card, ie: "Watching – not downloaded".
+    pub fn label(&self) -> String {
+        match self.snapshot.entry.status {
+            Some(ListStatus::Planning) => ListStatus::Planning.as_str().to_string(),
+            Some(status) => format!("{} – not downloaded", status.as_str()),
+            None => "Not downloaded".to_string(),
+        }
+    }
+}
+
+/// Entries of `collections` whose media is not in `linked`, most recently updated first.
+/// Thumbnails of `previous` entries are kept while their cover is the same.
+fn remote_entries(
+    collections: &[Collection],
+    linked: &BTreeSet<u32>,
+    previous: &[RemoteEntry],
+) -> Vec<RemoteEntry> {
+    let mut entries: Vec<RemoteEntry> = vec![];
+    for entry in collections.iter().flat_map(|v| v.entries()) {
+        let media_id = entry.id();
+        // Custom lists repeat entries of the status lists
+        if linked.contains(&media_id) || entries.iter().any(|v| v.media_id == media_id) {
+            continue;
+        }
+        let cover = entry.cover_image().map(str::to_owned);
+        let thumbnail = previous
+            .iter()
+            .find(|v| v.media_id == media_id && v.cover == cover)
+            .and_then(|v| v.thumbnail.clone());
+        entries.push(RemoteEntry {
+            media_id,
+            title: entry
+                .title()
+                .map_or_else(|| format!("AniList {media_id}"), str::to_owned),
+            cover,
+            thumbnail,
+            snapshot: entry.snapshot(),
+            updated_at: entry.updated_at(),
+        });
+    }
+    entries.sort_by_key(|v| Reverse(v.updated_at));
+    entries
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -73,13 +187,140 @@ pub struct Database<'a> {
     anime_map: Vec<Anime>,
     previous_update: Vec<(Box<str>, u64)>,
     skip_login: bool,
+    // Credentials used to be stored in the database, they are kept until moved into the
+    // credential store
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     anilist_cred: Option<AniListCred>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mal_cred: Option<MalCred>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kitsu_cred: Option<KitsuCred>,
+    /// Kept in the `CredentialStore`
+    #[serde(skip)]
+    credentials: Credentials,
+    #[serde(default)]
+    sync_queue: SyncQueue,
+    #[serde(default)]
+    remote_entries: Vec<RemoteEntry>,
+    /// From AniList, kept to show scores the same way offline
+    #[serde(default)]
+    score_format: ScoreFormat,
+    /// Active profile, `None` for `DEFAULT_PROFILE`
+    #[serde(default)]
+    profile: Option<String>,
+    /// The other profiles, swapped in by `switch_profile`
+    #[serde(default)]
+    profiles: BTreeMap<String, ProfileState>,
+    #[serde(skip)]
+    conflicts: Vec<Conflict>,
     #[serde(skip)]
     indexed_db: Option<JsonIndexed<'a>>,
     #[serde(skip)]
     cached_view: CachedView<'a>,
     #[serde(skip)]
-    anilist_collections: Option<Box<[Media]>>,
+    sequels: BTreeMap<u64, Option<&'a AnimeDatabaseData>>,
+    #[serde(skip)]
+    tag_filter: TagFilter,
+    #[serde(skip)]
+    tag_counts: Box<[(Box<str>, usize)]>,
+    /// Library search from the main screen, empty when not searching
+    #[serde(skip)]
+    search: String,
+    #[serde(skip)]
+    library_order: LibraryOrder,
+    /// Out of 100, 0 to show unscored anime too
+    #[serde(skip)]
+    min_score: u32,
+    #[serde(skip)]
+    thumbnails: ThumbnailCache,
+    /// Banner paths by whether they are downloaded, so drawing them does not touch the disk
+    #[serde(skip)]
+    banners: BTreeMap<String, bool>,
+    #[serde(skip)]
+    artwork_patterns: Vec<String>,
+}
+
+/// Order of the anime on the main screen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LibraryOrder {
+    #[default]
+    LastWatched,
+    /// Highest first, unscored last
+    Score,
+}
+
+impl LibraryOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::LastWatched => "Last watched",
+            Self::Score => "Score",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagState {
+    Include,
+    Exclude,
+    None,
+}
+
+/// Tags an anime must all have (include) or must not have any of (exclude) to be shown.
+#[derive(Debug, Clone, Default)]
+pub struct TagFilter {
+    include: BTreeSet<Box<str>>,
+    exclude: BTreeSet<Box<str>>,
+}
+
+impl TagFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn state(&self, tag: &str) -> TagState {
+        if self.include.contains(tag) {
+            TagState::Include
+        } else if self.exclude.contains(tag) {
+            TagState::Exclude
+        } else {
+            TagState::None
+        }
+    }
+
+    pub fn toggle_include(&mut self, tag: &str) {
+        self.exclude.remove(tag);
+        if !self.include.remove(tag) {
+            self.include.insert(tag.into());
+        }
+    }
+
+    pub fn toggle_exclude(&mut self, tag: &str) {
+        self.include.remove(tag);
+        if !self.exclude.remove(tag) {
+            self.exclude.insert(tag.into());
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.include.clear();
+        self.exclude.clear();
+    }
+
+    pub fn matches(&self, tags: &[String]) -> bool {
+        self.include
+            .iter()
+            .all(|tag| tags.iter().any(|v| v.as_str() == tag.as_ref()))
+            && !tags.iter().any(|v| self.exclude.contains(v.as_str()))
+    }
+}
+
+/// Entry related to an anime through `relatedAnime` in the json database.
+#[derive(Debug, Clone)]
+pub struct FranchiseEntry {
+    pub title: String,
+    pub year: Option<u32>,
+    /// `None` if the entry is not in the library.
+    pub idx: Option<AnimeMapIdx>,
 }
 
 #[derive(Debug, Default)]
@@ -112,6 +353,8 @@ pub enum DatabaseError {
     UTF8,
     #[error("{0}")]
     InvalidEpisode(InvalidEpisodeError),
+    #[error("AniList entry {0} is not in the anime database")]
+    UnknownMedia(u32),
 }
 
 type Err = DatabaseError;
@@ -158,7 +401,7 @@ macro_rules! o_to_str {
     };
 }
 
-fn get_time() -> u64 {
+pub fn get_time() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
@@ -192,6 +435,8 @@ impl Anime {
             episodes: Vec::new(),
             thumbnail: None,
             alias: None,
+            thumbnail_pinned: false,
+            local_artwork: Box::new([]),
             single_flags: vec![],
             pair_flags: PairVideoPlayerFlags {
                 enabled: false,
@@ -200,6 +445,13 @@ impl Anime {
             },
             video_player: None,
             metadata,
+            details: None,
+            list_entry: ListEntry::default(),
+            list_updated: 0,
+            synced: None,
+            mal_synced: None,
+            kitsu_synced: None,
+            airing: None,
         };
         anime.update_episodes();
         anime
@@ -221,6 +473,50 @@ impl Anime {
         self.thumbnail = path;
     }
 
+    /// Sets a thumbnail picked by the user. `None` goes back to choosing one automatically.
+    pub fn pin_thumbnail(&mut self, path: Option<String>) {
+        self.thumbnail_pinned = path.is_some();
+        self.thumbnail = path;
+    }
+
+    pub fn thumbnail_pinned(&self) -> bool {
+        self.thumbnail_pinned
+    }
+
+    pub fn local_artwork(&self) -> &[String] {
+        &self.local_artwork
+    }
+
+    pub fn update_local_artwork(&mut self, patterns: &[String]) {
+        let artwork = self
+            .paths
+            .iter()
+            .flat_map(|path| artwork::find_artwork(path, patterns))
+            .collect::<Vec<_>>();
+        // Artwork that was removed, or no longer matches, is not used as the thumbnail anymore
+        if let Some(thumbnail) = &self.thumbnail {
+            if !self.thumbnail_pinned
+                && self.local_artwork.contains(thumbnail)
+                && !artwork.contains(thumbnail)
+            {
+                self.thumbnail = None;
+            }
+        }
+        self.local_artwork = artwork.into();
+    }
+
+    /// Whether the thumbnail should be taken from the json database's `picture`.
+    fn wants_remote_thumbnail(&self, image_directory: impl AsRef<Path>) -> bool {
+        if self.thumbnail_pinned || !self.local_artwork.is_empty() {
+            return false;
+        }
+        // Thumbnails set before `thumbnail_pinned` existed
+        match &self.thumbnail {
+            Some(v) => is_cached_thumbnail(v, image_directory) || !Path::new(v).exists(),
+            None => true,
+        }
+    }
+
     pub fn title(&self) -> &str {
         self.metadata
             .as_ref()
@@ -239,6 +535,56 @@ impl Anime {
         self.alias = Some(s);
     }
 
+    /// Whether any of the titles, the folder name or the notes contain `query`, ignoring case.
+    pub fn matches_search(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        [
+            self.display_title(),
+            self.title(),
+            &self.filename,
+            &self.list_entry.notes,
+        ]
+        .iter()
+        .any(|v| v.to_lowercase().contains(&query))
+    }
+
+    pub fn details(&self) -> Option<&MediaDetails> {
+        self.details.as_ref()
+    }
+
+    /// Whether the anime is being watched or rewatched, going by its list entry.
+    pub fn is_watching(&self) -> bool {
+        matches!(
+            self.list_entry.status,
+            Some(ListStatus::Current | ListStatus::Repeating)
+        )
+    }
+
+    /// Next episode to air after `time`, while watching.
+    pub fn next_airing(&self, time: u64) -> Option<&Airing> {
+        let airing = self.airing.as_ref().filter(|_| self.is_watching())?;
+        airing.upcoming(time).next()
+    }
+
+    /// Latest episode aired by `time` when it is past the episodes on disk, while watching.
+    pub fn aired_not_downloaded(&self, time: u64) -> Option<u32> {
+        let airing = self.airing.as_ref().filter(|_| self.is_watching())?;
+        let downloaded = self.last_downloaded();
+        airing.aired(time).filter(|aired| *aired > downloaded)
+    }
+
+    /// Highest numbered episode on disk, 0 without any.
+    pub fn last_downloaded(&self) -> u32 {
+        self.episodes
+            .iter()
+            .filter_map(|(episode, _)| match episode {
+                Episode::Numbered { episode, .. } => Some(*episode),
+                Episode::Special { .. } => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
     pub fn set_metadata(&mut self, metadata: Option<AnimeDatabaseData>) {
         self.metadata = metadata;
     }
@@ -251,6 +597,101 @@ impl Anime {
         self.last_watched
     }
 
+    pub fn list_entry(&self) -> &ListEntry {
+        &self.list_entry
+    }
+
+    pub fn set_list_entry(&mut self, list_entry: ListEntry) {
+        self.list_entry = list_entry;
+        self.list_updated = get_time();
+    }
+
+    /// Last time anything synced with the tracker was changed.
+    pub fn last_modified(&self) -> u64 {
+        self.last_watched.max(self.list_updated)
+    }
+
+    /// Progress and list entry as they would be sent to `tracker`. `None` while watching a
+    /// special, as it has no progress.
+    pub fn local_snapshot(&self, tracker: Tracker) -> Option<EntrySnapshot> {
+        let mut entry = self.list_entry.clone();
+        if !tracker.syncs_notes() {
+            entry.notes.clear();
+        }
+        match self.current_episode {
+            Episode::Numbered { episode, .. } => Some(EntrySnapshot {
+                progress: episode,
+                entry,
+            }),
+            Episode::Special { .. } => None,
+        }
+    }
+
+    pub fn pending_change(&self, tracker: Tracker) -> Option<PendingChange> {
+        let snapshot = self.local_snapshot(tracker)?;
+        Some(PendingChange::new(
+            tracker,
+            self.tracker_id(tracker)?,
+            snapshot.progress,
+            snapshot.entry,
+            self.last_modified(),
+        ))
+    }
+
+    fn synced_mut(&mut self, tracker: Tracker) -> &mut Option<SyncedState> {
+        match tracker {
+            Tracker::AniList => &mut self.synced,
+            Tracker::MyAnimeList => &mut self.mal_synced,
+            Tracker::Kitsu => &mut self.kitsu_synced,
+        }
+    }
+
+    /// Records both sides after a successful sync with `tracker`.
+    pub fn set_synced(&mut self, tracker: Tracker, local: EntrySnapshot, remote: EntrySnapshot) {
+        *self.synced_mut(tracker) = Some(SyncedState { local, remote });
+    }
+
+    /// Takes progress and list entry from `tracker`. Notes are kept when the tracker has none.
+    fn apply_remote(&mut self, tracker: Tracker, remote: &EntrySnapshot, updated_at: u64) {
+        self.set_last_watched(updated_at);
+        self.set_progress(remote.progress);
+        let notes = std::mem::take(&mut self.list_entry.notes);
+        self.list_entry = remote.entry.clone();
+        if !tracker.syncs_notes() {
+            self.list_entry.notes = notes;
+        }
+        self.list_updated = updated_at;
+        if let Some(local) = self.local_snapshot(tracker) {
+            self.set_synced(tracker, local, remote.clone());
+        }
+    }
+
+    /// Takes progress and list entry from an imported list. Unlike `apply_remote` nothing is
+    /// recorded as synced, so the imported entry is sent to the trackers.
+    fn import_entry(&mut self, imported: &EntrySnapshot, time: u64) {
+        self.set_progress(imported.progress);
+        let notes = std::mem::take(&mut self.list_entry.notes);
+        self.list_entry = imported.entry.clone();
+        if self.list_entry.notes.is_empty() {
+            self.list_entry.notes = notes;
+        }
+        self.list_updated = time;
+    }
+
+    /// Whether the current episode is the last one, going by the episode count of the series
+    /// when known rather than the episodes that are on disk.
+    fn watched_final_episode(&self) -> bool {
+        let total = self
+            .details
+            .as_ref()
+            .and_then(|d| d.episodes())
+            .or_else(|| self.metadata.as_ref().and_then(|m| m.episodes()));
+        match (total, &self.current_episode) {
+            (Some(total), Episode::Numbered { episode, .. }) => *episode >= total,
+            _ => self.finished(),
+        }
+    }
+
     pub fn as_ptr_id(&self) -> u64 {
         self.filename.as_ptr() as u64
     }
@@ -302,7 +743,38 @@ impl Anime {
         self.episodes.len()
     }
 
+    /// Number of episodes, not counting specials.
+    pub fn numbered_len(&self) -> usize {
+        self.episodes
+            .iter()
+            .filter(|(ep, _)| matches!(ep, Episode::Numbered { .. }))
+            .count()
+    }
+
     pub fn anilist_id(&self) -> Option<u32> {
+        self.source_id("anilist.co")
+    }
+
+    pub fn mal_id(&self) -> Option<u32> {
+        self.source_id("myanimelist.net")
+    }
+
+    pub fn kitsu_id(&self) -> Option<u32> {
+        // Sources from before the move to kitsu.app are still on kitsu.io
+        self.source_id("kitsu.app")
+            .or_else(|| self.source_id("kitsu.io"))
+    }
+
+    pub fn tracker_id(&self, tracker: Tracker) -> Option<u32> {
+        match tracker {
+            Tracker::AniList => self.anilist_id(),
+            Tracker::MyAnimeList => self.mal_id(),
+            Tracker::Kitsu => self.kitsu_id(),
+        }
+    }
+
+    /// Id in the source url on `domain`, ie: 1 for "https://anilist.co/anime/1".
+    fn source_id(&self, domain: &str) -> Option<u32> {
         let metadata = match self.metadata() {
             Some(v) => v,
             None => return None,
@@ -310,7 +782,7 @@ impl Anime {
 
         for source in metadata.sources() {
             let url = reqwest::Url::parse(source.as_str()).expect("Valid url");
-            if let Some("anilist.co") = url.domain() {
+            if url.domain() == Some(domain) {
                 return Some(
                     url.path()
                         .chars()
@@ -371,6 +843,19 @@ impl Anime {
             .unwrap_or_else(|| &[])
     }
 
+    /// Whether the last episode on disk has been watched.
+    pub fn finished(&self) -> bool {
+        self.last_watched > 0
+            && matches!(self.current_episode, Episode::Numbered { .. })
+            && !self.has_next_episode()
+            && self
+                .episodes
+                .iter()
+                .rev()
+                .find(|(ep, _)| matches!(ep, Episode::Numbered { .. }))
+                .is_some_and(|(ep, _)| self.current_episode.eq(ep))
+    }
+
     pub fn has_next_episode(&self) -> bool {
         self.next_episode().is_some()
     }
@@ -461,6 +946,9 @@ impl Anime {
         let timestamp = get_time();
         self.last_watched = timestamp;
         self.current_episode = watched;
+        let finished = self.watched_final_episode();
+        self.list_entry.on_watched(finished, timestamp);
+        self.list_updated = timestamp;
     }
 
     pub fn update_watched(&mut self, watched: Episode) -> Result<()> {
@@ -487,47 +975,37 @@ fn dir_modified_time(path: impl AsRef<Path>) -> Result<u64> {
     }
 }
 
-fn download_image(url: &str, path: &str) -> anyhow::Result<()> {
-    eprintln!("Retrieving images...");
-    let url = url.to_owned();
-    let path = path.to_owned();
-    tokio::task::spawn(async {
-        let data = reqwest::get(url)
-            .await
-            .context("Failed to connect to url")?
-            .bytes()
-            .await?;
-        tokio::fs::write(path, data)
-            .await
-            .context("Failed to write to file")?;
-        Ok::<(), anyhow::Error>(())
-    });
-    Ok(())
-}
-
-impl AniListCred {
-    pub fn new(user_id: u64, access_token: String) -> Self {
-        Self {
-            user_id,
-            access_token: access_token.into(),
+/// Queues the entry of `anime` for `trackers` other than `source`, after taking it from `source`.
+fn queue_other_trackers(
+    sync_queue: &mut SyncQueue,
+    trackers: &[Tracker],
+    source: Tracker,
+    anime: &Anime,
+) {
+    for tracker in trackers.iter().filter(|v| **v != source) {
+        if let Some(change) = anime.pending_change(*tracker) {
+            sync_queue.push(change);
         }
     }
+}
 
-    pub fn user_id(&self) -> u64 {
-        self.user_id
-    }
-
-    pub fn access_token(&self) -> &str {
-        &self.access_token
-    }
+/// Whether `thumbnail` was put in `image_directory` by `retrieve_images`, rather than being
+/// picked by the user.
+fn is_cached_thumbnail(thumbnail: &str, image_directory: impl AsRef<Path>) -> bool {
+    Path::new(thumbnail).parent() == Some(image_directory.as_ref())
 }
 
 impl<'a> Database<'a> {
-    pub fn new(path: impl AsRef<str>, anime_directories: Vec<impl AsRef<str>>) -> Result<Self> {
+    pub fn new(
+        path: impl AsRef<str>,
+        anime_directories: Vec<impl AsRef<str>>,
+        artwork_patterns: Vec<String>,
+    ) -> Result<Self> {
         let path = path.as_ref();
         let mut db = match std::fs::read(path) {
             Ok(v) => {
                 let mut db = flexbuffers::from_slice::<Self>(&v)?;
+                db.artwork_patterns = artwork_patterns;
 
                 // Check if directory has been updated
                 for directory in anime_directories.iter() {
@@ -556,6 +1034,11 @@ impl<'a> Database<'a> {
                         }
                     }
                 }
+                // Artwork can be added, or the patterns changed, without the directories
+                // being modified
+                for anime in db.anime_map.iter_mut() {
+                    anime.update_local_artwork(&db.artwork_patterns);
+                }
                 db
             }
             Err(_) => {
@@ -564,9 +1047,26 @@ impl<'a> Database<'a> {
                     previous_update: vec![],
                     skip_login: false,
                     anilist_cred: None,
+                    mal_cred: None,
+                    kitsu_cred: None,
+                    credentials: Credentials::default(),
+                    sync_queue: SyncQueue::default(),
+                    remote_entries: vec![],
+                    score_format: ScoreFormat::default(),
+                    profile: None,
+                    profiles: BTreeMap::new(),
+                    conflicts: vec![],
                     indexed_db: None,
                     cached_view: CachedView::default(),
-                    anilist_collections: None,
+                    sequels: BTreeMap::new(),
+                    tag_filter: TagFilter::default(),
+                    tag_counts: Box::new([]),
+                    search: String::new(),
+                    library_order: LibraryOrder::default(),
+                    min_score: 0,
+                    thumbnails: ThumbnailCache::default(),
+                    banners: BTreeMap::new(),
+                    artwork_patterns,
                 };
                 db.update(anime_directories);
                 db
@@ -585,46 +1085,294 @@ impl<'a> Database<'a> {
         self.skip_login = v;
     }
 
+    pub fn profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// Every profile including the active one, sorted by name.
+    pub fn profile_names(&self) -> Vec<&str> {
+        let mut names = self
+            .profiles
+            .keys()
+            .map(String::as_str)
+            .chain([self.profile()])
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Swaps the progress, list entries and tracker state of the active profile for the ones
+    /// of `name`, creating it when it does not exist. The credentials are cleared, they are
+    /// read from the profile's own `CredentialStore` afterwards.
+    pub fn switch_profile(&mut self, name: &str) {
+        if name == self.profile() {
+            return;
+        }
+        let previous = ProfileState::take(self);
+        self.profiles.insert(self.profile().to_string(), previous);
+        self.profiles.remove(name).unwrap_or_default().restore(self);
+        self.profile = (name != DEFAULT_PROFILE).then(|| name.to_string());
+        self.credentials = Credentials::default();
+        self.conflicts.clear();
+        self.update_cached();
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// Replaces the credentials, ie: with the ones read from the `CredentialStore`.
+    pub fn credentials_set(&mut self, credentials: Credentials) {
+        self.credentials = credentials;
+    }
+
+    pub fn take_credentials(&mut self) -> Credentials {
+        std::mem::take(&mut self.credentials)
+    }
+
+    pub fn has_legacy_credentials(&self) -> bool {
+        self.profile.is_none()
+            && (self.anilist_cred.is_some() || self.mal_cred.is_some() || self.kitsu_cred.is_some())
+    }
+
+    /// Credentials of a database from before the `CredentialStore`, to be moved into it. They
+    /// belong to the default profile.
+    pub fn take_legacy_credentials(&mut self) -> Credentials {
+        if self.profile.is_some() {
+            return Credentials::default();
+        }
+        Credentials {
+            anilist: self.anilist_cred.take(),
+            mal: self.mal_cred.take(),
+            kitsu: self.kitsu_cred.take(),
+        }
+    }
+
+    /// `None` once the token expired, changes are kept queued until logging in again.
     pub fn anilist_access_token(&self) -> Option<&str> {
-        self.anilist_cred.as_ref().map(|v| v.access_token())
+        self.credentials
+            .anilist
+            .as_ref()
+            .filter(|v| !v.expired(get_time()))
+            .map(|v| v.access_token())
     }
 
     pub fn anilist_cred_set(&mut self, cred: Option<AniListCred>) {
-        self.anilist_cred = cred;
+        self.credentials.anilist = cred;
     }
 
     pub fn anilist_cred(&self) -> &Option<AniListCred> {
-        &self.anilist_cred
+        &self.credentials.anilist
+    }
+
+    /// Expires the AniList token, which is kept so the toolbar can ask to log in again.
+    pub fn anilist_expire(&mut self, time: u64) {
+        if let Some(cred) = self.credentials.anilist.as_mut() {
+            cred.expire(time);
+        }
     }
 
     pub fn anilist_user_id(&self) -> Option<u64> {
-        self.anilist_cred.as_ref().map(|v| v.user_id())
+        self.credentials.anilist.as_ref().map(|v| v.user_id())
     }
 
     pub fn anilist_clear(&mut self) {
-        self.anilist_cred = None;
+        self.credentials.anilist = None;
+        self.remote_entries.clear();
+        self.clear_tracker(Tracker::AniList);
+    }
+
+    pub fn mal_cred(&self) -> &Option<MalCred> {
+        &self.credentials.mal
+    }
+
+    pub fn mal_cred_set(&mut self, cred: Option<MalCred>) {
+        self.credentials.mal = cred;
+    }
+
+    pub fn mal_access_token(&self) -> Option<&str> {
+        self.credentials.mal.as_ref().map(|v| v.access_token())
+    }
+
+    pub fn mal_clear(&mut self) {
+        self.credentials.mal = None;
+        self.clear_tracker(Tracker::MyAnimeList);
+    }
+
+    pub fn kitsu_cred(&self) -> &Option<KitsuCred> {
+        &self.credentials.kitsu
+    }
+
+    pub fn kitsu_cred_set(&mut self, cred: Option<KitsuCred>) {
+        self.credentials.kitsu = cred;
+    }
+
+    pub fn kitsu_clear(&mut self) {
+        self.credentials.kitsu = None;
+        self.clear_tracker(Tracker::Kitsu);
+    }
+
+    fn clear_tracker(&mut self, tracker: Tracker) {
+        self.sync_queue.clear(tracker);
+        self.conflicts.retain(|v| v.tracker != tracker);
+    }
+
+    /// Trackers that are logged in. An expired AniList token is left out, changes for it stay
+    /// queued until logging in again.
+    pub fn trackers(&self) -> Vec<Tracker> {
+        let mut trackers = vec![];
+        if self.anilist_access_token().is_some() {
+            trackers.push(Tracker::AniList);
+        }
+        if self.credentials.mal.is_some() {
+            trackers.push(Tracker::MyAnimeList);
+        }
+        if self.credentials.kitsu.is_some() {
+            trackers.push(Tracker::Kitsu);
+        }
+        trackers
+    }
+
+    /// Queue of changes to send to the trackers, `None` when not logged in to any.
+    pub fn sync_queue<'b>(&mut self) -> Option<&'b mut SyncQueue> {
+        let trackers = self.trackers();
+        if trackers.is_empty() {
+            return None;
+        }
+        self.sync_queue.set_trackers(trackers);
+        // Unsafe is needed to untie the lifetime of `sync_queue` from `self`, so animes can be
+        // borrowed at the same time.
+        Some(unsafe { &mut *(&mut self.sync_queue as *mut _) })
+    }
+
+    pub fn pending_sync_len(&self) -> usize {
+        self.sync_queue.len()
     }
 
     pub fn len(&self) -> usize {
         self.cached_view.animes.len()
     }
 
+    /// Points thumbnails at the cache in `image_directory`, queueing downloads for missing ones.
+    ///
+    /// Thumbnails are only set once their download finishes, see `poll_thumbnails`.
     pub fn retrieve_images(&mut self, image_directory: &str) -> anyhow::Result<()> {
-        if !Path::new(image_directory).exists() {
-            std::fs::create_dir(image_directory)?;
-        }
-        for anime in &mut self.anime_map {
-            if let Some(metadata) = &anime.metadata {
-                let thumbnail_path = format!("{image_directory}/{}.jpg", metadata.title());
-                if !std::path::Path::new(&thumbnail_path).exists() {
-                    download_image(metadata.thumbnail(), &thumbnail_path)?;
+        let banner_directory = thumbnail::banner_directory(image_directory);
+        if !Path::new(&banner_directory).exists() {
+            std::fs::create_dir_all(&banner_directory)?;
+        }
+        for anime in self.anime_map.iter_mut() {
+            if !anime.thumbnail_pinned {
+                if let Some(artwork) = anime.local_artwork.first() {
+                    anime.thumbnail = Some(artwork.clone());
                 }
+            }
+            if !anime.wants_remote_thumbnail(image_directory) {
+                continue;
+            }
+            let url = match &anime.metadata {
+                Some(metadata) if !metadata.thumbnail().is_empty() => metadata.thumbnail(),
+                _ => continue,
+            };
+
+            let thumbnail_path = thumbnail::cache_path(image_directory, url);
+            if Path::new(&thumbnail_path).exists() {
                 anime.thumbnail = Some(thumbnail_path);
+            } else {
+                self.thumbnails
+                    .request(url, &thumbnail_path, THUMBNAIL_MAX_SIZE);
+            }
+        }
+        for entry in self.remote_entries.iter_mut() {
+            let url = match &entry.cover {
+                Some(url) => url,
+                None => continue,
+            };
+            let thumbnail_path = thumbnail::cache_path(image_directory, url);
+            if Path::new(&thumbnail_path).exists() {
+                entry.thumbnail = Some(thumbnail_path);
+            } else {
+                entry.thumbnail = None;
+                self.thumbnails
+                    .request(url, &thumbnail_path, THUMBNAIL_MAX_SIZE);
             }
         }
+
+        // Downloads in flight are not referenced yet, so wait until they are done.
+        if !self.thumbnails.has_pending() {
+            let keep = self
+                .anime_map
+                .iter()
+                .filter_map(|v| v.thumbnail.as_deref())
+                .chain(self.remote_entries.iter().filter_map(|v| v.thumbnail()))
+                .chain(self.profiles.values().flat_map(|v| v.remote_thumbnails()))
+                .collect();
+            thumbnail::collect_garbage(image_directory, &keep)?;
+        }
         Ok(())
     }
 
+    pub fn artwork_patterns(&self) -> &[String] {
+        &self.artwork_patterns
+    }
+
+    /// Queues a download of `url` into `path`, see `poll_thumbnails`.
+    pub fn request_thumbnail(&mut self, url: &str, path: &str) {
+        self.thumbnails.request(url, path, THUMBNAIL_MAX_SIZE);
+    }
+
+    /// Cached AniList banner of the anime, queueing a download into the banner directory of
+    /// `image_directory` if it is not cached yet. The directory is made by `retrieve_images`.
+    ///
+    /// Called every frame, so the disk is only checked the first time a banner is asked for.
+    pub fn banner(&mut self, idx: AnimeMapIdx, image_directory: &str) -> Option<String> {
+        let url = self.get_idx(idx).details()?.banner_image()?.to_owned();
+        let path = thumbnail::cache_path(&thumbnail::banner_directory(image_directory), &url);
+        let cached = *self
+            .banners
+            .entry(path.clone())
+            .or_insert_with(|| Path::new(&path).exists());
+        if cached {
+            return Some(path);
+        }
+        // Nothing is queued while the download is in flight or failed recently
+        self.thumbnails.request(&url, &path, BANNER_MAX_SIZE);
+        None
+    }
+
+    /// Sets thumbnails whose download has finished. Returns whether any download finished.
+    pub fn poll_thumbnails(&mut self) -> bool {
+        let results = self.thumbnails.poll();
+        for ThumbnailResult { url, path, result } in results.iter() {
+            if let Err(e) = result {
+                eprintln!("ERROR:failed to retrieve thumbnail {url}:{e:#}");
+                continue;
+            }
+            if let Some(cached) = self.banners.get_mut(path) {
+                *cached = true;
+                continue;
+            }
+            let image_directory = Path::new(path).parent().unwrap_or(Path::new(""));
+            for anime in self.anime_map.iter_mut() {
+                let is_source = anime
+                    .metadata
+                    .as_ref()
+                    .is_some_and(|m| m.thumbnail() == url);
+                if is_source && anime.wants_remote_thumbnail(image_directory) {
+                    anime.thumbnail = Some(path.clone());
+                }
+            }
+            for entry in self
+                .remote_entries
+                .iter_mut()
+                .filter(|v| v.cover.as_ref() == Some(url))
+            {
+                entry.thumbnail = Some(path.clone());
+            }
+        }
+        !results.is_empty()
+    }
+
     pub fn update_directory(&mut self, directory: impl AsRef<str>, time: u64, buf: &mut String) {
         let mut sanitized_name = buf;
         self.previous_update.push((directory.as_ref().into(), time));
@@ -643,6 +1391,13 @@ impl<'a> Database<'a> {
                     None => {
                         let mut chars = name.chars();
                         sanitize::sanitize_name(&mut chars, &mut sanitized_name);
+                        let name_hints = sanitize::name_hints(&name);
+                        let mut anime = Anime::from_path(path, name, None, time);
+                        anime.update_local_artwork(&self.artwork_patterns);
+                        let hints = MatchHints {
+                            name: name_hints,
+                            episodes: Some(anime.numbered_len() as u32).filter(|n| *n > 0),
+                        };
 
                         // `JsonIndexed::map()` calls to `optimize_json_db`, which
                         // does not invalidate any references.
@@ -655,9 +1410,9 @@ impl<'a> Database<'a> {
                         let metadata = self
                             .indexed_db
                             .get_or_insert_with(JsonIndexed::new)
-                            .match_name(map, sanitized_name.trim());
-                        self.anime_map
-                            .push(Anime::from_path(path, name, metadata.cloned(), time));
+                            .match_name(map, sanitized_name.trim(), &hints);
+                        anime.set_metadata(metadata.cloned());
+                        self.anime_map.push(anime);
                         sanitized_name.clear();
                     }
                     Some(v) => {
@@ -707,13 +1462,99 @@ impl<'a> Database<'a> {
         // TODO: cached_view use indices
         let anime_map: &'a mut Vec<Anime> = unsafe { &mut *(&mut self.anime_map as *mut _) };
         self.cached_view.last_updated = get_time();
-        self.cached_view.animes = anime_map
+        self.sequels.clear();
+        let animes = anime_map
             .iter_mut()
             .filter(|v| v.paths.iter().any(|v| Path::new(&v).exists()))
+            .collect::<Vec<_>>();
+
+        // Counts are taken before filtering so excluded tags stay listed.
+        let mut tag_counts = BTreeMap::<&str, usize>::new();
+        for metadata in animes.iter().filter_map(|v| v.metadata.as_ref()) {
+            for tag in metadata.tags() {
+                *tag_counts.entry(tag.as_str()).or_default() += 1;
+            }
+        }
+        let mut tag_counts = tag_counts
+            .into_iter()
+            .map(|(tag, count)| (Box::from(tag), count))
+            .collect::<Box<[_]>>();
+        tag_counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        self.tag_counts = tag_counts;
+
+        self.cached_view.animes = animes
+            .into_iter()
+            .filter(|v| {
+                let tags = v.metadata.as_ref().map_or(&[][..], |m| m.tags());
+                self.tag_filter.is_empty() || self.tag_filter.matches(tags)
+            })
+            .filter(|v| self.search.is_empty() || v.matches_search(&self.search))
+            .filter(|v| v.list_entry.score >= self.min_score)
             .collect();
         self.cached_view
             .animes
             .sort_by(|a, b| b.last_watched.cmp(&a.last_watched));
+        if self.library_order == LibraryOrder::Score {
+            // Stable, so ties stay by last watched
+            self.cached_view
+                .animes
+                .sort_by_key(|v| Reverse(v.list_entry.score));
+        }
+    }
+
+    pub fn tag_filter(&self) -> &TagFilter {
+        &self.tag_filter
+    }
+
+    /// Applies `f` to the tag filter and refreshes the cached view.
+    pub fn update_tag_filter(&mut self, f: impl FnOnce(&mut TagFilter)) {
+        f(&mut self.tag_filter);
+        self.update_cached();
+    }
+
+    /// Whether anything hides anime from the main screen.
+    pub fn is_filtered(&self) -> bool {
+        !self.tag_filter.is_empty() || !self.search.is_empty() || self.min_score > 0
+    }
+
+    pub fn library_order(&self) -> LibraryOrder {
+        self.library_order
+    }
+
+    pub fn set_library_order(&mut self, order: LibraryOrder) {
+        self.library_order = order;
+        self.update_cached();
+    }
+
+    pub fn min_score(&self) -> u32 {
+        self.min_score
+    }
+
+    /// Hides anime scored below `score`, out of 100.
+    pub fn set_min_score(&mut self, score: u32) {
+        self.min_score = score;
+        self.update_cached();
+    }
+
+    pub fn score_format(&self) -> ScoreFormat {
+        self.score_format
+    }
+
+    pub fn set_score_format(&mut self, score_format: ScoreFormat) {
+        self.score_format = score_format;
+    }
+
+    /// Shows only anime matching `query`, see `Anime::matches_search`.
+    pub fn update_search(&mut self, query: &str) {
+        if self.search != query {
+            self.search = query.to_string();
+            self.update_cached();
+        }
+    }
+
+    /// Tags across the library and how many anime have them, most common first.
+    pub fn tag_counts(&self) -> &[(Box<str>, usize)] {
+        &self.tag_counts
     }
 
     pub fn write(&mut self, path: impl AsRef<Path>) -> Result<()> {
@@ -776,26 +1617,306 @@ impl<'a> Database<'a> {
             .fuzzy_find_anime(input)
     }
 
-    pub fn update_media<'b>(&mut self, entry: &MediaEntry) -> Vec<&'b mut Anime> {
+    fn find_source_idx(&self, url: &str) -> Option<AnimeMapIdx> {
+        self.anime_map
+            .iter()
+            .position(|anime| {
+                anime
+                    .metadata()
+                    .as_ref()
+                    .is_some_and(|m| m.sources().iter().any(|v| v == url))
+            })
+            .map(AnimeMapIdx)
+    }
+
+    /// Resolves `relatedAnime` of `idx` against the library, falling back to the json database
+    /// for entries that are not in the library.
+    pub fn franchise(&mut self, idx: AnimeMapIdx) -> Box<[FranchiseEntry]> {
+        let related = match self.get_idx(idx).metadata() {
+            Some(metadata) => metadata.related_anime().to_vec(),
+            None => return Box::new([]),
+        };
+
+        let mut entries: Vec<FranchiseEntry> = vec![];
+        let mut seen: Vec<*const AnimeDatabaseData> = vec![];
+        for url in related.iter() {
+            let data = match self
+                .indexed_db
+                .get_or_insert_with(JsonIndexed::new)
+                .find_source(url)
+            {
+                Some(v) => v,
+                None => continue,
+            };
+            // `relatedAnime` lists a url per source, so the same entry shows up several times.
+            if seen.contains(&(data as *const _)) {
+                continue;
+            }
+            seen.push(data);
+
+            let idx = data
+                .sources()
+                .iter()
+                .find_map(|source| self.find_source_idx(source));
+            let title = match idx {
+                Some(idx) => self.get_idx(idx).display_title().to_owned(),
+                None => data.title().to_owned(),
+            };
+            entries.push(FranchiseEntry {
+                title,
+                year: data.year(),
+                idx,
+            });
+        }
+        entries.sort_by_key(|v| v.year);
+        entries.into_boxed_slice()
+    }
+
+    /// Earliest related entry released after `anime` that is not in the library, but only once
+    /// `anime` has been watched to the end.
+    pub fn sequel(&mut self, anime: &Anime) -> Option<&'a AnimeDatabaseData> {
+        if !anime.finished() {
+            return None;
+        }
+        let id = anime.as_ptr_id();
+        if let Some(sequel) = self.sequels.get(&id) {
+            return *sequel;
+        }
+
+        let sequel = anime.metadata().as_ref().and_then(|metadata| {
+            let key = (metadata.year()?, metadata.season());
+            let indexed_db = self.indexed_db.get_or_insert_with(JsonIndexed::new);
+            // See `update_directory` on untying `indexed_db` from `self`.
+            let indexed_db: &mut JsonIndexed<'a> = unsafe { &mut *(indexed_db as *mut _) };
+            metadata
+                .related_anime()
+                .iter()
+                .filter_map(|url| indexed_db.find_source(url))
+                .filter(|data| data.year().is_some_and(|year| (year, data.season()) > key))
+                .filter(|data| {
+                    !data
+                        .sources()
+                        .iter()
+                        .any(|source| self.find_source_idx(source).is_some())
+                })
+                .min_by_key(|data| (data.year(), data.season()))
+        });
+        self.sequels.insert(id, sequel);
+        sequel
+    }
+
+    /// Drops the cached `sequel` of `anime`, ie: after its metadata changed.
+    pub fn forget_sequel(&mut self, anime: &Anime) {
+        self.sequels.remove(&anime.as_ptr_id());
+    }
+
+    /// AniList ids of the anime being watched, to fetch airing schedules of.
+    pub fn airing_media_ids(&self) -> Vec<u32> {
+        let ids = self
+            .anime_map
+            .iter()
+            .filter(|v| v.is_watching())
+            .filter_map(Anime::anilist_id)
+            .collect::<BTreeSet<_>>();
+        ids.into_iter().collect()
+    }
+
+    pub fn update_airing(&mut self, schedules: &[MediaAiring]) {
+        for airing in schedules {
+            for anime in self
+                .anime_map
+                .iter_mut()
+                .filter(|v| v.anilist_id() == Some(airing.id()))
+            {
+                anime.airing = Some(airing.clone());
+            }
+        }
+    }
+
+    /// Episodes of the anime on disk that air from `start` until `end`, soonest first.
+    pub fn airing_between(&self, start: u64, end: u64) -> Vec<(AnimeMapIdx, Airing)> {
+        let mut airings = self
+            .anime_map
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_watching())
+            .filter(|(_, v)| v.paths.iter().any(|v| Path::new(&v).exists()))
+            .filter_map(|(idx, v)| Some((AnimeMapIdx(idx), v.airing.as_ref()?)))
+            .flat_map(|(idx, airing)| {
+                airing
+                    .upcoming(start)
+                    .take_while(move |v| v.airing_at < end)
+                    .map(move |v| (idx, *v))
+            })
+            .collect::<Vec<_>>();
+        airings.sort_by_key(|(_, v)| v.airing_at);
+        airings
+    }
+
+    pub fn update_details(&mut self, details: &MediaDetails) {
+        for anime in self.anime_map.iter_mut() {
+            if anime.anilist_id() == Some(details.id()) {
+                anime.details = Some(details.clone());
+            }
+        }
+    }
+
+    /// Three-way merges the entry on `tracker` with local changes since the last sync.
+    /// Returns animes whose local entry should be sent. Conflicts are kept for the user to
+    /// resolve, see `resolve_conflict`.
+    fn merge_remote<'b>(
+        &mut self,
+        tracker: Tracker,
+        media_id: u32,
+        remote: EntrySnapshot,
+        updated_at: u64,
+    ) -> Vec<&'b mut Anime> {
         let mut vec = vec![];
-        'anime: for anime in self.animes() {
-            match anime.anilist_id() {
-                Some(anilist_id) if anilist_id == entry.id() => {
-                    if anime.last_watched > entry.updated_at() {
-                        let anime = unsafe { &mut *(*anime as *mut Anime) };
-                        vec.push(anime);
-                        continue 'anime;
+        let trackers = self.trackers();
+        for anime in self.animes() {
+            if anime.tracker_id(tracker) != Some(media_id) {
+                continue;
+            }
+            let local = match anime.local_snapshot(tracker) {
+                Some(v) => v,
+                None => continue,
+            };
+            let local_newer = anime.last_modified() > updated_at;
+            let synced = anime.synced_mut(tracker).as_ref();
+            match list_entry::merge(synced, &local, &remote, local_newer) {
+                Merge::Unchanged => {
+                    if anime.synced_mut(tracker).is_none() {
+                        anime.set_synced(tracker, local, remote.clone());
                     }
-
-                    anime.set_last_watched(entry.updated_at());
-                    anime.set_progress(entry.progress());
                 }
-                _ => (),
+                Merge::Push => {
+                    let anime = unsafe { &mut *(*anime as *mut Anime) };
+                    vec.push(anime);
+                }
+                Merge::Pull => {
+                    anime.apply_remote(tracker, &remote, updated_at);
+                    queue_other_trackers(&mut self.sync_queue, &trackers, tracker, anime);
+                }
+                Merge::Conflict => {
+                    // Held back until resolved
+                    self.sync_queue.remove(tracker, media_id);
+                    self.conflicts
+                        .retain(|v| v.tracker != tracker || v.media_id != media_id);
+                    self.conflicts.push(Conflict {
+                        tracker,
+                        media_id,
+                        title: anime.display_title().to_string(),
+                        local,
+                        remote: remote.clone(),
+                        updated_at,
+                    });
+                }
             }
         }
         vec
     }
 
+    pub fn update_media<'b>(&mut self, entry: &MediaEntry) -> Vec<&'b mut Anime> {
+        self.merge_remote(
+            Tracker::AniList,
+            entry.id(),
+            entry.snapshot(),
+            entry.updated_at(),
+        )
+    }
+
+    /// Entries changed both locally and on a tracker since the last sync.
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    /// Keeps one side of a conflict. The local side is queued to be sent, the tracker's side
+    /// is queued for the other trackers.
+    pub fn resolve_conflict(&mut self, tracker: Tracker, media_id: u32, keep_local: bool) {
+        let conflict = match self
+            .conflicts
+            .iter()
+            .position(|v| v.tracker == tracker && v.media_id == media_id)
+        {
+            Some(idx) => self.conflicts.remove(idx),
+            None => return,
+        };
+        let trackers = self.trackers();
+        for anime in self.anime_map.iter_mut() {
+            if anime.tracker_id(tracker) != Some(media_id) {
+                continue;
+            }
+            if keep_local {
+                if let Some(change) = anime.pending_change(tracker) {
+                    self.sync_queue.push(change);
+                }
+            } else {
+                anime.apply_remote(tracker, &conflict.remote, conflict.updated_at);
+                queue_other_trackers(&mut self.sync_queue, &trackers, tracker, anime);
+            }
+        }
+        self.update_cached();
+    }
+
+    /// Entries on the AniList list no folder on disk is linked to.
+    pub fn remote_entries(&self) -> &[RemoteEntry] {
+        &self.remote_entries
+    }
+
+    /// Keeps the entries of `collections` no anime on disk is linked to, as placeholders.
+    pub fn update_remote_entries(&mut self, collections: &[Collection]) {
+        let linked = self
+            .anime_map
+            .iter()
+            .filter(|v| v.paths.iter().any(|v| Path::new(&v).exists()))
+            .filter_map(Anime::anilist_id)
+            .collect();
+        self.remote_entries = remote_entries(collections, &linked, &self.remote_entries);
+    }
+
+    /// Links the placeholder of `media_id` to the folder at `path`, taking progress and list
+    /// entry from AniList. The folder is added as an anime unless it already is one.
+    pub fn link_remote(&mut self, media_id: u32, path: &Path) -> Result<()> {
+        let pos = match self
+            .remote_entries
+            .iter()
+            .position(|v| v.media_id == media_id)
+        {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+        let url = format!("https://anilist.co/anime/{media_id}");
+        let metadata = self
+            .indexed_db
+            .get_or_insert_with(JsonIndexed::new)
+            .find_source(&url)
+            .cloned()
+            .ok_or(Err::UnknownMedia(media_id))?;
+        let directory = path.to_str().ok_or(Err::UTF8)?;
+        let file_name = path.file_name().ok_or(Err::InvalidFile)?;
+        let file_name = file_name.to_str().ok_or(Err::UTF8)?.to_string();
+
+        let remote = self.remote_entries.remove(pos);
+        let anime = match self
+            .anime_map
+            .iter()
+            .position(|v| v.paths.iter().any(|v| v == directory))
+        {
+            Some(idx) => &mut self.anime_map[idx],
+            None => {
+                let mut anime = Anime::from_path(path, file_name, None, get_time());
+                anime.update_local_artwork(&self.artwork_patterns);
+                self.anime_map.push(anime);
+                self.anime_map.last_mut().unwrap()
+            }
+        };
+        anime.set_metadata(Some(metadata));
+        anime.apply_remote(Tracker::AniList, &remote.snapshot, remote.updated_at);
+        // `anime_map` may have grown, which `cached_view` borrows
+        self.update_cached();
+        Ok(())
+    }
+
     /// Returns list of entries that need to be updated
     pub fn update_anilist_list<'b>(&mut self, collection: &Collection) -> Box<[&'b mut Anime]> {
         collection
@@ -804,13 +1925,90 @@ impl<'a> Database<'a> {
             .flat_map(|entry| self.update_media(entry).into_iter())
             .collect()
     }
+
+    /// Returns list of entries that need to be sent to MyAnimeList
+    pub fn update_mal_list<'b>(&mut self, entries: &[MalEntry]) -> Box<[&'b mut Anime]> {
+        entries
+            .iter()
+            .flat_map(|entry| {
+                let status = entry.list_status();
+                self.merge_remote(
+                    Tracker::MyAnimeList,
+                    entry.id(),
+                    status.snapshot(),
+                    status.updated_at(),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns list of entries that need to be sent to Kitsu
+    pub fn update_kitsu_list<'b>(&mut self, entries: &[LibraryEntry]) -> Box<[&'b mut Anime]> {
+        entries
+            .iter()
+            // Entries of anime that were not included have no id
+            .filter_map(|entry| Some((entry.anime_id()?, entry)))
+            .flat_map(|(anime_id, entry)| {
+                self.merge_remote(
+                    Tracker::Kitsu,
+                    anime_id,
+                    entry.snapshot(),
+                    entry.updated_at(),
+                )
+            })
+            .collect()
+    }
+
+    /// Applies imported entries to the anime with the same source id, queueing them for the
+    /// trackers that are logged in. Nothing is changed when `dry_run`.
+    pub fn import_list(&mut self, entries: &[ImportedEntry], dry_run: bool) -> ImportReport {
+        let mut report = ImportReport::default();
+        let trackers = self.trackers();
+        let time = get_time();
+        for imported in entries {
+            let mut matched = false;
+            for anime in self
+                .anime_map
+                .iter_mut()
+                .filter(|v| v.tracker_id(imported.tracker) == Some(imported.media_id))
+            {
+                matched = true;
+                if dry_run {
+                    break;
+                }
+                anime.import_entry(&imported.snapshot, time);
+                for tracker in trackers.iter() {
+                    if let Some(change) = anime.pending_change(*tracker) {
+                        self.sync_queue.push(change);
+                    }
+                }
+            }
+            match matched {
+                true => report.matched += 1,
+                false => report.unmatched.push(imported.clone()),
+            }
+        }
+        if !dry_run {
+            self.update_cached();
+        }
+        report
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::BTreeMap, path::PathBuf};
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        path::PathBuf,
+    };
 
-    use super::is_empty_dir;
+    use crate::anilist_serde::{Collection, MediaAiring};
+
+    use super::credentials::AniListCred;
+    use super::list_entry::{EntrySnapshot, ListEntry, ListStatus};
+    use super::profile::DEFAULT_PROFILE;
+    use super::sync_queue::Tracker;
+    use super::{is_empty_dir, remote_entries, Anime, Database, TagFilter, TagState};
 
     #[test]
     fn btree_test() {
@@ -829,6 +2027,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tag_filter_test() {
+        let tags = ["Action".to_string(), "Comedy".to_string()];
+        let mut filter = TagFilter::default();
+        assert!(filter.matches(&tags));
+
+        filter.toggle_include("Action");
+        assert!(filter.matches(&tags));
+        filter.toggle_include("Drama");
+        assert!(!filter.matches(&tags));
+
+        filter.toggle_exclude("Drama");
+        assert_eq!(TagState::Exclude, filter.state("Drama"));
+        assert!(filter.matches(&tags));
+        filter.toggle_exclude("Comedy");
+        assert!(!filter.matches(&tags));
+
+        filter.toggle_exclude("Comedy");
+        assert_eq!(TagState::None, filter.state("Comedy"));
+        assert!(filter.matches(&tags));
+    }
+
     #[test]
     fn empty_directory_test() {
         let directory = PathBuf::from("tests/empty-dir-test");
@@ -837,4 +2057,180 @@ mod tests {
         assert!(is_empty_dir(directory));
         //read_dir
     }
+
+    #[test]
+    fn local_artwork_test() {
+        let directory =
+            std::env::temp_dir().join(format!("aniki-artwork-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("cover.jpg"), b"").unwrap();
+        let cover = directory.join("cover.jpg").to_str().unwrap().to_string();
+        let mut anime = Anime::from_path(&directory, "Artwork Test".into(), None, 0);
+
+        anime.update_local_artwork(&["cover".to_string()]);
+        assert_eq!([cover.clone()], anime.local_artwork());
+        anime.set_thumbnail(Some(cover.clone()));
+        anime.update_local_artwork(&["cover".to_string(), "poster".to_string()]);
+        assert_eq!(Some(cover), anime.thumbnail);
+
+        // Patterns changed
+        anime.update_local_artwork(&["poster".to_string()]);
+        assert!(anime.local_artwork().is_empty());
+        assert_eq!(None, anime.thumbnail);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn remote_entries_test() {
+        let entry = |id: u32, updated_at: u64, cover: &str| {
+            format!(
+                r#"{{"progress": 2, "updatedAt": {updated_at}, "status": "CURRENT",
+                "media": {{"id": {id}, "title": {{"userPreferred": "Title {id}"}},
+                "coverImage": {{"large": "{cover}"}}}}}}"#
+            )
+        };
+        let watching = format!(
+            r#"{{"name": "Watching", "status": "CURRENT", "entries": [{}, {}, {}]}}"#,
+            entry(1, 10, "a.jpg"),
+            entry(2, 30, "b.jpg"),
+            entry(3, 20, "c.jpg"),
+        );
+        let custom = format!(
+            r#"{{"name": "Favorites", "entries": [{}]}}"#,
+            entry(3, 20, "c.jpg")
+        );
+        let collections: Vec<Collection> = [watching, custom]
+            .iter()
+            .map(|v| serde_json::from_str(v).unwrap())
+            .collect();
+
+        let linked = BTreeSet::from([1]);
+        let mut entries = remote_entries(&collections, &linked, &[]);
+        assert_eq!(
+            vec![2, 3],
+            entries.iter().map(|v| v.media_id).collect::<Vec<_>>()
+        );
+        assert_eq!("Title 2", entries[0].title);
+        assert_eq!("Watching – not downloaded", entries[0].label());
+
+        // Cached thumbnails are kept until the cover changes
+        entries[0].thumbnail = Some(String::from("b.png"));
+        entries[1].thumbnail = Some(String::from("old.png"));
+        entries[1].cover = Some(String::from("old.jpg"));
+        let entries = remote_entries(&collections, &linked, &entries);
+        assert_eq!(Some("b.png"), entries[0].thumbnail());
+        assert_eq!(None, entries[1].thumbnail());
+    }
+
+    #[test]
+    fn airing_test() {
+        let json = r#"{"data": {"Page": {"media": [
+            {"id": 1, "episodes": 12, "airingSchedule": {"nodes": [
+                {"episode": 6, "airingAt": 2000},
+                {"episode": 5, "airingAt": 1000}
+            ]}},
+            {"id": 2, "episodes": 12, "airingSchedule": {"nodes": []}}
+        ]}}}"#;
+        let media = MediaAiring::deserialize_page(json.as_bytes()).unwrap();
+        let (airing, finished) = (&media[0], &media[1]);
+
+        assert_eq!(Some(4), airing.aired(500));
+        assert_eq!(
+            vec![5, 6],
+            airing.upcoming(500).map(|v| v.episode).collect::<Vec<_>>()
+        );
+        // Fetched as not aired yet, but the time has passed
+        assert_eq!(Some(5), airing.aired(1000));
+        assert_eq!(Some(6), airing.aired(3000));
+        assert_eq!(None, airing.upcoming(3000).next());
+
+        assert_eq!(Some(12), finished.aired(500));
+    }
+
+    #[test]
+    fn notes_test() {
+        let mut anime = Anime::from_path("tests/notes-test", "Notes Test".into(), None, 0);
+        anime.set_list_entry(ListEntry {
+            status: Some(ListStatus::Paused),
+            notes: "Paused because of the sub group".to_string(),
+            ..Default::default()
+        });
+        let notes = |anime: &Anime, tracker| anime.local_snapshot(tracker).unwrap().entry.notes;
+        assert_eq!(
+            "Paused because of the sub group",
+            notes(&anime, Tracker::AniList)
+        );
+        assert_eq!("", notes(&anime, Tracker::MyAnimeList));
+        assert!(anime.matches_search("SUB GROUP"));
+        assert!(anime.matches_search("notes test"));
+        assert!(!anime.matches_search("dub"));
+
+        // Trackers without notes leave them alone
+        let remote = EntrySnapshot {
+            progress: 1,
+            entry: ListEntry {
+                status: Some(ListStatus::Current),
+                ..Default::default()
+            },
+        };
+        anime.apply_remote(Tracker::Kitsu, &remote, 0);
+        assert_eq!(Some(ListStatus::Current), anime.list_entry().status);
+        assert_eq!("Paused because of the sub group", anime.list_entry().notes);
+        anime.apply_remote(Tracker::AniList, &remote, 0);
+        assert_eq!("", anime.list_entry().notes);
+    }
+
+    #[test]
+    fn profile_test() {
+        let mut db = Database::new("tests/profile-test.db", Vec::<&str>::new(), vec![]).unwrap();
+        db.anime_map.push(Anime::from_path(
+            "tests/profile-test",
+            "Profile Test".into(),
+            None,
+            0,
+        ));
+        let anime = &mut db.anime_map[0];
+        anime.set_alias("Shared folder".to_string());
+        anime.set_list_entry(ListEntry {
+            notes: "Default notes".to_string(),
+            ..Default::default()
+        });
+        db.skip_login_set(true);
+
+        db.switch_profile("guest");
+        assert_eq!("guest", db.profile());
+        assert_eq!(vec!["default", "guest"], db.profile_names());
+        assert!(!db.skip_login());
+        let anime = &mut db.anime_map[0];
+        assert_eq!(None, anime.alias);
+        assert_eq!("", anime.list_entry().notes);
+        anime.set_list_entry(ListEntry {
+            notes: "Guest notes".to_string(),
+            ..Default::default()
+        });
+
+        db.switch_profile(DEFAULT_PROFILE);
+        assert!(db.profile.is_none());
+        assert!(db.skip_login());
+        let anime = &db.anime_map[0];
+        assert_eq!(Some("Shared folder"), anime.alias.as_deref());
+        assert_eq!("Default notes", anime.list_entry().notes);
+
+        db.switch_profile("guest");
+        assert_eq!("Guest notes", db.anime_map[0].list_entry().notes);
+    }
+
+    #[test]
+    fn trackers_test() {
+        let mut db = Database::new("tests/trackers-test.db", Vec::<&str>::new(), vec![]).unwrap();
+        assert!(db.sync_queue().is_none());
+        db.anilist_cred_set(Some(AniListCred::new(1, "token".to_string(), None)));
+        assert_eq!(vec![Tracker::AniList], db.trackers());
+        assert!(db.sync_queue().is_some());
+
+        // Nothing is sent with a token AniList no longer accepts
+        db.anilist_expire(0);
+        assert!(db.trackers().is_empty());
+        assert!(db.sync_queue().is_none());
+    }
 }
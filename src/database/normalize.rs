@@ -0,0 +1,283 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+static ROMANIZE_KANA: AtomicBool = AtomicBool::new(true);
+
+/// Toggles whether kana titles are also indexed and matched by their romaji reading.
+///
+/// Must be set before the json database is indexed, as the index is only built once.
+pub fn set_romanize_kana(enabled: bool) {
+    ROMANIZE_KANA.store(enabled, Ordering::Relaxed);
+}
+
+pub fn romanize_enabled() -> bool {
+    ROMANIZE_KANA.load(Ordering::Relaxed)
+}
+
+fn is_latin(c: char) -> bool {
+    c.is_ascii_alphabetic() || ('\u{00C0}'..='\u{024F}').contains(&c)
+}
+
+/// Latin letters that do not decompose into a base letter and a combining mark.
+fn fold_latin(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ø' => "o",
+        'Ø' => "O",
+        'æ' => "ae",
+        'Æ' => "AE",
+        'œ' => "oe",
+        'Œ' => "OE",
+        'ß' => "ss",
+        'đ' => "d",
+        'Đ' => "D",
+        'ł' => "l",
+        'Ł' => "L",
+        _ => return None,
+    })
+}
+
+/// Normalizes a title for indexing and matching.
+///
+/// Applies NFKC (folding full-width latin and half-width katakana) and strips diacritics from
+/// latin letters. Marks on other scripts are kept, so "が" does not turn into "か".
+pub fn normalize_title(s: &str) -> String {
+    let mut buf = String::with_capacity(s.len());
+    let mut base_is_latin = false;
+    for c in s.nfkc().nfd() {
+        if is_combining_mark(c) {
+            if base_is_latin {
+                continue;
+            }
+        } else {
+            base_is_latin = is_latin(c);
+        }
+
+        match fold_latin(c) {
+            Some(folded) => buf.push_str(folded),
+            None => buf.push(c),
+        }
+    }
+    buf.nfc().collect()
+}
+
+/// Removes whitespace so that unspaced romaji can be compared against spaced titles.
+pub fn compact(s: &str) -> String {
+    s.split_whitespace().collect()
+}
+
+/// Keys a title should be indexed and looked up by: the normalized title, and its romaji
+/// reading when it contains kana.
+pub fn index_keys(normalized: &str) -> Vec<String> {
+    let mut keys = vec![normalized.to_owned()];
+    if romanize_enabled() {
+        if let Some(romaji) = romanize_kana(normalized) {
+            keys.push(romaji);
+        }
+    }
+    keys
+}
+
+fn is_kana(c: char) -> bool {
+    ('\u{3041}'..='\u{3096}').contains(&c) || ('\u{30A1}'..='\u{30FA}').contains(&c)
+}
+
+fn to_hiragana(c: char) -> char {
+    match c {
+        '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn kana_romaji(c: char) -> Option<&'static str> {
+    Some(match c {
+        'あ' | 'ぁ' => "a",
+        'い' | 'ぃ' | 'ゐ' => "i",
+        'う' | 'ぅ' => "u",
+        'え' | 'ぇ' | 'ゑ' => "e",
+        'お' | 'ぉ' | 'を' => "o",
+        'か' | 'ゕ' => "ka",
+        'き' => "ki",
+        'く' => "ku",
+        'け' | 'ゖ' => "ke",
+        'こ' => "ko",
+        'が' => "ga",
+        'ぎ' => "gi",
+        'ぐ' => "gu",
+        'げ' => "ge",
+        'ご' => "go",
+        'さ' => "sa",
+        'し' => "shi",
+        'す' => "su",
+        'せ' => "se",
+        'そ' => "so",
+        'ざ' => "za",
+        'じ' | 'ぢ' => "ji",
+        'ず' | 'づ' => "zu",
+        'ぜ' => "ze",
+        'ぞ' => "zo",
+        'た' => "ta",
+        'ち' => "chi",
+        'つ' => "tsu",
+        'て' => "te",
+        'と' => "to",
+        'だ' => "da",
+        'で' => "de",
+        'ど' => "do",
+        'な' => "na",
+        'に' => "ni",
+        'ぬ' => "nu",
+        'ね' => "ne",
+        'の' => "no",
+        'は' => "ha",
+        'ひ' => "hi",
+        'ふ' => "fu",
+        'へ' => "he",
+        'ほ' => "ho",
+        'ば' => "ba",
+        'び' => "bi",
+        'ぶ' => "bu",
+        'べ' => "be",
+        'ぼ' => "bo",
+        'ぱ' => "pa",
+        'ぴ' => "pi",
+        'ぷ' => "pu",
+        'ぺ' => "pe",
+        'ぽ' => "po",
+        'ま' => "ma",
+        'み' => "mi",
+        'む' => "mu",
+        'め' => "me",
+        'も' => "mo",
+        'や' | 'ゃ' => "ya",
+        'ゆ' | 'ゅ' => "yu",
+        'よ' | 'ょ' => "yo",
+        'ら' => "ra",
+        'り' => "ri",
+        'る' => "ru",
+        'れ' => "re",
+        'ろ' => "ro",
+        'わ' | 'ゎ' => "wa",
+        'ん' => "n",
+        'ゔ' => "vu",
+        _ => return None,
+    })
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'e' | 'i' | 'o' | 'u')
+}
+
+/// Converts kana to Hepburn romaji, leaving every other character untouched.
+///
+/// Returns `None` if `s` contains no kana.
+pub fn romanize_kana(s: &str) -> Option<String> {
+    if !s.chars().any(is_kana) {
+        return None;
+    }
+
+    let mut buf = String::with_capacity(s.len() * 2);
+    let mut double_next = false;
+    let mut chars = s.chars().map(to_hiragana).peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            'っ' => {
+                double_next = true;
+                continue;
+            }
+            'ー' => {
+                if let Some(vowel) = buf.chars().last().filter(|c| is_vowel(*c)) {
+                    buf.push(vowel);
+                }
+                continue;
+            }
+            _ => (),
+        }
+
+        let mut syllable = match kana_romaji(c) {
+            Some(v) => v.to_string(),
+            None => {
+                double_next = false;
+                buf.push(c);
+                continue;
+            }
+        };
+
+        match chars.peek() {
+            Some(&small @ ('ゃ' | 'ゅ' | 'ょ')) if syllable.len() > 1 && syllable.ends_with('i') => {
+                chars.next();
+                syllable.pop();
+                let y = kana_romaji(small).expect("Small kana has romaji");
+                if matches!(syllable.as_str(), "sh" | "ch" | "j") {
+                    syllable.push_str(&y[1..]);
+                } else {
+                    syllable.push_str(y);
+                }
+            }
+            Some(&small @ ('ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ')) if syllable.len() > 1 => {
+                chars.next();
+                syllable.pop();
+                syllable.push_str(kana_romaji(small).expect("Small kana has romaji"));
+            }
+            _ => (),
+        }
+
+        if double_next {
+            double_next = false;
+            if syllable.starts_with("ch") {
+                buf.push('t');
+            } else if let Some(c) = syllable.chars().next().filter(|c| !is_vowel(*c)) {
+                buf.push(c);
+            }
+        }
+        buf.push_str(&syllable);
+    }
+    Some(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_full_width() {
+        assert_eq!("ABC 123", normalize_title("ＡＢＣ　１２３"));
+    }
+
+    #[test]
+    fn normalize_diacritics() {
+        assert_eq!("Pokemon", normalize_title("Pokémon"));
+        assert_eq!("Kaiji Ultimate Survivor", normalize_title("Kaijí Últimate Survivør"));
+    }
+
+    #[test]
+    fn normalize_keeps_dakuten() {
+        assert_eq!("進撃の巨人", normalize_title("進撃の巨人"));
+        assert_eq!("がっこうぐらし", normalize_title("がっこうぐらし"));
+    }
+
+    #[test]
+    fn normalize_half_width_katakana() {
+        assert_eq!("シンゲキ", normalize_title("ｼﾝｹﾞｷ"));
+    }
+
+    #[test]
+    fn romanize_hiragana() {
+        assert_eq!(Some("shingekinokyojin".to_string()), romanize_kana("しんげきのきょじん"));
+        assert_eq!(Some("gakkougurashi".to_string()), romanize_kana("がっこうぐらし"));
+    }
+
+    #[test]
+    fn romanize_katakana() {
+        assert_eq!(Some("kyatchi".to_string()), romanize_kana("キャッチ"));
+        assert_eq!(Some("fairu".to_string()), romanize_kana("ファイル"));
+        assert_eq!(Some("raamen".to_string()), romanize_kana("ラーメン"));
+    }
+
+    #[test]
+    fn romanize_without_kana() {
+        assert_eq!(None, romanize_kana("Attack on Titan"));
+        assert_eq!(None, romanize_kana("巨人"));
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::episode::Episode;
+use super::list_entry::{ListEntry, ScoreFormat, SyncedState};
+use super::sync_queue::SyncQueue;
+use super::{Anime, Database, RemoteEntry};
+
+/// Profile used when none was picked, it owns the credentials from before profiles existed.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Whether `name` can be used for a profile. It ends up in the credentials file name.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Progress of one anime in a profile that is not the active one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnimeState {
+    last_watched: u64,
+    current_episode: Episode,
+    alias: Option<String>,
+    list_entry: ListEntry,
+    list_updated: u64,
+    synced: Option<SyncedState>,
+    mal_synced: Option<SyncedState>,
+    kitsu_synced: Option<SyncedState>,
+}
+
+impl AnimeState {
+    /// Takes the progress out of `anime`, leaving it as if it was never watched.
+    fn take(anime: &mut Anime) -> Self {
+        let first_episode = anime
+            .episodes
+            .first()
+            .map(|(episode, _)| episode.clone())
+            .unwrap_or(Episode::from((1, 1)));
+        Self {
+            last_watched: std::mem::take(&mut anime.last_watched),
+            current_episode: std::mem::replace(&mut anime.current_episode, first_episode),
+            alias: anime.alias.take(),
+            list_entry: std::mem::take(&mut anime.list_entry),
+            list_updated: std::mem::take(&mut anime.list_updated),
+            synced: anime.synced.take(),
+            mal_synced: anime.mal_synced.take(),
+            kitsu_synced: anime.kitsu_synced.take(),
+        }
+    }
+
+    fn restore(self, anime: &mut Anime) {
+        anime.last_watched = self.last_watched;
+        anime.alias = self.alias;
+        anime.list_entry = self.list_entry;
+        anime.list_updated = self.list_updated;
+        anime.synced = self.synced;
+        anime.mal_synced = self.mal_synced;
+        anime.kitsu_synced = self.kitsu_synced;
+        // Episodes may have been removed since
+        if anime.has_episode(&self.current_episode) {
+            anime.current_episode = self.current_episode;
+        }
+    }
+
+    fn is_unwatched(&self) -> bool {
+        self.last_watched == 0
+            && self.alias.is_none()
+            && self.list_updated == 0
+            && self.synced.is_none()
+            && self.mal_synced.is_none()
+            && self.kitsu_synced.is_none()
+    }
+}
+
+/// Everything kept per profile, stored in the database while another profile is active.
+/// Credentials are in the profile's own `CredentialStore`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProfileState {
+    /// By `Anime::filename`, anime that were never watched in the profile are left out
+    animes: BTreeMap<String, AnimeState>,
+    skip_login: bool,
+    sync_queue: SyncQueue,
+    remote_entries: Vec<RemoteEntry>,
+    score_format: ScoreFormat,
+}
+
+impl ProfileState {
+    /// Takes the active profile out of `database`, leaving it as a new profile.
+    pub(super) fn take(database: &mut Database) -> Self {
+        let animes = database
+            .anime_map
+            .iter_mut()
+            .map(|anime| (anime.filename.clone(), AnimeState::take(anime)))
+            .filter(|(_, state)| !state.is_unwatched())
+            .collect();
+        let mut sync_queue = std::mem::take(&mut database.sync_queue);
+        // Responses to changes being sent are dropped while switching
+        sync_queue.reset_in_flight();
+        Self {
+            animes,
+            skip_login: std::mem::take(&mut database.skip_login),
+            sync_queue,
+            remote_entries: std::mem::take(&mut database.remote_entries),
+            score_format: std::mem::take(&mut database.score_format),
+        }
+    }
+
+    /// Makes this the active profile of `database`, which should have been left as a new
+    /// profile by `take`.
+    pub(super) fn restore(mut self, database: &mut Database) {
+        for anime in database.anime_map.iter_mut() {
+            if let Some(state) = self.animes.remove(&anime.filename) {
+                state.restore(anime);
+            }
+        }
+        database.skip_login = self.skip_login;
+        database.sync_queue = self.sync_queue;
+        database.remote_entries = self.remote_entries;
+        database.score_format = self.score_format;
+    }
+
+    /// Thumbnails of the remote entries, which are still in use while the profile is inactive.
+    pub(super) fn remote_thumbnails(&self) -> impl Iterator<Item = &str> {
+        self.remote_entries.iter().filter_map(|v| v.thumbnail())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_name;
+
+    #[test]
+    fn profile_name_test() {
+        assert!(is_valid_name("default"));
+        assert!(is_valid_name("kid_2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("じろう"));
+        assert!(!is_valid_name("../credentials"));
+        assert!(!is_valid_name("two words"));
+    }
+}
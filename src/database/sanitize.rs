@@ -1,5 +1,34 @@
 use std::str::Chars;
 
+use regex::Regex;
+
+lazy_static::lazy_static! {
+    static ref REG_YEAR: Regex = Regex::new(r#"(?:^|[\s(\[._-])((?:19[6-9]|20[0-9])\d)(?:$|[\s)\]._-])"#).unwrap();
+    static ref REG_SEASON: Regex = Regex::new(r#"(?i)(?:^|[\s(\[._-])(?:season|s)[\s._-]?0*(?P<n>\d{1,2})(?:$|[\s)\]._-])|(?P<o>\d{1,2})(?:st|nd|rd|th)[\s._-]season"#).unwrap();
+}
+
+/// Year and season number found in a directory name, used to tell apart sequels and remakes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NameHints {
+    pub year: Option<u32>,
+    pub season: Option<u32>,
+}
+
+/// Extracts hints from the raw (unsanitized) name, as `sanitize_name` drops bracketed years.
+pub fn name_hints(name: &str) -> NameHints {
+    let year = REG_YEAR
+        .captures(name)
+        .and_then(|caps| caps.get(1)?.as_str().parse().ok());
+    let season = REG_SEASON.captures(name).and_then(|caps| {
+        caps.name("n")
+            .or_else(|| caps.name("o"))?
+            .as_str()
+            .parse()
+            .ok()
+    });
+    NameHints { year, season }
+}
+
 fn matches_string(cursor: Chars, matches: &str) -> bool {
     if cursor.as_str().is_empty() {
         return false;
@@ -132,3 +161,29 @@ pub fn sanitize_name(s: &mut Chars, buf: &mut String) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_hints_year() {
+        assert_eq!(
+            NameHints {
+                year: Some(2011),
+                season: None
+            },
+            name_hints("Hunter x Hunter (2011)")
+        );
+        assert_eq!(Some(2019), name_hints("Fruits Basket 2019").year);
+        assert_eq!(None, name_hints("[sam] Vinland Saga [BD 1080p FLAC]").year);
+    }
+
+    #[test]
+    fn name_hints_season() {
+        assert_eq!(Some(2), name_hints("Shingeki no Kyojin Season 2").season);
+        assert_eq!(Some(3), name_hints("Mob Psycho 100 S03 [1080p]").season);
+        assert_eq!(Some(2), name_hints("Kaguya-sama 2nd Season").season);
+        assert_eq!(None, name_hints("Steins;Gate").season);
+    }
+}
@@ -0,0 +1,313 @@
+use serde::{Deserialize, Serialize};
+
+use super::list_entry::{EntrySnapshot, ListEntry};
+
+const RETRY_BACKOFF_SECS: u64 = 5;
+const MAX_RETRY_BACKOFF_SECS: u64 = 15 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tracker {
+    #[default]
+    AniList,
+    MyAnimeList,
+    Kitsu,
+}
+
+impl Tracker {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AniList => "AniList",
+            Self::MyAnimeList => "MyAnimeList",
+            Self::Kitsu => "Kitsu",
+        }
+    }
+
+    /// Whether the tracker keeps `ListEntry::notes`, others leave the local notes alone.
+    pub fn syncs_notes(&self) -> bool {
+        matches!(self, Self::AniList)
+    }
+}
+
+/// Change to a list entry waiting to be sent to the tracker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingChange {
+    // Queues from before other trackers were supported only hold AniList changes
+    #[serde(default)]
+    pub tracker: Tracker,
+    /// Id of the media on `tracker`
+    pub media_id: u32,
+    pub progress: u32,
+    pub entry: ListEntry,
+    /// `Anime::last_modified` when the change was queued, identifies the change when the
+    /// tracker responds.
+    pub modified: u64,
+    attempts: u32,
+    // Not stored so every change is retried straight away on startup
+    #[serde(skip)]
+    next_attempt: u64,
+    #[serde(skip)]
+    in_flight: bool,
+}
+
+impl PendingChange {
+    pub fn new(
+        tracker: Tracker,
+        media_id: u32,
+        progress: u32,
+        entry: ListEntry,
+        modified: u64,
+    ) -> Self {
+        Self {
+            tracker,
+            media_id,
+            progress,
+            entry,
+            modified,
+            attempts: 0,
+            next_attempt: 0,
+            in_flight: false,
+        }
+    }
+
+    pub fn snapshot(&self) -> EntrySnapshot {
+        EntrySnapshot {
+            progress: self.progress,
+            entry: self.entry.clone(),
+        }
+    }
+}
+
+/// Outbound tracker changes, stored with the database so nothing is lost while offline.
+///
+/// There is at most one change per media and tracker; a newer change replaces the queued one.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncQueue {
+    changes: Vec<PendingChange>,
+    /// Trackers that are logged in. Changes for other trackers are kept but not sent.
+    #[serde(skip)]
+    trackers: Vec<Tracker>,
+}
+
+impl SyncQueue {
+    pub fn set_trackers(&mut self, trackers: Vec<Tracker>) {
+        self.trackers = trackers;
+    }
+
+    pub fn trackers(&self) -> &[Tracker] {
+        &self.trackers
+    }
+
+    pub fn push(&mut self, change: PendingChange) {
+        match self
+            .changes
+            .iter_mut()
+            .find(|v| v.tracker == change.tracker && v.media_id == change.media_id)
+        {
+            // Keep `in_flight` so the change is not sent twice at once. It is sent again once
+            // the older one is done, as `complete` only removes a matching change.
+            Some(queued) => {
+                let in_flight = queued.in_flight;
+                *queued = change;
+                queued.in_flight = in_flight;
+            }
+            None => self.changes.push(change),
+        }
+    }
+
+    /// Changes that should be sent now. They are marked as in flight until `complete` or
+    /// `fail` is called.
+    pub fn due(&mut self, now: u64) -> Vec<PendingChange> {
+        self.changes
+            .iter_mut()
+            .filter(|v| !v.in_flight && v.next_attempt <= now)
+            .filter(|v| self.trackers.contains(&v.tracker))
+            .map(|v| {
+                v.in_flight = true;
+                v.clone()
+            })
+            .collect()
+    }
+
+    pub fn complete(&mut self, tracker: Tracker, media_id: u32, modified: u64) {
+        if let Some(idx) = self.position(tracker, media_id) {
+            if self.changes[idx].modified == modified {
+                self.changes.remove(idx);
+            } else {
+                self.changes[idx].in_flight = false;
+            }
+        }
+    }
+
+    /// Schedules a retry with exponential backoff.
+    pub fn fail(&mut self, tracker: Tracker, media_id: u32, now: u64) {
+        if let Some(idx) = self.position(tracker, media_id) {
+            let change = &mut self.changes[idx];
+            change.in_flight = false;
+            change.next_attempt = now + backoff(change.attempts);
+            change.attempts += 1;
+        }
+    }
+
+    /// Sends the change again once it is due, without counting a failed attempt, ie: when the
+    /// tracker was rate limited or rejected the token.
+    pub fn release(&mut self, tracker: Tracker, media_id: u32) {
+        if let Some(idx) = self.position(tracker, media_id) {
+            self.changes[idx].in_flight = false;
+        }
+    }
+
+    /// Holds back every change for `tracker` until `until`, ie: while rate limited.
+    pub fn delay(&mut self, tracker: Tracker, until: u64) {
+        for change in self.changes.iter_mut().filter(|v| v.tracker == tracker) {
+            change.next_attempt = change.next_attempt.max(until);
+        }
+    }
+
+    /// Retries every change straight away, ie: after reconnecting.
+    pub fn retry_now(&mut self) {
+        for change in self.changes.iter_mut() {
+            change.next_attempt = 0;
+        }
+    }
+
+    /// Sends every change in flight again, ie: when their responses will not arrive.
+    pub fn reset_in_flight(&mut self) {
+        for change in self.changes.iter_mut() {
+            change.in_flight = false;
+        }
+    }
+
+    pub fn remove(&mut self, tracker: Tracker, media_id: u32) {
+        self.changes
+            .retain(|v| v.tracker != tracker || v.media_id != media_id);
+    }
+
+    pub fn contains(&self, tracker: Tracker, media_id: u32) -> bool {
+        self.position(tracker, media_id).is_some()
+    }
+
+    /// Drops every change for `tracker`, ie: after logging out of it.
+    pub fn clear(&mut self, tracker: Tracker) {
+        self.changes.retain(|v| v.tracker != tracker);
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    fn position(&self, tracker: Tracker, media_id: u32) -> Option<usize> {
+        self.changes
+            .iter()
+            .position(|v| v.tracker == tracker && v.media_id == media_id)
+    }
+}
+
+fn backoff(attempts: u32) -> u64 {
+    RETRY_BACKOFF_SECS
+        .saturating_mul(1 << attempts.min(16))
+        .min(MAX_RETRY_BACKOFF_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(tracker: Tracker, media_id: u32, progress: u32, modified: u64) -> PendingChange {
+        PendingChange::new(tracker, media_id, progress, ListEntry::default(), modified)
+    }
+
+    #[test]
+    fn sync_queue_test() {
+        use Tracker::AniList;
+        let mut queue = SyncQueue::default();
+        queue.set_trackers(vec![AniList]);
+        queue.push(change(AniList, 1, 1, 100));
+        queue.push(change(AniList, 2, 5, 100));
+        queue.push(change(AniList, 1, 2, 200));
+        assert_eq!(2, queue.len());
+
+        let due = queue.due(0);
+        assert_eq!(2, due.len());
+        assert_eq!(2, due[0].progress);
+        assert!(queue.due(0).is_empty());
+
+        // Changed again while the first one was being sent
+        queue.push(change(AniList, 1, 3, 300));
+        queue.complete(AniList, 1, 200);
+        assert!(queue.contains(AniList, 1));
+        assert_eq!(3, queue.due(0)[0].progress);
+        queue.complete(AniList, 1, 300);
+        assert!(!queue.contains(AniList, 1));
+
+        queue.fail(AniList, 2, 1000);
+        assert!(queue.due(1000).is_empty());
+        assert_eq!(1, queue.due(1000 + RETRY_BACKOFF_SECS).len());
+        queue.fail(AniList, 2, 2000);
+        assert!(queue.due(2000 + RETRY_BACKOFF_SECS).is_empty());
+        queue.retry_now();
+        assert_eq!(1, queue.due(0).len());
+
+        queue.fail(AniList, 2, 3000);
+        queue.delay(AniList, 4000);
+        assert!(queue.due(3999).is_empty());
+        assert_eq!(1, queue.due(4000).len());
+    }
+
+    #[test]
+    fn rate_limited_test() {
+        use Tracker::AniList;
+        let mut queue = SyncQueue::default();
+        queue.set_trackers(vec![AniList]);
+        queue.push(change(AniList, 1, 1, 100));
+        queue.push(change(AniList, 2, 5, 100));
+        assert_eq!(2, queue.due(1000).len());
+
+        // The whole batch was answered with a 429
+        queue.delay(AniList, 1060);
+        queue.release(AniList, 1);
+        queue.release(AniList, 2);
+        assert!(queue.due(1059).is_empty());
+        let due = queue.due(1060);
+        assert_eq!(2, due.len());
+        assert!(due.iter().all(|v| v.attempts == 0));
+
+        // Answered with an invalid token, then logged in again
+        queue.release(AniList, 1);
+        queue.release(AniList, 2);
+        queue.retry_now();
+        assert_eq!(2, queue.due(0).len());
+    }
+
+    #[test]
+    fn sync_queue_trackers_test() {
+        use Tracker::{AniList, MyAnimeList};
+        let mut queue = SyncQueue::default();
+        queue.push(change(AniList, 1, 1, 100));
+        queue.push(change(MyAnimeList, 1, 2, 100));
+        assert_eq!(2, queue.len());
+
+        // Nothing is sent for trackers that are not logged in
+        assert!(queue.due(0).is_empty());
+        queue.set_trackers(vec![MyAnimeList]);
+        let due = queue.due(0);
+        assert_eq!(1, due.len());
+        assert_eq!(MyAnimeList, due[0].tracker);
+
+        queue.complete(MyAnimeList, 1, 100);
+        assert!(queue.contains(AniList, 1));
+        assert!(!queue.contains(MyAnimeList, 1));
+        queue.clear(AniList);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn backoff_test() {
+        assert_eq!(5, backoff(0));
+        assert_eq!(10, backoff(1));
+        assert_eq!(MAX_RETRY_BACKOFF_SECS, backoff(20));
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use sdl2::image::{ImageRWops, SaveSurface};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+use sdl2::rwops::RWops;
+use sdl2::surface::Surface;
+use tokio::sync::Semaphore;
+
+use crate::http;
+
+/// Thumbnails are scaled down to fit twice the card size, which is enough for the episode screen.
+pub const THUMBNAIL_MAX_SIZE: (u32, u32) = (400, 600);
+/// AniList banners are 1900x400, keep them as they are stretched across the episode screen.
+pub const BANNER_MAX_SIZE: (u32, u32) = (1900, 400);
+
+const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(20);
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// Failed downloads are not requested again until this long after failing.
+const FAILED_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
+pub struct ThumbnailResult {
+    pub url: String,
+    pub path: String,
+    pub result: anyhow::Result<()>,
+}
+
+/// Downloads thumbnails into a cache directory.
+///
+/// Files are named by a hash of their url, so titles with `/` or duplicate titles are not an
+/// issue. Downloads are written to a temporary file and renamed into place once they have been
+/// decoded and scaled, so a path is only ever handed out for a complete image.
+#[derive(Debug)]
+pub struct ThumbnailCache {
+    tx: mpsc::Sender<ThumbnailResult>,
+    rx: mpsc::Receiver<ThumbnailResult>,
+    semaphore: Arc<Semaphore>,
+    pending: BTreeSet<String>,
+    /// When the download into each path last failed
+    failed: BTreeMap<String, Instant>,
+}
+
+impl Default for ThumbnailCache {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            tx,
+            rx,
+            semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT_DOWNLOADS)),
+            pending: BTreeSet::new(),
+            failed: BTreeMap::new(),
+        }
+    }
+}
+
+impl ThumbnailCache {
+    /// Queues a download of `url` into `path`, scaled down to fit `max_size`, unless one is
+    /// already in flight or has failed recently.
+    pub fn request(&mut self, url: &str, path: &str, max_size: (u32, u32)) {
+        if self.recently_failed(path) || !self.pending.insert(path.to_owned()) {
+            return;
+        }
+        let tx = self.tx.clone();
+        let semaphore = Arc::clone(&self.semaphore);
+        let url = url.to_owned();
+        let path = path.to_owned();
+        tokio::spawn(async move {
+            let result = match semaphore.acquire().await {
+                Ok(_permit) => download(&url, &path, max_size).await,
+                Err(e) => Err(e.into()),
+            };
+            let _ = tx.send(ThumbnailResult { url, path, result });
+        });
+    }
+
+    /// Finished downloads since the last call.
+    pub fn poll(&mut self) -> Vec<ThumbnailResult> {
+        let results = self.rx.try_iter().collect::<Vec<_>>();
+        for result in results.iter() {
+            self.pending.remove(&result.path);
+            match result.result {
+                Ok(()) => {
+                    // Failures were likely from being offline, which is over
+                    self.failed.clear();
+                }
+                Err(_) => {
+                    self.failed.insert(result.path.clone(), Instant::now());
+                }
+            }
+        }
+        results
+    }
+
+    fn recently_failed(&mut self, path: &str) -> bool {
+        match self.failed.get(path) {
+            Some(failed_at) if failed_at.elapsed() < FAILED_RETRY_DELAY => true,
+            Some(_) => {
+                self.failed.remove(path);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+async fn download(url: &str, path: &str, max_size: (u32, u32)) -> anyhow::Result<()> {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 1;
+    let data = loop {
+        match fetch(url).await {
+            Ok(data) => break data,
+            Err(e) if attempt >= DOWNLOAD_ATTEMPTS => return Err(e),
+            Err(e) => {
+                eprintln!("WARN:thumbnail download failed ({attempt}/{DOWNLOAD_ATTEMPTS}):{e:#}");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    };
+
+    let path = PathBuf::from(path);
+    tokio::task::spawn_blocking(move || write_scaled(&data, &path, max_size)).await?
+}
+
+async fn fetch(url: &str) -> anyhow::Result<Vec<u8>> {
+    let res = http::client::client()
+        .get(url)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await
+        .context("Failed to connect to url")?
+        .error_for_status()?;
+    Ok(res.bytes().await?.to_vec())
+}
+
+fn write_scaled(data: &[u8], path: &Path, max_size: (u32, u32)) -> anyhow::Result<()> {
+    let mut image = RWops::from_bytes(data)
+        .and_then(|rw| rw.load())
+        .map_err(|e| anyhow::anyhow!("Failed to decode image:{e}"))?;
+    let (width, height) = scaled_size(image.size(), max_size);
+    let mut scaled =
+        Surface::new(width, height, PixelFormatEnum::RGBA32).map_err(|e| anyhow::anyhow!(e))?;
+    image
+        .set_blend_mode(BlendMode::None)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    image
+        .blit_scaled(None, &mut scaled, Rect::new(0, 0, width, height))
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    let tmp_path = path.with_extension("tmp");
+    let result = scaled
+        .save(&tmp_path)
+        .map_err(|e| anyhow::anyhow!("Failed to write thumbnail:{e}"))
+        .and_then(|_| {
+            std::fs::rename(&tmp_path, path).context("Failed to move thumbnail into place")
+        });
+    // Not named like a cached thumbnail, so it would never be garbage collected
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Largest size with the same aspect ratio as `size` that fits in `max`. Never scales up.
+pub fn scaled_size(
+    size @ (width, height): (u32, u32),
+    (max_width, max_height): (u32, u32),
+) -> (u32, u32) {
+    if width <= max_width && height <= max_height || width == 0 || height == 0 {
+        return size;
+    }
+    if width as u64 * max_height as u64 > height as u64 * max_width as u64 {
+        (
+            max_width,
+            (height as u64 * max_width as u64 / width as u64).max(1) as u32,
+        )
+    } else {
+        (
+            (width as u64 * max_height as u64 / height as u64).max(1) as u32,
+            max_height,
+        )
+    }
+}
+
+/// FNV-1a, used instead of `DefaultHasher` as the output must not change between releases.
+pub(super) fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Path the thumbnail at `url` is cached under.
+pub fn cache_path(directory: &str, url: &str) -> String {
+    let directory = directory.trim_end_matches('/');
+    format!("{directory}/{:016x}.png", fnv1a(url))
+}
+
+/// Directory inside `image_directory` banners are cached in. Being a subdirectory keeps them
+/// from being garbage collected with the thumbnails.
+pub fn banner_directory(image_directory: &str) -> String {
+    format!("{}/banners", image_directory.trim_end_matches('/'))
+}
+
+/// Whether `name` is the name of a file in the cache, see `cache_path`.
+fn is_cache_file_name(name: &OsStr) -> bool {
+    name.to_str()
+        .and_then(|v| v.strip_suffix(".png"))
+        .is_some_and(|hash| {
+            hash.len() == 16 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        })
+}
+
+/// Removes thumbnails in `directory` that are not in `keep`. Files are matched by name, and
+/// anything not named like a cached thumbnail is left alone, as the directory is configurable.
+pub fn collect_garbage(directory: &str, keep: &BTreeSet<&str>) -> anyhow::Result<()> {
+    let keep = keep
+        .iter()
+        .filter_map(|v| Path::new(v).file_name())
+        .collect::<BTreeSet<_>>();
+    for entry in std::fs::read_dir(directory)?.filter_map(|v| v.ok()) {
+        let name = entry.file_name();
+        if !is_cache_file_name(&name) || keep.contains(name.as_os_str()) {
+            continue;
+        }
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        if let Err(e) = std::fs::remove_file(&path) {
+            eprintln!("WARN:failed to remove unused thumbnail {path:?}:{e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_path_test() {
+        let a = cache_path(
+            "thumbnails",
+            "https://cdn.myanimelist.net/images/anime/10/47347.jpg",
+        );
+        let b = cache_path(
+            "thumbnails",
+            "https://cdn.myanimelist.net/images/anime/10/47348.jpg",
+        );
+        assert_ne!(a, b);
+        assert!(a.starts_with("thumbnails/"));
+        assert!(!a["thumbnails/".len()..].contains('/'));
+        assert_eq!(
+            a,
+            cache_path(
+                "thumbnails",
+                "https://cdn.myanimelist.net/images/anime/10/47347.jpg"
+            )
+        );
+    }
+
+    #[test]
+    fn failed_test() {
+        let result = |path: &str, result| ThumbnailResult {
+            url: String::new(),
+            path: path.to_string(),
+            result,
+        };
+        let mut cache = ThumbnailCache::default();
+        cache
+            .tx
+            .send(result("a.png", Err(anyhow::anyhow!("offline"))))
+            .unwrap();
+        cache
+            .tx
+            .send(result("b.png", Err(anyhow::anyhow!("offline"))))
+            .unwrap();
+        cache.poll();
+        assert!(cache.recently_failed("a.png"));
+        assert!(!cache.recently_failed("c.png"));
+
+        let failed_at = Instant::now().checked_sub(FAILED_RETRY_DELAY).unwrap();
+        cache.failed.insert("a.png".to_string(), failed_at);
+        assert!(!cache.recently_failed("a.png"));
+        assert!(cache.recently_failed("b.png"));
+
+        cache.tx.send(result("c.png", Ok(()))).unwrap();
+        cache.poll();
+        assert!(!cache.recently_failed("b.png"));
+    }
+
+    #[test]
+    fn collect_garbage_test() {
+        let directory = std::env::temp_dir().join(format!("aniki-gc-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // Trailing slash, as it can be set in aniki.conf
+        let directory = format!("{}/", directory.display());
+        let kept = cache_path(&directory, "https://example.com/kept.jpg");
+        let unused = cache_path(&directory, "https://example.com/unused.jpg");
+        let other = format!("{directory}notes.txt");
+        for path in [&kept, &unused, &other] {
+            std::fs::write(path, b"").unwrap();
+        }
+
+        collect_garbage(&directory, &BTreeSet::from([kept.as_str()])).unwrap();
+        assert!(Path::new(&kept).exists());
+        assert!(!Path::new(&unused).exists());
+        assert!(Path::new(&other).exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn scaled_size_test() {
+        assert_eq!((200, 300), scaled_size((200, 300), (400, 600)));
+        assert_eq!((400, 600), scaled_size((800, 1200), (400, 600)));
+        assert_eq!((400, 200), scaled_size((1000, 500), (400, 600)));
+        assert_eq!((300, 600), scaled_size((500, 1000), (400, 600)));
+    }
+}
@@ -0,0 +1,92 @@
+//! Client every request goes through, so connections are pooled and the proxy, user agent and
+//! timeouts from aniki.conf apply to all of them.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::Context;
+
+pub const USER_AGENT: &str = concat!("aniki/", env!("CARGO_PKG_VERSION"));
+
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpConfig {
+    /// `http://`, `https://` or `socks5://` url of the proxy to send every request through
+    pub proxy: Option<String>,
+    pub user_agent: String,
+    pub connect_timeout: Duration,
+    /// Of a whole request, from connecting until the body is read
+    pub timeout: Duration,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            user_agent: USER_AGENT.to_string(),
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Builds the shared client, before any request is sent.
+pub fn init(config: &HttpConfig) -> anyhow::Result<()> {
+    CLIENT
+        .set(build(config)?)
+        .map_err(|_| anyhow::anyhow!("http client was already built"))
+}
+
+/// The shared client, built with the default config when `init` was not called, ie: in tests.
+pub fn client() -> &'static reqwest::Client {
+    CLIENT.get_or_init(|| build(&HttpConfig::default()).expect("Valid default config"))
+}
+
+fn build(config: &HttpConfig) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(&config.user_agent)
+        .connect_timeout(config.connect_timeout)
+        .timeout(config.timeout);
+    if let Some(proxy) = &config.proxy {
+        let proxy = reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy {proxy}"))?;
+        builder = builder.proxy(proxy);
+    }
+    builder.build().context("Failed to build http client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::mock_server;
+    use super::*;
+
+    #[tokio::test]
+    async fn build_test() {
+        // The proxy is sent the whole url
+        let (url, requests) = mock_server(vec![("http://anilist.invalid/", 200, "{}")]);
+        let config = HttpConfig {
+            proxy: Some(url),
+            ..Default::default()
+        };
+        let res = build(&config)
+            .unwrap()
+            .get("http://anilist.invalid/")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(200, res.status().as_u16());
+        assert_eq!(1, requests.lock().unwrap().len());
+
+        let config = HttpConfig {
+            proxy: Some(String::from("socks5://127.0.0.1:1080")),
+            ..Default::default()
+        };
+        assert!(build(&config).is_ok());
+
+        let config = HttpConfig {
+            proxy: Some(String::from("not a url")),
+            ..Default::default()
+        };
+        assert!(build(&config).is_err());
+    }
+}
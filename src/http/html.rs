@@ -0,0 +1,149 @@
+/// Converts the small subset of HTML AniList uses in descriptions (`<br>`, `<i>`, `<b>`, `<a>`,
+/// entities) to plain text.
+///
+/// Newlines in the source are treated as whitespace, like a browser would, so only `<br>` and
+/// `<p>` break lines.
+pub fn html_to_text(html: &str) -> String {
+    let mut buf = String::with_capacity(html.len());
+    let mut chars = html.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '<' => {
+                let mut tag = String::new();
+                for c in chars.by_ref() {
+                    if c == '>' {
+                        break;
+                    }
+                    tag.push(c);
+                }
+                let name = tag
+                    .trim_start_matches('/')
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .next()
+                    .unwrap_or("")
+                    .to_lowercase();
+                match name.as_str() {
+                    "br" => push_newline(&mut buf),
+                    "p" if tag.starts_with('/') => {
+                        push_newline(&mut buf);
+                        push_newline(&mut buf);
+                    }
+                    _ => (),
+                }
+            }
+            '&' => {
+                let mut entity = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == ';' || entity.len() > 8 || !(c.is_ascii_alphanumeric() || c == '#') {
+                        break;
+                    }
+                    entity.push(c);
+                    chars.next();
+                }
+                match (chars.peek(), decode_entity(&entity)) {
+                    (Some(';'), Some(decoded)) => {
+                        chars.next();
+                        push_text(&mut buf, decoded);
+                    }
+                    _ => {
+                        push_text(&mut buf, '&');
+                        entity.chars().for_each(|c| push_text(&mut buf, c));
+                    }
+                }
+            }
+            c => push_text(&mut buf, c),
+        }
+    }
+
+    // At most one empty line between paragraphs
+    let mut text = String::with_capacity(buf.len());
+    let mut newlines = 0;
+    for c in buf.trim().chars() {
+        if c == '\n' {
+            newlines += 1;
+            if newlines > 2 {
+                continue;
+            }
+        } else {
+            newlines = 0;
+        }
+        text.push(c);
+    }
+    text
+}
+
+fn push_newline(buf: &mut String) {
+    while buf.ends_with(' ') {
+        buf.pop();
+    }
+    buf.push('\n');
+}
+
+/// Pushes `c`, collapsing whitespace.
+fn push_text(buf: &mut String, c: char) {
+    if c.is_whitespace() && c != '\u{A0}' {
+        if !(buf.is_empty() || buf.ends_with(' ') || buf.ends_with('\n')) {
+            buf.push(' ');
+        }
+    } else {
+        buf.push(c);
+    }
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(n) = entity.strip_prefix('#') {
+        let n = match n.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => n.parse().ok()?,
+        };
+        return char::from_u32(n);
+    }
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "mdash" => '—',
+        "ndash" => '–',
+        "hellip" => '…',
+        "rsquo" => '’',
+        "lsquo" => '‘',
+        "rdquo" => '”',
+        "ldquo" => '“',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_line_breaks() {
+        assert_eq!(
+            "First line.\nSecond line.\n\n(Source: AniDB)",
+            html_to_text("First line.<br>\nSecond line.<br><br>\n<br>\n(Source: AniDB)")
+        );
+        assert_eq!("a\nb", html_to_text("a<br />b"));
+    }
+
+    #[test]
+    fn html_strips_tags() {
+        assert_eq!(
+            "Note: based on the manga",
+            html_to_text("<i>Note: based on the <a href=\"https://anilist.co\">manga</a></i>")
+        );
+    }
+
+    #[test]
+    fn html_entities() {
+        assert_eq!(
+            "Tom & Jerry's \"show\" <3",
+            html_to_text("Tom &amp; Jerry&#039;s &quot;show&quot; &lt;3")
+        );
+        assert_eq!("AT&T", html_to_text("AT&T"));
+        assert_eq!("x & y", html_to_text("x & y"));
+    }
+}
@@ -0,0 +1,273 @@
+//! Kitsu JSON:API. Base urls are passed in so the requests can be pointed at a mock.
+
+use serde::de::DeserializeOwned;
+
+use crate::database::sync_queue::PendingChange;
+use crate::kitsu_serde::{
+    library_entry_attributes, Document, KitsuAuthError, KitsuErrors, KitsuToken, KitsuUser,
+    LibraryEntry, SingleDocument,
+};
+
+use super::client::client;
+
+pub const KITSU_API_URL: &str = "https://kitsu.app/api/edge";
+pub const KITSU_AUTH_URL: &str = "https://kitsu.app/api/oauth";
+
+const JSON_API: &str = "application/vnd.api+json";
+
+/// Kitsu has no authorization code flow for third party apps, so the user's credentials are
+/// exchanged for a token. Only the token is kept.
+pub async fn password_login(
+    auth_url: &str,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<KitsuToken> {
+    let res = client()
+        .post(format!("{auth_url}/token"))
+        .form(&[
+            ("grant_type", "password"),
+            ("username", username),
+            ("password", password),
+        ])
+        .send()
+        .await?;
+    read_json(res).await
+}
+
+pub async fn refresh_token(auth_url: &str, refresh_token: &str) -> anyhow::Result<KitsuToken> {
+    let res = client()
+        .post(format!("{auth_url}/token"))
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ])
+        .send()
+        .await?;
+    read_json(res).await
+}
+
+/// Id of the user the token belongs to.
+pub async fn get_user_id(api_url: &str, access_token: &str) -> anyhow::Result<u64> {
+    let res = client()
+        .get(format!("{api_url}/users?filter[self]=true"))
+        .bearer_auth(access_token)
+        .header("Accept", JSON_API)
+        .send()
+        .await?;
+    let users: Document<KitsuUser> = read_json(res).await?;
+    match users.data.first() {
+        Some(user) => Ok(user.id.parse()?),
+        None => anyhow::bail!("no user for token"),
+    }
+}
+
+/// Every anime in the user's library, following the pages.
+pub async fn get_library(
+    api_url: &str,
+    access_token: &str,
+    user_id: u64,
+) -> anyhow::Result<Vec<LibraryEntry>> {
+    let client = client();
+    let mut entries = vec![];
+    let mut next = Some(format!(
+        "{api_url}/library-entries?filter[userId]={user_id}&filter[kind]=anime&include=anime&fields[anime]=id&page[limit]=500"
+    ));
+    while let Some(url) = next {
+        let res = client
+            .get(url)
+            .bearer_auth(access_token)
+            .header("Accept", JSON_API)
+            .send()
+            .await?;
+        let page: Document<LibraryEntry> = read_json(res).await?;
+        entries.extend(page.data);
+        next = page.links.next;
+    }
+    Ok(entries)
+}
+
+/// Updates the library entry of `change.media_id`, creating it if the anime is not in the
+/// library yet.
+pub async fn update_library_entry(
+    api_url: &str,
+    access_token: &str,
+    user_id: u64,
+    change: &PendingChange,
+) -> anyhow::Result<LibraryEntry> {
+    let client = client();
+    let media_id = change.media_id;
+    let res = client
+        .get(format!(
+            "{api_url}/library-entries?filter[userId]={user_id}&filter[animeId]={media_id}"
+        ))
+        .bearer_auth(access_token)
+        .header("Accept", JSON_API)
+        .send()
+        .await?;
+    let existing: Document<LibraryEntry> = read_json(res).await?;
+
+    let attributes = library_entry_attributes(change.progress, &change.entry);
+    let request = match existing.data.first() {
+        Some(entry) => client
+            .patch(format!("{api_url}/library-entries/{}", entry.id))
+            .body(
+                serde_json::json!({
+                    "data": {"type": "libraryEntries", "id": entry.id, "attributes": attributes}
+                })
+                .to_string(),
+            ),
+        None => client.post(format!("{api_url}/library-entries")).body(
+            serde_json::json!({
+                "data": {
+                    "type": "libraryEntries",
+                    "attributes": attributes,
+                    "relationships": {
+                        "user": {"data": {"type": "users", "id": user_id.to_string()}},
+                        "anime": {"data": {"type": "anime", "id": media_id.to_string()}},
+                    }
+                }
+            })
+            .to_string(),
+        ),
+    };
+    let res = request
+        .bearer_auth(access_token)
+        .header("Content-Type", JSON_API)
+        .header("Accept", JSON_API)
+        .send()
+        .await?;
+    let updated: SingleDocument<LibraryEntry> = read_json(res).await?;
+    Ok(updated.data)
+}
+
+async fn read_json<T: DeserializeOwned>(res: reqwest::Response) -> anyhow::Result<T> {
+    let status = res.status();
+    let bytes = res.bytes().await?;
+    if !status.is_success() {
+        if let Ok(KitsuErrors { errors }) = serde_json::from_slice(&bytes) {
+            let messages = errors
+                .iter()
+                .filter_map(|e| e.detail.as_ref().or(e.title.as_ref()))
+                .cloned()
+                .collect::<Vec<_>>();
+            anyhow::bail!("{status}:{}", messages.join(","));
+        }
+        match serde_json::from_slice::<KitsuAuthError>(&bytes) {
+            Ok(KitsuAuthError {
+                error,
+                error_description: Some(description),
+            }) => anyhow::bail!("{status}:{error}:{description}"),
+            Ok(KitsuAuthError { error, .. }) => anyhow::bail!("{status}:{error}"),
+            Err(_) => anyhow::bail!("{status}"),
+        }
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::list_entry::{ListEntry, ListStatus};
+    use crate::database::sync_queue::Tracker;
+    use crate::http::mock::mock_server;
+
+    #[tokio::test]
+    async fn password_login_test() {
+        let (url, requests) = mock_server(vec![
+            (
+                "POST /token",
+                200,
+                r#"{"access_token": "access", "token_type": "bearer", "expires_in": 2592000, "refresh_token": "refresh", "scope": "public", "created_at": 1518235801}"#,
+            ),
+            (
+                "GET /users",
+                200,
+                r#"{"data": [{"id": "42", "type": "users", "attributes": {"name": "user"}}]}"#,
+            ),
+        ]);
+        let token = password_login(&url, "user@example.com", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!("access", token.access_token);
+        assert_eq!(2592000, token.expires_in);
+        assert_eq!(42, get_user_id(&url, "access").await.unwrap());
+
+        {
+            let requests = requests.lock().unwrap();
+            assert!(requests[0].contains("grant_type=password"));
+            assert!(requests[0].contains("password=hunter2"));
+        }
+
+        let (url, _) = mock_server(vec![(
+            "POST /token",
+            400,
+            r#"{"error": "invalid_grant", "error_description": "The provided authorization grant is invalid"}"#,
+        )]);
+        let err = password_login(&url, "user", "wrong").await.unwrap_err();
+        assert!(err.to_string().contains("invalid_grant"));
+    }
+
+    #[tokio::test]
+    async fn get_library_test() {
+        let (url, requests) = mock_server(vec![
+            (
+                "page[offset]=1",
+                200,
+                r#"{"data": [{"id": "2", "type": "libraryEntries", "attributes": {"status": "planned", "progress": 0, "updatedAt": "2020-01-01T00:00:00.000Z"}, "relationships": {"anime": {"data": {"type": "anime", "id": "12"}}}}], "links": {}}"#,
+            ),
+            (
+                "GET /library-entries",
+                200,
+                r#"{"data": [{"id": "1", "type": "libraryEntries", "attributes": {"status": "current", "progress": 3, "ratingTwenty": null, "updatedAt": "2020-01-01T00:00:00.000Z"}, "relationships": {"anime": {"data": {"type": "anime", "id": "11"}}}}], "links": {"next": "{url}/library-entries?page[offset]=1"}}"#,
+            ),
+        ]);
+        let entries = get_library(&url, "access", 42).await.unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(Some(11), entries[0].anime_id());
+        assert_eq!(3, entries[0].progress());
+        assert_eq!(0, entries[0].list_entry().score);
+        assert_eq!(1577836800, entries[0].updated_at());
+        assert_eq!(Some(ListStatus::Planning), entries[1].list_entry().status);
+        assert!(requests.lock().unwrap()[0].contains("filter[userId]=42"));
+    }
+
+    #[tokio::test]
+    async fn update_library_entry_test() {
+        let response = r#"{"data": {"id": "7", "type": "libraryEntries", "attributes": {"status": "completed", "progress": 64, "ratingTwenty": 18, "updatedAt": "2020-01-01T00:00:00.000Z"}}}"#;
+        let entry = ListEntry {
+            status: Some(ListStatus::Completed),
+            score: 90,
+            ..Default::default()
+        };
+        let change = PendingChange::new(Tracker::Kitsu, 5114, 64, entry.clone(), 0);
+
+        let (url, requests) = mock_server(vec![
+            (
+                "GET /library-entries",
+                200,
+                r#"{"data": [{"id": "7", "type": "libraryEntries", "attributes": {"status": "current", "progress": 60, "updatedAt": "2019-01-01T00:00:00.000Z"}}]}"#,
+            ),
+            ("PATCH /library-entries/7", 200, response),
+        ]);
+        let updated = update_library_entry(&url, "access", 42, &change)
+            .await
+            .unwrap();
+        assert_eq!(64, updated.progress());
+        assert_eq!(entry, updated.list_entry());
+        {
+            let requests = requests.lock().unwrap();
+            assert!(requests[0].contains("filter[animeId]=5114"));
+            assert!(requests[1].contains(r#""ratingTwenty":18"#));
+        }
+
+        let (url, requests) = mock_server(vec![
+            ("GET /library-entries", 200, r#"{"data": []}"#),
+            ("POST /library-entries", 201, response),
+        ]);
+        update_library_entry(&url, "access", 42, &change)
+            .await
+            .unwrap();
+        let requests = requests.lock().unwrap();
+        assert!(requests[1].contains(r#""anime":{"data":{"id":"5114","type":"anime"}}"#));
+    }
+}
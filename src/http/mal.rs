@@ -0,0 +1,270 @@
+//! MyAnimeList v2 REST API. Base urls are passed in so the requests can be pointed at a mock.
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use serde::de::DeserializeOwned;
+
+use crate::database::sync_queue::PendingChange;
+use crate::mal_serde::{
+    list_status_form, AnimeListPage, MalEntry, MalError, MalListStatus, MalToken, MalUser,
+};
+
+use super::client::client;
+
+pub const MAL_API_URL: &str = "https://api.myanimelist.net/v2";
+pub const MAL_AUTH_URL: &str = "https://myanimelist.net/v1/oauth2";
+
+const CODE_VERIFIER_LEN: usize = 128;
+
+/// Random PKCE code verifier. MyAnimeList only supports the `plain` challenge method, so it is
+/// also the code challenge.
+pub fn code_verifier() -> String {
+    const UNRESERVED: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
+    // Bytes past the last multiple of the alphabet length are dropped so every character is
+    // equally likely
+    let limit = (256 / UNRESERVED.len() * UNRESERVED.len()) as u8;
+    let mut verifier = String::with_capacity(CODE_VERIFIER_LEN);
+    let mut bytes = [0; CODE_VERIFIER_LEN];
+    while verifier.len() < CODE_VERIFIER_LEN {
+        OsRng.fill_bytes(&mut bytes);
+        verifier.extend(
+            bytes
+                .iter()
+                .filter(|b| **b < limit)
+                .map(|b| UNRESERVED[*b as usize % UNRESERVED.len()] as char)
+                .take(CODE_VERIFIER_LEN - verifier.len()),
+        );
+    }
+    verifier
+}
+
+pub fn authorize_url(auth_url: &str, client_id: &str, code_verifier: &str) -> String {
+    reqwest::Url::parse_with_params(
+        &format!("{auth_url}/authorize"),
+        [
+            ("response_type", "code"),
+            ("client_id", client_id),
+            ("code_challenge", code_verifier),
+            ("code_challenge_method", "plain"),
+        ],
+    )
+    .expect("Valid url")
+    .to_string()
+}
+
+/// Authorization code from what the user pasted, either the code or the url they were
+/// redirected to.
+pub fn parse_code(input: &str) -> String {
+    let input = input.trim();
+    match reqwest::Url::parse(input) {
+        Ok(url) => url
+            .query_pairs()
+            .find(|(k, _)| k == "code")
+            .map(|(_, v)| v.to_string())
+            .unwrap_or_default(),
+        Err(_) => input.to_string(),
+    }
+}
+
+pub async fn exchange_code(
+    auth_url: &str,
+    client_id: &str,
+    code: &str,
+    code_verifier: &str,
+) -> anyhow::Result<MalToken> {
+    let res = client()
+        .post(format!("{auth_url}/token"))
+        .form(&[
+            ("client_id", client_id),
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("code_verifier", code_verifier),
+        ])
+        .send()
+        .await?;
+    read_json(res).await
+}
+
+pub async fn refresh_token(
+    auth_url: &str,
+    client_id: &str,
+    refresh_token: &str,
+) -> anyhow::Result<MalToken> {
+    let res = client()
+        .post(format!("{auth_url}/token"))
+        .form(&[
+            ("client_id", client_id),
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ])
+        .send()
+        .await?;
+    read_json(res).await
+}
+
+pub async fn get_user(api_url: &str, access_token: &str) -> anyhow::Result<MalUser> {
+    let res = client()
+        .get(format!("{api_url}/users/@me"))
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+    read_json(res).await
+}
+
+/// Every entry in the user's anime list, following the pages.
+pub async fn get_anime_list(api_url: &str, access_token: &str) -> anyhow::Result<Vec<MalEntry>> {
+    let client = client();
+    let mut entries = vec![];
+    let mut next = Some(format!(
+        "{api_url}/users/@me/animelist?fields=list_status&limit=1000&nsfw=true"
+    ));
+    while let Some(url) = next {
+        let res = client.get(url).bearer_auth(access_token).send().await?;
+        let page: AnimeListPage = read_json(res).await?;
+        entries.extend(page.data);
+        next = page.paging.next;
+    }
+    Ok(entries)
+}
+
+pub async fn update_list_status(
+    api_url: &str,
+    access_token: &str,
+    change: &PendingChange,
+) -> anyhow::Result<MalListStatus> {
+    let res = client()
+        .patch(format!(
+            "{api_url}/anime/{}/my_list_status",
+            change.media_id
+        ))
+        .bearer_auth(access_token)
+        .form(&list_status_form(change.progress, &change.entry))
+        .send()
+        .await?;
+    read_json(res).await
+}
+
+async fn read_json<T: DeserializeOwned>(res: reqwest::Response) -> anyhow::Result<T> {
+    let status = res.status();
+    let bytes = res.bytes().await?;
+    if !status.is_success() {
+        match serde_json::from_slice::<MalError>(&bytes) {
+            Ok(MalError {
+                error,
+                message: Some(message),
+            }) => anyhow::bail!("{status}:{error}:{message}"),
+            Ok(MalError { error, .. }) => anyhow::bail!("{status}:{error}"),
+            Err(_) => anyhow::bail!("{status}"),
+        }
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::list_entry::{ListEntry, ListStatus};
+    use crate::database::sync_queue::Tracker;
+    use crate::http::mock::mock_server;
+
+    #[test]
+    fn authorize_url_test() {
+        let verifier = code_verifier();
+        assert_eq!(CODE_VERIFIER_LEN, verifier.len());
+        assert_ne!(verifier, code_verifier());
+
+        let url = authorize_url(MAL_AUTH_URL, "client", &verifier);
+        assert!(url.starts_with("https://myanimelist.net/v1/oauth2/authorize?"));
+        let url = reqwest::Url::parse(&url).unwrap();
+        let query = |key| url.query_pairs().find(|(k, _)| k == key).unwrap().1;
+        assert_eq!(verifier, query("code_challenge"));
+        assert_eq!("plain", query("code_challenge_method"));
+
+        assert_eq!("abc", parse_code(" abc\n"));
+        assert_eq!(
+            "abc",
+            parse_code("http://localhost/callback?code=abc&state=1")
+        );
+    }
+
+    #[tokio::test]
+    async fn exchange_code_test() {
+        let (url, requests) = mock_server(vec![(
+            "POST /token",
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "access", "refresh_token": "refresh"}"#,
+        )]);
+        let token = exchange_code(&url, "client", "code", "verifier")
+            .await
+            .unwrap();
+        assert_eq!("access", token.access_token);
+        assert_eq!("refresh", token.refresh_token);
+        assert_eq!(2678400, token.expires_in);
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains("grant_type=authorization_code"));
+        assert!(requests[0].contains("code_verifier=verifier"));
+    }
+
+    #[tokio::test]
+    async fn get_anime_list_test() {
+        let (url, requests) = mock_server(vec![
+            (
+                "offset=1",
+                200,
+                r#"{"data": [{"node": {"id": 2, "title": "B"}, "list_status": {"status": "plan_to_watch", "updated_at": "2020-01-01T00:00:00+00:00"}}], "paging": {}}"#,
+            ),
+            (
+                "GET /users/@me/animelist",
+                200,
+                r#"{"data": [{"node": {"id": 1, "title": "A"}, "list_status": {"status": "watching", "score": 7, "num_episodes_watched": 3, "is_rewatching": false, "updated_at": "2020-01-01T00:00:00+00:00"}}], "paging": {"next": "{url}/users/@me/animelist?offset=1"}}"#,
+            ),
+        ]);
+        let entries = get_anime_list(&url, "access").await.unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(1, entries[0].id());
+        assert_eq!(3, entries[0].list_status().progress());
+        assert_eq!(
+            Some(ListStatus::Current),
+            entries[0].list_status().list_entry().status
+        );
+        assert_eq!(70, entries[0].list_status().list_entry().score);
+        assert_eq!(1577836800, entries[0].list_status().updated_at());
+        assert_eq!(
+            Some(ListStatus::Planning),
+            entries[1].list_status().list_entry().status
+        );
+        assert_eq!(2, requests.lock().unwrap().len());
+
+        let (url, _) = mock_server(vec![(
+            "GET /users/@me/animelist",
+            401,
+            r#"{"error": "invalid_token"}"#,
+        )]);
+        let err = get_anime_list(&url, "expired").await.unwrap_err();
+        assert!(err.to_string().contains("invalid_token"));
+    }
+
+    #[tokio::test]
+    async fn update_list_status_test() {
+        let (url, requests) = mock_server(vec![(
+            "PATCH /anime/5114/my_list_status",
+            200,
+            r#"{"status": "completed", "score": 9, "num_episodes_watched": 64, "is_rewatching": false, "updated_at": "2020-01-01T00:00:00+00:00"}"#,
+        )]);
+        let entry = ListEntry {
+            status: Some(ListStatus::Completed),
+            score: 90,
+            ..Default::default()
+        };
+        let change = PendingChange::new(Tracker::MyAnimeList, 5114, 64, entry.clone(), 0);
+        let status = update_list_status(&url, "access", &change).await.unwrap();
+        assert_eq!(64, status.progress());
+        assert_eq!(entry, status.list_entry());
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains("num_watched_episodes=64"));
+        assert!(requests[0].contains("status=completed"));
+        assert!(requests[0].contains("score=9"));
+    }
+}
@@ -0,0 +1,62 @@
+//! Local http server standing in for tracker apis in tests.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+/// Stand-in for a tracker's api. Answers requests whose request line contains a key of
+/// `routes`, where "{url}" in the body is replaced with the server's url, and records the
+/// request line and body of every request. 429 responses ask to retry after 30 seconds.
+pub fn mock_server(
+    routes: Vec<(&'static str, u16, &'static str)>,
+) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(vec![]));
+    let (server_url, server_requests) = (url.clone(), requests.clone());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((k, v)) = line.split_once(':') {
+                    if k.eq_ignore_ascii_case("content-length") {
+                        content_length = v.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            server_requests.lock().unwrap().push(format!(
+                "{} {}",
+                request_line.trim(),
+                String::from_utf8_lossy(&body)
+            ));
+
+            let (status, body) = routes
+                .iter()
+                .find(|(route, ..)| request_line.contains(route))
+                .map(|(_, status, body)| (*status, body.replace("{url}", &server_url)))
+                .unwrap_or((404, r#"{"error": "not_found"}"#.to_string()));
+            let retry_after = match status {
+                429 => "Retry-After: 30\r\n",
+                _ => "",
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\n{retry_after}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        }
+    });
+    (url, requests)
+}
@@ -1,14 +1,41 @@
+pub mod anilist;
+pub mod client;
+pub mod html;
+pub mod kitsu;
+pub mod mal;
+#[cfg(test)]
+mod mock;
+
 use std::{sync::mpsc, time::Duration};
 
 use crate::{
-    anilist_serde::{MediaEntry, MediaList, Viewer},
-    database::{self, AniListCred},
-    ui::update_anilist_watched,
-    App, ConnectionOverlayState, LoginProgress, CONNECTION_OVERLAY_TIMEOUT,
+    anilist_serde::{AniListError, MediaAiring, MediaDetails, MediaEntry, MediaList, Viewer},
+    config::Config,
+    database::{
+        self,
+        sync_queue::{PendingChange, SyncQueue, Tracker},
+        AniListCred, KitsuCred, MalCred,
+    },
+    kitsu_serde::{KitsuToken, LibraryEntry},
+    mal_serde::{MalEntry, MalListStatus, MalToken, MalUser},
+    save_credentials, App, ConnectionOverlayState, LoginProgress, CONNECTION_OVERLAY_TIMEOUT,
+    ERROR_OVERLAY_TIMEOUT,
 };
 
 pub type HttpSender = mpsc::Sender<anyhow::Result<HttpData>>;
 
+/// Configures the shared client from aniki.conf, before any request is sent.
+pub fn init(cfg: &Config) -> anyhow::Result<()> {
+    let defaults = client::HttpConfig::default();
+    client::init(&client::HttpConfig {
+        proxy: cfg.http_proxy().map(String::from),
+        user_agent: cfg.user_agent().map_or(defaults.user_agent, String::from),
+        connect_timeout: Duration::from_secs(cfg.connect_timeout()),
+        timeout: Duration::from_secs(cfg.request_timeout()),
+    })?;
+    anilist::init(cfg.anilist_api_url().unwrap_or(anilist::ANILIST_API_URL))
+}
+
 pub fn get_anilist_media_list(tx: &HttpSender, user_id: u64, access_token: &str) {
     let access_token = access_token.to_string();
     send_request(
@@ -25,6 +52,77 @@ pub fn send_login(tx: &HttpSender, access_token: &str) {
     send_request(tx, RequestKind::SendLogin { access_token });
 }
 
+/// Waits for the browser to be redirected back after authorizing, see
+/// `anilist::listen_for_token`.
+pub fn listen_for_anilist_token(tx: &HttpSender, redirect_uri: &str) {
+    let redirect_uri = redirect_uri.to_string();
+    send_request(tx, RequestKind::AniListRedirectLogin { redirect_uri });
+}
+
+pub fn get_media_details(tx: &HttpSender, media_id: u32) {
+    send_request(tx, RequestKind::GetMediaDetails { media_id });
+}
+
+/// Fetches upcoming episodes of `media_ids`, a page of `anilist::AIRING_PAGE_LEN` at a time.
+pub fn get_airing_schedules(tx: &HttpSender, media_ids: &[u32]) {
+    for media_ids in media_ids.chunks(anilist::AIRING_PAGE_LEN) {
+        let media_ids = media_ids.to_vec();
+        send_request(tx, RequestKind::GetAiringSchedules { media_ids });
+    }
+}
+
+pub fn send_mal_login(tx: &HttpSender, client_id: &str, code: &str, code_verifier: &str) {
+    send_request(
+        tx,
+        RequestKind::MalLogin {
+            client_id: client_id.to_string(),
+            code: code.to_string(),
+            code_verifier: code_verifier.to_string(),
+        },
+    );
+}
+
+pub fn refresh_mal_token(tx: &HttpSender, client_id: &str, refresh_token: &str) {
+    send_request(
+        tx,
+        RequestKind::RefreshMalToken {
+            client_id: client_id.to_string(),
+            refresh_token: refresh_token.to_string(),
+        },
+    );
+}
+
+pub fn get_mal_list(tx: &HttpSender, access_token: &str) {
+    let access_token = access_token.to_string();
+    send_request(tx, RequestKind::GetMalList { access_token });
+}
+
+pub fn send_kitsu_login(tx: &HttpSender, username: &str, password: &str) {
+    send_request(
+        tx,
+        RequestKind::KitsuLogin {
+            username: username.to_string(),
+            password: password.to_string(),
+        },
+    );
+}
+
+pub fn refresh_kitsu_token(tx: &HttpSender, refresh_token: &str) {
+    let refresh_token = refresh_token.to_string();
+    send_request(tx, RequestKind::RefreshKitsuToken { refresh_token });
+}
+
+pub fn get_kitsu_library(tx: &HttpSender, user_id: u64, access_token: &str) {
+    let access_token = access_token.to_string();
+    send_request(
+        tx,
+        RequestKind::GetKitsuLibrary {
+            user_id,
+            access_token,
+        },
+    );
+}
+
 #[derive(Debug)]
 pub enum RequestKind {
     GetAnilistMediaList {
@@ -34,11 +132,52 @@ pub enum RequestKind {
     SendLogin {
         access_token: String,
     },
+    AniListRedirectLogin {
+        redirect_uri: String,
+    },
+    /// Sent together, at most `anilist::SAVE_BATCH_LEN`
     UpdateMedia {
         access_token: String,
+        changes: Vec<PendingChange>,
+    },
+    GetMediaDetails {
         media_id: u32,
-        episode: u32,
-        ptr_id: u64,
+    },
+    /// At most `anilist::AIRING_PAGE_LEN`
+    GetAiringSchedules {
+        media_ids: Vec<u32>,
+    },
+    MalLogin {
+        client_id: String,
+        code: String,
+        code_verifier: String,
+    },
+    RefreshMalToken {
+        client_id: String,
+        refresh_token: String,
+    },
+    GetMalList {
+        access_token: String,
+    },
+    UpdateMalEntry {
+        access_token: String,
+        change: PendingChange,
+    },
+    KitsuLogin {
+        username: String,
+        password: String,
+    },
+    RefreshKitsuToken {
+        refresh_token: String,
+    },
+    GetKitsuLibrary {
+        user_id: u64,
+        access_token: String,
+    },
+    UpdateKitsuEntry {
+        user_id: u64,
+        access_token: String,
+        change: PendingChange,
     },
     Test(String),
 }
@@ -47,16 +186,26 @@ pub enum RequestKind {
 pub enum HttpData {
     Viewer(Viewer, String),
     MediaList(MediaList),
-    UpdateMedia(u64 /* ptr_id */, MediaEntry),
+    UpdateMedia(PendingChange, MediaEntry),
+    SyncFailed(Tracker, u32 /* media_id */),
+    /// AniList answered with an error, `media_id` is of the change that failed to sync
+    AniListError(AniListError, Option<u32>),
+    MediaDetails(MediaDetails),
+    AiringSchedules(Vec<MediaAiring>),
+    MalLogin(MalUser, MalToken),
+    /// Logging in failed before there was a token to check
+    LoginFailed,
+    MalList(Vec<MalEntry>),
+    UpdateMalEntry(PendingChange, MalListStatus),
+    KitsuLogin(u64 /* user_id */, KitsuToken),
+    KitsuLibrary(Vec<LibraryEntry>),
+    UpdateKitsuEntry(PendingChange, LibraryEntry),
     Debug(String),
 }
 
 pub fn send_request(tx: &mpsc::Sender<anyhow::Result<HttpData>>, request: RequestKind) {
     let tx = tx.clone();
     tokio::spawn(async move {
-        let request_err = |err| eprintln!("ERROR:failed to await request:{err}");
-        let byte_err = |err| eprintln!("ERROR:failed to receive bytes:{err}");
-        let deserialize_err = |err| println!("ERROR:failed to deserialize json:{err}");
         let send_err = |err| eprintln!("ERROR:failed to send http data:{err}");
 
         match request {
@@ -65,74 +214,224 @@ pub fn send_request(tx: &mpsc::Sender<anyhow::Result<HttpData>>, request: Reques
                 access_token,
             } => {
                 let anime_list_query = include_str!("anime_list.gql");
-                let json = serde_json::json!({"query": anime_list_query, "variables": {"id": 15125, "uid": user_id}});
-                let res = reqwest::Client::new()
-                    .post("https://graphql.anilist.co")
-                    .header("Authorization", format!("Bearer {access_token}"))
-                    .header("Content-Type", "application/json")
-                    .header("Accept", "application/json")
-                    .body(json.to_string())
-                    .timeout(Duration::from_secs(10))
-                    .send()
-                    .await
-                    .map_err(request_err)?;
-                tx.send(Ok(HttpData::MediaList(
-                    MediaList::deserialize_json(&res.bytes().await.map_err(byte_err)?)
-                        .map_err(deserialize_err)?,
-                )))
-                .map_err(send_err)
+                let variables = serde_json::json!({"id": 15125, "uid": user_id});
+                let res = anilist::client().query(Some(&access_token), anime_list_query, variables);
+                let data = match res.await.and_then(|v| MediaList::deserialize_json(&v)) {
+                    Ok(media_list) => Some(HttpData::MediaList(media_list)),
+                    Err(e) => anilist_failed(e, "get AniList list"),
+                };
+                match data {
+                    Some(data) => tx.send(Ok(data)).map_err(send_err),
+                    None => Ok(()),
+                }
             }
             RequestKind::SendLogin { access_token } => {
-                let mutation = r#"query { Viewer { id } }"#;
-                let json = serde_json::json!({"query": mutation, "variables": {"id": 15125}});
-                let res = reqwest::Client::new()
-                    .post("https://graphql.anilist.co")
-                    .header("Authorization", format!("Bearer {access_token}"))
-                    .header("Content-Type", "application/json")
-                    .header("Accept", "application/json")
-                    .body(json.to_string())
-                    .timeout(Duration::from_secs(10))
-                    .send()
-                    .await
-                    .map_err(request_err)?;
-                let data = HttpData::Viewer(
-                    Viewer::deserialize_json(&res.bytes().await.map_err(byte_err)?)
-                        .map_err(deserialize_err)?,
-                    access_token.to_string(),
-                );
-                tx.send(Ok(data)).map_err(send_err)
+                let query = r#"query { Viewer { id mediaListOptions { scoreFormat } } }"#;
+                let variables = serde_json::json!({});
+                let res = anilist::client().query(Some(&access_token), query, variables);
+                let viewer = match res.await.and_then(|v| Viewer::deserialize_json(&v)) {
+                    Ok(viewer) => viewer,
+                    Err(e) => match e.downcast::<AniListError>() {
+                        Ok(e) => Viewer::Err(e),
+                        Err(e) => {
+                            eprintln!("ERROR:failed to check AniList token:{e:#}");
+                            return Ok(());
+                        }
+                    },
+                };
+                tx.send(Ok(HttpData::Viewer(viewer, access_token)))
+                    .map_err(send_err)
+            }
+            RequestKind::AniListRedirectLogin { redirect_uri } => {
+                match anilist::listen_for_token(&redirect_uri).await {
+                    // Checked like a pasted token
+                    Ok(access_token) => {
+                        send_request(&tx, RequestKind::SendLogin { access_token });
+                        Ok(())
+                    }
+                    Err(e) => {
+                        eprintln!("ERROR:failed to get AniList token:{e:#}");
+                        tx.send(Ok(HttpData::LoginFailed)).map_err(send_err)
+                    }
+                }
             }
-            // TODO: If the same episode is pressed, the `last_watched` is updateed
-            // locally, but the `updatedAt` is not updated in anilist.
-            //
-            // This causes unnecessary requests to anilist everytime the aniki is opened.
-            //
-            // Maybe have a dirty bit on every anime entry that is cleared by this?
             RequestKind::UpdateMedia {
                 access_token,
-                media_id,
-                episode,
-                ptr_id,
+                changes,
+            } => {
+                let entries = anilist::client().save_entries(&access_token, &changes);
+                match entries.await {
+                    Ok(entries) => {
+                        for (change, entry) in changes.into_iter().zip(entries) {
+                            let data = match entry {
+                                Ok(entry) => HttpData::UpdateMedia(change, entry),
+                                Err(e) => {
+                                    let media_id = change.media_id;
+                                    eprintln!("ERROR:failed to sync media {media_id}:{e}");
+                                    HttpData::AniListError(e, Some(media_id))
+                                }
+                            };
+                            tx.send(Ok(data)).map_err(send_err)?;
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("ERROR:failed to sync {} media:{e:#}", changes.len());
+                        let error = e.downcast::<AniListError>().ok();
+                        for change in changes {
+                            let data = match &error {
+                                Some(e) => HttpData::AniListError(e.clone(), Some(change.media_id)),
+                                None => HttpData::SyncFailed(Tracker::AniList, change.media_id),
+                            };
+                            tx.send(Ok(data)).map_err(send_err)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            RequestKind::MalLogin {
+                client_id,
+                code,
+                code_verifier,
+            } => {
+                let token =
+                    mal::exchange_code(mal::MAL_AUTH_URL, &client_id, &code, &code_verifier);
+                let data = match mal_login(token.await).await {
+                    Ok((user, token)) => HttpData::MalLogin(user, token),
+                    Err(e) => {
+                        eprintln!("ERROR:failed to log in to MyAnimeList:{e:#}");
+                        HttpData::LoginFailed
+                    }
+                };
+                tx.send(Ok(data)).map_err(send_err)
+            }
+            RequestKind::RefreshMalToken {
+                client_id,
+                refresh_token,
+            } => {
+                let token = mal::refresh_token(mal::MAL_AUTH_URL, &client_id, &refresh_token);
+                match mal_login(token.await).await {
+                    Ok((user, token)) => tx
+                        .send(Ok(HttpData::MalLogin(user, token)))
+                        .map_err(send_err),
+                    // The current token is kept, it may still work
+                    Err(e) => {
+                        eprintln!("ERROR:failed to refresh MyAnimeList token:{e:#}");
+                        Ok(())
+                    }
+                }
+            }
+            RequestKind::GetMalList { access_token } => {
+                match mal::get_anime_list(mal::MAL_API_URL, &access_token).await {
+                    Ok(entries) => tx.send(Ok(HttpData::MalList(entries))).map_err(send_err),
+                    Err(e) => {
+                        eprintln!("ERROR:failed to get MyAnimeList list:{e:#}");
+                        Ok(())
+                    }
+                }
+            }
+            RequestKind::UpdateMalEntry {
+                access_token,
+                change,
             } => {
-                let anime_list_query = include_str!("update_anilist_media.gql");
-                let json = serde_json::json!({"query": anime_list_query, "variables": {"id": 15125, "mediaId": media_id, "episode": episode}});
-                let res = reqwest::Client::new()
-                    .post("https://graphql.anilist.co")
-                    .header("Authorization", format!("Bearer {access_token}"))
-                    .header("Content-Type", "application/json")
-                    .header("Accept", "application/json")
-                    .body(json.to_string())
-                    .timeout(Duration::from_secs(10))
-                    .send()
-                    .await
-                    .map_err(request_err)?;
-                let data = HttpData::UpdateMedia(
-                    ptr_id,
-                    MediaEntry::deserialize_json(&res.bytes().await.map_err(byte_err)?)
-                        .map_err(deserialize_err)?,
+                let media_id = change.media_id;
+                let status = mal::update_list_status(mal::MAL_API_URL, &access_token, &change);
+                let data = match status.await {
+                    Ok(status) => HttpData::UpdateMalEntry(change, status),
+                    Err(e) => {
+                        eprintln!("ERROR:failed to sync MyAnimeList media {media_id}:{e:#}");
+                        HttpData::SyncFailed(Tracker::MyAnimeList, media_id)
+                    }
+                };
+                tx.send(Ok(data)).map_err(send_err)
+            }
+            RequestKind::KitsuLogin { username, password } => {
+                let token = kitsu::password_login(kitsu::KITSU_AUTH_URL, &username, &password);
+                let data = match kitsu_login(token.await).await {
+                    Ok((user_id, token)) => HttpData::KitsuLogin(user_id, token),
+                    Err(e) => {
+                        eprintln!("ERROR:failed to log in to Kitsu:{e:#}");
+                        HttpData::LoginFailed
+                    }
+                };
+                tx.send(Ok(data)).map_err(send_err)
+            }
+            RequestKind::RefreshKitsuToken { refresh_token } => {
+                let token = kitsu::refresh_token(kitsu::KITSU_AUTH_URL, &refresh_token);
+                match kitsu_login(token.await).await {
+                    Ok((user_id, token)) => tx
+                        .send(Ok(HttpData::KitsuLogin(user_id, token)))
+                        .map_err(send_err),
+                    // The current token is kept, it may still work
+                    Err(e) => {
+                        eprintln!("ERROR:failed to refresh Kitsu token:{e:#}");
+                        Ok(())
+                    }
+                }
+            }
+            RequestKind::GetKitsuLibrary {
+                user_id,
+                access_token,
+            } => {
+                let library = kitsu::get_library(kitsu::KITSU_API_URL, &access_token, user_id);
+                match library.await {
+                    Ok(entries) => tx
+                        .send(Ok(HttpData::KitsuLibrary(entries)))
+                        .map_err(send_err),
+                    Err(e) => {
+                        eprintln!("ERROR:failed to get Kitsu library:{e:#}");
+                        Ok(())
+                    }
+                }
+            }
+            RequestKind::UpdateKitsuEntry {
+                user_id,
+                access_token,
+                change,
+            } => {
+                let media_id = change.media_id;
+                let entry = kitsu::update_library_entry(
+                    kitsu::KITSU_API_URL,
+                    &access_token,
+                    user_id,
+                    &change,
                 );
-                tx.send(anyhow::Ok(data))
-                    .map_err(|err| eprintln!("ERROR:failed to send http data:{err}"))
+                let data = match entry.await {
+                    Ok(entry) => HttpData::UpdateKitsuEntry(change, entry),
+                    Err(e) => {
+                        eprintln!("ERROR:failed to sync Kitsu media {media_id}:{e:#}");
+                        HttpData::SyncFailed(Tracker::Kitsu, media_id)
+                    }
+                };
+                tx.send(Ok(data)).map_err(send_err)
+            }
+            RequestKind::GetMediaDetails { media_id } => {
+                let media_details_query = include_str!("media_details.gql");
+                let variables = serde_json::json!({"id": media_id});
+                let res = anilist::client().query(None, media_details_query, variables);
+                let data = match res.await.and_then(|v| MediaDetails::deserialize_json(&v)) {
+                    Ok(mut details) => {
+                        details.set_description(details.description().map(html::html_to_text));
+                        Some(HttpData::MediaDetails(details))
+                    }
+                    Err(e) => anilist_failed(e, &format!("get details of {media_id}")),
+                };
+                match data {
+                    Some(data) => tx.send(Ok(data)).map_err(send_err),
+                    None => Ok(()),
+                }
+            }
+            RequestKind::GetAiringSchedules { media_ids } => {
+                let airing_schedule_query = include_str!("airing_schedule.gql");
+                let variables = serde_json::json!({"ids": media_ids, "perPage": media_ids.len()});
+                let res = anilist::client().query(None, airing_schedule_query, variables);
+                let data = match res.await.and_then(|v| MediaAiring::deserialize_page(&v)) {
+                    Ok(schedules) => Some(HttpData::AiringSchedules(schedules)),
+                    Err(e) => anilist_failed(e, "get airing schedules"),
+                };
+                match data {
+                    Some(data) => tx.send(Ok(data)).map_err(send_err),
+                    None => Ok(()),
+                }
             }
             RequestKind::Test(s) => {
                 eprintln!("Got test:{s}");
@@ -142,69 +441,293 @@ pub fn send_request(tx: &mpsc::Sender<anyhow::Result<HttpData>>, request: Reques
     });
 }
 
-fn sync_to_anilist(tx: &HttpSender, access_token: &str, animes: &mut [&mut database::Anime]) {
+/// What to do with a failed AniList request. Errors AniList answered with are shown, others,
+/// ie: being offline, are only logged.
+fn anilist_failed(e: anyhow::Error, what: &str) -> Option<HttpData> {
+    eprintln!("ERROR:failed to {what}:{e:#}");
+    e.downcast::<AniListError>()
+        .ok()
+        .map(|e| HttpData::AniListError(e, None))
+}
+
+/// Gets the user a newly issued token belongs to.
+async fn mal_login(token: anyhow::Result<MalToken>) -> anyhow::Result<(MalUser, MalToken)> {
+    let token = token?;
+    let user = mal::get_user(mal::MAL_API_URL, &token.access_token).await?;
+    Ok((user, token))
+}
+
+/// Gets the id of the user a newly issued Kitsu token belongs to.
+async fn kitsu_login(token: anyhow::Result<KitsuToken>) -> anyhow::Result<(u64, KitsuToken)> {
+    let token = token?;
+    let user_id = kitsu::get_user_id(kitsu::KITSU_API_URL, &token.access_token).await?;
+    Ok((user_id, token))
+}
+
+fn sync_to_tracker(
+    sync_queue: &mut SyncQueue,
+    tracker: Tracker,
+    animes: &mut [&mut database::Anime],
+) {
     for anime in animes {
-        update_anilist_watched(tx, access_token, *anime);
+        if let Some(change) = anime.pending_change(tracker) {
+            sync_queue.push(change);
+        }
+    }
+}
+
+/// Sends queued changes that are due, see `SyncQueue`.
+pub fn flush_sync_queue(app: &mut App) {
+    let anilist_token = app.database.anilist_access_token().map(str::to_string);
+    let mal_token = app.database.mal_access_token().map(str::to_string);
+    let kitsu_cred = app
+        .database
+        .kitsu_cred()
+        .as_ref()
+        .map(|v| (v.user_id(), v.access_token().to_string()));
+    if let Some(sync_queue) = app.database.sync_queue() {
+        let mut anilist_changes = vec![];
+        for change in sync_queue.due(database::get_time()) {
+            // `due` only has changes for trackers that are logged in, anything else is left
+            // for the next flush rather than kept in flight
+            let request = match (change.tracker, &anilist_token, &mal_token, &kitsu_cred) {
+                (Tracker::AniList, Some(_), ..) => {
+                    anilist_changes.push(change);
+                    continue;
+                }
+                (Tracker::MyAnimeList, _, Some(access_token), _) => RequestKind::UpdateMalEntry {
+                    access_token: access_token.clone(),
+                    change,
+                },
+                (Tracker::Kitsu, .., Some((user_id, access_token))) => {
+                    RequestKind::UpdateKitsuEntry {
+                        user_id: *user_id,
+                        access_token: access_token.clone(),
+                        change,
+                    }
+                }
+                _ => {
+                    sync_queue.release(change.tracker, change.media_id);
+                    continue;
+                }
+            };
+            send_request(&app.http_tx, request);
+        }
+        // Batched, so a large library does not hit the rate limit
+        if let Some(access_token) = anilist_token {
+            for changes in anilist_changes.chunks(anilist::SAVE_BATCH_LEN) {
+                let request = RequestKind::UpdateMedia {
+                    access_token: access_token.clone(),
+                    changes: changes.to_vec(),
+                };
+                send_request(&app.http_tx, request);
+            }
+        }
+    }
+}
+
+/// Acts on an error AniList answered with, and shows it, see `ErrorOverlay`.
+fn handle_anilist_error(app: &mut App, error: AniListError, media_id: Option<u32>) {
+    let now = database::get_time();
+    match &error {
+        // Kept until logging in again, which the overlay and toolbar ask for
+        AniListError::InvalidToken => {
+            app.database.anilist_expire(now);
+            save_credentials(app);
+        }
+        // Left for AniList to cool down, not retried as a failure
+        AniListError::RateLimited(secs) => {
+            if let Some(sync_queue) = app.database.sync_queue() {
+                sync_queue.delay(Tracker::AniList, now + secs);
+            }
+        }
+        _ => (),
     }
+    if let (Some(media_id), Some(sync_queue)) = (media_id, app.database.sync_queue()) {
+        match error {
+            // Retrying will not make the anime exist
+            AniListError::NotFound => sync_queue.remove(Tracker::AniList, media_id),
+            // Sent again after logging in, or once the rate limit is over
+            AniListError::InvalidToken | AniListError::RateLimited(_) => {
+                sync_queue.release(Tracker::AniList, media_id)
+            }
+            _ => sync_queue.fail(Tracker::AniList, media_id, now),
+        }
+    }
+    app.error_overlay.error = Some(error);
+    app.error_overlay.timeout = ERROR_OVERLAY_TIMEOUT;
 }
 
-pub fn poll_http(app: &mut App) {
+/// Handles a response from the http thread. Returns whether there was one.
+pub fn poll_http(app: &mut App) -> bool {
     if let Ok(data) = app.http_rx.try_recv() {
         let data = match data {
             Ok(v) => v,
             Err(e) => {
                 eprintln!("ERROR:Something went wrong in http thread:{e}");
-                return;
+                return true;
             }
         };
 
         match data {
             HttpData::Viewer(viewer, access_token) => match viewer {
-                Viewer::Ok(id) => {
-                    app.database
-                        .anilist_cred_set(Some(AniListCred::new(id, access_token)));
+                Viewer::Ok(id, score_format) => {
+                    let expires_at = anilist::token_expiry(&access_token);
+                    let cred = AniListCred::new(id, access_token, expires_at);
+                    app.database.anilist_cred_set(Some(cred));
+                    app.database.set_score_format(score_format);
+                    save_credentials(app);
                     app.login_progress = LoginProgress::None;
                     app.connection_overlay.state = ConnectionOverlayState::Connected;
+                    if let Some(sync_queue) = app.database.sync_queue() {
+                        sync_queue.retry_now();
+                    }
                     app.connection_overlay.timeout = CONNECTION_OVERLAY_TIMEOUT;
                 }
-                Viewer::Err(_) => {
+                Viewer::Err(AniListError::InvalidToken) => {
                     app.login_progress = LoginProgress::Failed;
                     app.login_state.textbox.text.clear();
+                    // The stored token is checked on start, and may have been revoked since
+                    let stored = app.database.anilist_cred().as_ref();
+                    if stored.is_some_and(|v| v.access_token() == access_token) {
+                        handle_anilist_error(app, AniListError::InvalidToken, None);
+                    }
+                }
+                // Not the token's fault, so it is not reported as wrong
+                Viewer::Err(e) => {
+                    app.login_progress = LoginProgress::None;
+                    handle_anilist_error(app, e, None);
                 }
             },
             HttpData::MediaList(media_list) => match media_list {
                 MediaList::Ok(collections) => {
+                    // Reachable again, so do not wait for the backoff
+                    if let Some(sync_queue) = app.database.sync_queue() {
+                        sync_queue.retry_now();
+                    }
                     for collection in collections.iter() {
                         let mut sync_newer = app.database.update_anilist_list(collection);
 
-                        if let Some(access_token) = app.database.anilist_access_token() {
-                            sync_to_anilist(&app.http_tx, access_token, &mut sync_newer);
+                        if let Some(sync_queue) = app.database.sync_queue() {
+                            sync_to_tracker(sync_queue, Tracker::AniList, &mut sync_newer);
                         }
                     }
                     app.database.update_cached();
+                    app.database.update_remote_entries(&collections);
+                    // Statuses may have changed, so which anime are being watched did too
+                    get_airing_schedules(&app.http_tx, &app.database.airing_media_ids());
+                    if let Err(e) = app.database.retrieve_images(&app.thumbnail_path) {
+                        eprintln!("ERROR:failed to retrieve thumbnails:{e}");
+                    }
                 }
-                MediaList::Err(_) => {
-                    eprintln!("{}:{}:Oops", std::file!(), std::line!());
-                }
+                MediaList::Err(e) => handle_anilist_error(app, e, None),
             },
-            HttpData::UpdateMedia(anime_ptr, entry) => {
-                eprintln!("Update media request received... {}", entry.id());
-                if let Some(anime) = app
+            HttpData::UpdateMedia(change, entry) => {
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_queue.complete(Tracker::AniList, entry.id(), change.modified);
+                }
+                for anime in app
                     .database
                     .anime_map()
                     .iter_mut()
-                    .find(|v| v.as_ptr_id() == anime_ptr) {
+                    .filter(|v| v.anilist_id() == Some(entry.id()))
+                {
                     if entry.updated_at() > anime.last_watched() {
-                        eprintln!("Updated \"{}\"...", anime.title());
                         anime.set_last_watched(entry.updated_at());
                     }
-                } else {
-                    eprintln!("Failed to update {}", entry.id());
+                    anime.set_synced(Tracker::AniList, change.snapshot(), entry.snapshot());
                 }
             }
+            HttpData::SyncFailed(tracker, media_id) => {
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_queue.fail(tracker, media_id, database::get_time());
+                }
+            }
+            HttpData::AniListError(e, media_id) => handle_anilist_error(app, e, media_id),
+            HttpData::MalLogin(user, token) => {
+                get_mal_list(&app.http_tx, &token.access_token);
+                let cred = MalCred::new(user.name, token, database::get_time());
+                app.database.mal_cred_set(Some(cred));
+                save_credentials(app);
+                app.login_progress = LoginProgress::None;
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_queue.retry_now();
+                }
+            }
+            HttpData::LoginFailed => {
+                app.login_progress = LoginProgress::Failed;
+                app.login_state.textbox.text.clear();
+                app.login_state.password.text.clear();
+            }
+            HttpData::MalList(entries) => {
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_queue.retry_now();
+                }
+                let mut sync_newer = app.database.update_mal_list(&entries);
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_to_tracker(sync_queue, Tracker::MyAnimeList, &mut sync_newer);
+                }
+                app.database.update_cached();
+            }
+            HttpData::UpdateMalEntry(change, status) => {
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_queue.complete(Tracker::MyAnimeList, change.media_id, change.modified);
+                }
+                for anime in app
+                    .database
+                    .anime_map()
+                    .iter_mut()
+                    .filter(|v| v.mal_id() == Some(change.media_id))
+                {
+                    anime.set_synced(Tracker::MyAnimeList, change.snapshot(), status.snapshot());
+                }
+            }
+            HttpData::KitsuLogin(user_id, token) => {
+                get_kitsu_library(&app.http_tx, user_id, &token.access_token);
+                let cred = KitsuCred::new(user_id, token, database::get_time());
+                app.database.kitsu_cred_set(Some(cred));
+                save_credentials(app);
+                app.login_progress = LoginProgress::None;
+                app.login_state.password.text.clear();
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_queue.retry_now();
+                }
+            }
+            HttpData::KitsuLibrary(entries) => {
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_queue.retry_now();
+                }
+                let mut sync_newer = app.database.update_kitsu_list(&entries);
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_to_tracker(sync_queue, Tracker::Kitsu, &mut sync_newer);
+                }
+                app.database.update_cached();
+            }
+            HttpData::UpdateKitsuEntry(change, entry) => {
+                if let Some(sync_queue) = app.database.sync_queue() {
+                    sync_queue.complete(Tracker::Kitsu, change.media_id, change.modified);
+                }
+                for anime in app
+                    .database
+                    .anime_map()
+                    .iter_mut()
+                    .filter(|v| v.kitsu_id() == Some(change.media_id))
+                {
+                    anime.set_synced(Tracker::Kitsu, change.snapshot(), entry.snapshot());
+                }
+            }
+            HttpData::MediaDetails(details) => {
+                app.database.update_details(&details);
+            }
+            HttpData::AiringSchedules(schedules) => {
+                app.database.update_airing(&schedules);
+            }
             HttpData::Debug(v) => {
                 dbg!(v);
             }
         }
+        true
+    } else {
+        false
     }
 }
@@ -0,0 +1,258 @@
+use serde::{Deserialize, Serialize};
+
+use crate::database::list_entry::{
+    parse_timestamp, EntrySnapshot, FuzzyDate, ListEntry, ListStatus,
+};
+
+/// Response of the token endpoint, for both the password and refresh grants.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KitsuToken {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Seconds until `access_token` expires
+    pub expires_in: u64,
+}
+
+/// JSON:API document with a list of resources, ie: `GET /library-entries`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Document<T> {
+    pub data: Vec<T>,
+    #[serde(default)]
+    pub links: Links,
+}
+
+/// JSON:API document with a single resource, ie: the response of `PATCH /library-entries/{id}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SingleDocument<T> {
+    pub data: T,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Links {
+    pub next: Option<String>,
+}
+
+/// Error document, ie: `{"errors": [{"title": "Invalid token", "status": "401"}]}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KitsuErrors {
+    pub errors: Vec<KitsuError>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KitsuError {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+/// Error body of the token endpoint, ie: `{"error": "invalid_grant"}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KitsuAuthError {
+    pub error: String,
+    #[serde(default)]
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KitsuUser {
+    /// Ids are sent as strings
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LibraryEntry {
+    pub id: String,
+    attributes: LibraryEntryAttributes,
+    #[serde(default)]
+    relationships: Option<LibraryEntryRelationships>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct LibraryEntryRelationships {
+    anime: Relationship,
+}
+
+/// `data` is only sent when the resource is included, ie: `include=anime`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Relationship {
+    #[serde(default)]
+    data: Option<ResourceIdentifier>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ResourceIdentifier {
+    id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KitsuStatus {
+    Current,
+    Planned,
+    Completed,
+    OnHold,
+    Dropped,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct LibraryEntryAttributes {
+    #[serde(default)]
+    status: Option<KitsuStatus>,
+    #[serde(default)]
+    progress: u32,
+    #[serde(default)]
+    reconsuming: bool,
+    #[serde(default)]
+    reconsume_count: u32,
+    /// Out of 20 in steps of 1 (2 is the lowest), `None` when not rated
+    #[serde(default)]
+    rating_twenty: Option<u32>,
+    #[serde(default)]
+    started_at: Option<String>,
+    #[serde(default)]
+    finished_at: Option<String>,
+    /// ie: "2017-11-11T19:51:22.123Z"
+    updated_at: String,
+}
+
+impl LibraryEntry {
+    /// Id of the anime, `None` unless the anime was included.
+    pub fn anime_id(&self) -> Option<u32> {
+        self.relationships
+            .as_ref()?
+            .anime
+            .data
+            .as_ref()?
+            .id
+            .parse()
+            .ok()
+    }
+
+    pub fn progress(&self) -> u32 {
+        self.attributes.progress
+    }
+
+    pub fn updated_at(&self) -> u64 {
+        parse_timestamp(&self.attributes.updated_at).unwrap_or(0)
+    }
+
+    pub fn list_entry(&self) -> ListEntry {
+        let attributes = &self.attributes;
+        // Only the date is taken, as it is the day the user picked
+        let parse_date = |date: &Option<String>| {
+            date.as_deref()
+                .and_then(|v| v.split('T').next()?.parse().ok())
+                .unwrap_or_default()
+        };
+        ListEntry {
+            status: attributes.status.map(|status| match status {
+                KitsuStatus::Current | KitsuStatus::Completed if attributes.reconsuming => {
+                    ListStatus::Repeating
+                }
+                KitsuStatus::Current => ListStatus::Current,
+                KitsuStatus::Planned => ListStatus::Planning,
+                KitsuStatus::Completed => ListStatus::Completed,
+                KitsuStatus::OnHold => ListStatus::Paused,
+                KitsuStatus::Dropped => ListStatus::Dropped,
+            }),
+            score: attributes.rating_twenty.unwrap_or(0) * 5,
+            started_at: parse_date(&attributes.started_at),
+            completed_at: parse_date(&attributes.finished_at),
+            repeat: attributes.reconsume_count,
+            notes: String::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> EntrySnapshot {
+        EntrySnapshot {
+            progress: self.attributes.progress,
+            entry: self.list_entry(),
+        }
+    }
+}
+
+/// Attributes of a library entry to create or update. Unset status and dates are left out so
+/// they are left unchanged, like with AniList. Score and rewatches are always sent, a null
+/// rating clears it.
+pub fn library_entry_attributes(progress: u32, entry: &ListEntry) -> serde_json::Value {
+    let mut attributes = serde_json::json!({ "progress": progress });
+    if let Some(status) = entry.status {
+        // Kitsu keeps rewatches as current
+        let (status, reconsuming) = match status {
+            ListStatus::Current => (KitsuStatus::Current, false),
+            ListStatus::Planning => (KitsuStatus::Planned, false),
+            ListStatus::Completed => (KitsuStatus::Completed, false),
+            ListStatus::Dropped => (KitsuStatus::Dropped, false),
+            ListStatus::Paused => (KitsuStatus::OnHold, false),
+            ListStatus::Repeating => (KitsuStatus::Current, true),
+        };
+        attributes["status"] = serde_json::json!(status);
+        attributes["reconsuming"] = serde_json::json!(reconsuming);
+    }
+    attributes["ratingTwenty"] = match entry.score {
+        0 => serde_json::Value::Null,
+        score => serde_json::json!(((score + 2) / 5).clamp(2, 20)),
+    };
+    // Kitsu only takes full dates
+    let timestamp = |date: &FuzzyDate| date.to_unix().map(|_| format!("{date}T00:00:00.000Z"));
+    if let Some(started_at) = timestamp(&entry.started_at) {
+        attributes["startedAt"] = serde_json::json!(started_at);
+    }
+    if let Some(finished_at) = timestamp(&entry.completed_at) {
+        attributes["finishedAt"] = serde_json::json!(finished_at);
+    }
+    attributes["reconsumeCount"] = serde_json::json!(entry.repeat);
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn library_entry_test() {
+        let json = r#"{
+            "id": "42",
+            "type": "libraryEntries",
+            "attributes": {
+                "status": "current",
+                "progress": 5,
+                "reconsuming": false,
+                "reconsumeCount": 0,
+                "ratingTwenty": 17,
+                "startedAt": "2017-11-01T00:00:00.000Z",
+                "finishedAt": null,
+                "updatedAt": "2017-11-11T19:51:22.123Z"
+            },
+            "relationships": {"anime": {"data": {"type": "anime", "id": "7442"}}}
+        }"#;
+        let library_entry = serde_json::from_str::<LibraryEntry>(json).unwrap();
+        assert_eq!(Some(7442), library_entry.anime_id());
+        assert_eq!(1510429882, library_entry.updated_at());
+        let entry = library_entry.list_entry();
+        assert_eq!(Some(ListStatus::Current), entry.status);
+        assert_eq!(85, entry.score);
+        assert_eq!("2017-11-01", entry.started_at.to_string());
+        assert!(entry.completed_at.is_empty());
+
+        let attributes = library_entry_attributes(library_entry.progress(), &entry);
+        assert_eq!(5, attributes["progress"]);
+        assert_eq!("current", attributes["status"]);
+        assert_eq!(17, attributes["ratingTwenty"]);
+        assert_eq!("2017-11-01T00:00:00.000Z", attributes["startedAt"]);
+        assert!(attributes.get("finishedAt").is_none());
+
+        let repeating = ListEntry {
+            status: Some(ListStatus::Repeating),
+            ..Default::default()
+        };
+        let attributes = library_entry_attributes(1, &repeating);
+        assert_eq!("current", attributes["status"]);
+        assert_eq!(true, attributes["reconsuming"]);
+        // Score and rewatches were cleared
+        assert_eq!(serde_json::Value::Null, attributes["ratingTwenty"]);
+        assert_eq!(0, attributes["reconsumeCount"]);
+    }
+}
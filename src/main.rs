@@ -5,14 +5,23 @@ mod anilist_serde;
 mod config;
 mod database;
 mod http;
+mod kitsu_serde;
+mod mal_serde;
+mod player;
 mod ui;
 
+use anilist_serde::AniListError;
 use config::Config;
+use database::credentials::CredentialStore;
 use database::episode::Episode;
+use database::episode_thumbnail::EpisodeThumbnails;
 use database::json_database::AnimeDatabaseData;
-use database::{Anime, Database};
+use database::list_import::{ImportReport, ImportedEntry};
+use database::sync_queue::{SyncQueue, Tracker};
+use database::{Anime, Database, FranchiseEntry};
 use http::{HttpData, HttpSender};
 use lexopt::prelude::*;
+use player::{Player, Watched};
 use regex::Regex;
 use sdl2::clipboard::ClipboardUtil;
 use sdl2::keyboard::TextInputUtil;
@@ -33,7 +42,6 @@ use std::collections::{BTreeSet, HashSet};
 use std::fs;
 use std::ops::Sub;
 use std::path::Path;
-use std::process::Command;
 use std::sync::mpsc;
 use std::time::Duration;
 use ui::Screen;
@@ -42,9 +50,12 @@ use ui::TextureManager;
 use ui::WINDOW_HEIGHT;
 use ui::WINDOW_WIDTH;
 use ui::{color_hex, draw, BACKGROUND_COLOR};
-use ui::{update_anilist_watched, FontManager};
+use ui::{update_trackers_watched, FontManager};
 
-use crate::http::{get_anilist_media_list, poll_http, send_login, send_request, RequestKind};
+use crate::http::{
+    flush_sync_queue, get_airing_schedules, get_anilist_media_list, get_kitsu_library,
+    get_mal_list, poll_http, refresh_kitsu_token, refresh_mal_token, send_login,
+};
 use crate::ui::layout::Layout;
 use crate::ui::{INPUT_BOX_FONT_INFO, SCROLLBAR_COLOR};
 
@@ -57,6 +68,7 @@ const RESIZED: u8          = 1 << 5;
 const ID_UPDATED: u8       = 1 << 6;
 
 pub const CONNECTION_OVERLAY_TIMEOUT: f32 = 170.0;
+pub const ERROR_OVERLAY_TIMEOUT: f32 = 400.0;
 pub const DEFAULT_VIDEO_PLAYER: &str = "mpv";
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
@@ -111,6 +123,13 @@ pub enum ConnectionOverlayState {
     Disconnected,
 }
 
+/// Tells what went wrong with an AniList request, drawn like `ConnectionOverlay`.
+#[derive(Debug, Default)]
+pub struct ErrorOverlay {
+    timeout: f32,
+    error: Option<AniListError>,
+}
+
 #[derive(Debug, Default)]
 pub struct SingleFlag {
     switch: Switch,
@@ -142,7 +161,13 @@ pub struct AttachFlagState {
 #[derive(Debug, Default)]
 pub struct EpisodeState {
     episode_scroll: Scroll,
+    franchise_scroll: Scroll,
+    franchise: Option<(usize, Box<[FranchiseEntry]>)>,
+    /// AniList ids whose details have been requested this session
+    details_requested: BTreeSet<u32>,
     selectable: BTreeSet<usize>,
+    /// Notes being edited and the index of their anime
+    notes: Option<(usize, Textbox)>,
 }
 
 #[derive(Debug, Default)]
@@ -163,6 +188,22 @@ pub struct TitlePopupState {
 pub struct LoginState {
     selectable: BTreeSet<usize>,
     textbox: Textbox,
+    /// Kitsu logs in with a password rather than a token
+    password: Textbox,
+    tracker: Tracker,
+    /// PKCE code verifier of the MyAnimeList authorization in progress
+    code_verifier: String,
+}
+
+#[derive(Debug, Default)]
+pub struct AiringState {
+    scroll: Scroll,
+}
+
+#[derive(Debug, Default)]
+pub struct UnlockState {
+    passphrase: Textbox,
+    failed: bool,
 }
 
 #[derive(Debug, Default)]
@@ -175,7 +216,19 @@ pub struct MainState {
     pub keyboard_override: bool,
     pub search_anime: Option<u32>,
     pub alias_anime: Option<u32>,
+    pub thumbnail_anime: Option<u32>,
     pub search_previous: Option<(String, Box<[*const AnimeDatabaseData]>)>,
+    pub show_tags: bool,
+    pub tag_scroll: Scroll,
+    pub show_conflicts: bool,
+    pub conflict_scroll: Scroll,
+    /// Entries of a list export and what importing them would do, until the user confirms
+    pub list_import: Option<(Vec<ImportedEntry>, ImportReport)>,
+    pub list_import_scroll: Scroll,
+    /// Filters the library by title, folder name and notes while open
+    library_search: Option<Textbox>,
+    /// Profile menu while open, with the name of a new profile
+    profiles: Option<Textbox>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -193,6 +246,8 @@ struct Textbox {
     history_time: f32,
     cursor_location: usize,
     view_offset: i32,
+    /// Draws the text masked, for passwords
+    hidden: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -263,7 +318,13 @@ pub struct App<'a, 'b> {
     screen: Screen,
 
     pub thumbnail_path: String,
+    pub episode_thumbnails: EpisodeThumbnails,
+    pub player: Player,
+    pub mal_client_id: Option<String>,
+    pub anilist_client_id: u64,
+    pub anilist_redirect_uri: Option<String>,
     pub database: Database<'a>,
+    pub credentials: CredentialStore,
     pub running: bool,
     pub show_toolbar: bool,
     pub frametime: std::time::Duration,
@@ -272,27 +333,25 @@ pub struct App<'a, 'b> {
     pub http_tx: HttpSender,
 
     pub connection_overlay: ConnectionOverlay,
+    pub error_overlay: ErrorOverlay,
     pub login_progress: LoginProgress,
 
     pub main_state: MainState,
     pub episode_state: EpisodeState,
     pub login_state: LoginState,
+    pub unlock_state: UnlockState,
     pub attach_flag_state: AttachFlagState,
+    pub airing_state: AiringState,
 
     pub alias_popup_state: AliasPopupState,
     pub title_popup_state: TitlePopupState,
 }
 
 //pub fn update_watched(app: &mut App, anime: &mut Anime, ep: &Episode) {
-pub fn update_watched(
-    tx: &HttpSender,
-    access_token: Option<String>,
-    anime: &mut Anime,
-    ep: &Episode,
-) {
+pub fn update_watched(sync_queue: Option<&mut SyncQueue>, anime: &mut Anime, ep: &Episode) {
     anime.update_watched(ep.clone()).unwrap();
-    if let Some(access_token) = access_token {
-        update_anilist_watched(tx, &access_token, anime);
+    if let Some(sync_queue) = sync_queue {
+        update_trackers_watched(sync_queue, anime);
     }
 }
 
@@ -414,15 +473,50 @@ fn get_video_args(chosen_path: &str, anime: &Anime) -> Vec<String> {
     return dbg!(args);
 }
 
-fn open_video(path: &str, anime: &Anime) {
+/// Opens `episode` in the anime's video player. Unless `mark_watched` is false, it is marked
+/// as watched once played past the threshold. Returns whether it was marked straight away, as
+/// playback can only be followed in mpv.
+pub fn play_episode(
+    player: &mut Player,
+    sync_queue: Option<&mut SyncQueue>,
+    anime: &mut Anime,
+    episode: &Episode,
+    mark_watched: bool,
+) -> bool {
+    let Some(path) = anime.find_episode_path(episode).first() else {
+        return false;
+    };
     let video_player = anime
         .video_player
         .clone()
         .unwrap_or(DEFAULT_VIDEO_PLAYER.to_string());
     let args = get_video_args(path, anime);
-    tokio::task::spawn(async move {
-        Command::new(&video_player).args(&args).spawn().unwrap().wait().unwrap();
+    let watched = mark_watched.then(|| Watched {
+        anime: anime.filename().to_string(),
+        episode: episode.clone(),
     });
+    if player.play(video_player, args, watched) || !mark_watched {
+        return false;
+    }
+    update_watched(sync_queue, anime, episode);
+    true
+}
+
+/// Marks episodes that were played far enough as watched. Returns whether any were.
+fn poll_playback(app: &mut App) -> bool {
+    let mut any = false;
+    for Watched { anime, episode } in app.player.poll() {
+        let sync_queue = app.database.sync_queue();
+        if let Some(anime) = app
+            .database
+            .get_anime(anime)
+            .filter(|v| v.has_episode(&episode))
+        {
+            update_watched(sync_queue, anime, &episode);
+            any = true;
+        }
+    }
+    any
 }
 
 fn textbox(
@@ -488,18 +582,20 @@ fn textbox(
 
     let mut cursor_offset = 0;
     if !textbox_state.text.is_empty() {
-        let font_texture =
-            context
-                .text_manager
-                .load(&textbox_state.text, font_info, text_color, None);
-        let TextureQuery { width, height, .. } = font_texture.query();
         debug_assert!(textbox_state.cursor_location <= textbox_state.text.len());
+        let mut text = textbox_state.text.clone();
+        let mut cursor_text_len = textbox_state.cursor_location;
+        if textbox_state.hidden {
+            cursor_text_len = text[0..cursor_text_len].chars().count();
+            text = "*".repeat(text.chars().count());
+        }
+        let font_texture = context
+            .text_manager
+            .load(&text, font_info, text_color, None);
+        let TextureQuery { width, height, .. } = font_texture.query();
         cursor_offset = context
             .text_manager
-            .text_size(
-                font_info,
-                &textbox_state.text[0..textbox_state.cursor_location],
-            )
+            .text_size(font_info, &text[0..cursor_text_len])
             .0 as i32;
         if cursor_offset < -textbox_state.view_offset {
             textbox_state.view_offset += -textbox_state.view_offset - cursor_offset;
@@ -648,35 +744,215 @@ fn textbox(
     false
 }
 
-impl<'a> App<'a, '_> {
+/// Byte ranges of the lines `text` is drawn as, split at newlines then wrapped at spaces to fit
+/// `width`. Words wider than a line are clipped.
+fn wrap_lines(
+    text_manager: &mut TextManager,
+    font_info: (&'static str, u16),
+    text: &str,
+    width: u32,
+) -> Vec<(usize, usize)> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for paragraph in text.split('\n') {
+        let end = start + paragraph.len();
+        let mut line_start = start;
+        let mut fits = start;
+        let word_ends = paragraph
+            .match_indices(' ')
+            .map(|(idx, _)| start + idx + 1)
+            .chain(std::iter::once(end));
+        for word_end in word_ends {
+            let (line_width, _) = text_manager.text_size(font_info, &text[line_start..word_end]);
+            if line_width > width && fits > line_start {
+                lines.push((line_start, fits));
+                line_start = fits;
+            }
+            fits = word_end;
+        }
+        lines.push((line_start, end));
+        start = end + 1;
+    }
+    lines
+}
+
+/// Multi-line `textbox` filling `region`. Return starts a new line, so submitting is
+/// Ctrl+Return.
+fn multiline_textbox(context: &mut Context, textbox_state: &mut Textbox, region: Rect) -> bool {
+    context.input_util.start();
+    let font_info = INPUT_BOX_FONT_INFO;
+    let text_color = color_hex(0xB0B0B0);
+    let line_height = context.text_manager.font_height(font_info) as i32;
+    let text_region = region.pad_outer(6, 6);
+
+    textbox_state.id = context.create_id(region);
+    if context.click_elem(textbox_state.id) {
+        context.textbox_id = Some(textbox_state.id);
+        context.text.clear();
+    }
+    context.canvas.set_draw_color(color_hex(0x909090));
+    context.canvas.draw_rect(region).unwrap();
+
+    /// Byte offset `column` chars into `line`, or its end when it is shorter.
+    fn column_in(text: &str, (start, end): (usize, usize), column: usize) -> usize {
+        text[start..end]
+            .char_indices()
+            .nth(column)
+            .map_or(end, |(idx, _)| start + idx)
+    }
+    let line_of = |lines: &[(usize, usize)], cursor: usize| {
+        lines
+            .iter()
+            .rposition(|(start, _)| *start <= cursor)
+            .unwrap_or(0)
+    };
+
+    let focused = context.textbox_id == Some(textbox_state.id);
+    let mut submit = false;
+    if focused {
+        use sdl2::keyboard::Scancode::*;
+        let key = |k| context.keyset.contains(&Keycode::from_scancode(k).unwrap());
+        let ctrl = context.keymod.contains(Mod::LCTRLMOD);
+        let text = &mut textbox_state.text;
+        let cursor = &mut textbox_state.cursor_location;
+        *cursor = (*cursor).min(text.len());
+        for c in context.text.drain(..) {
+            if !ctrl && !context.keymod.contains(Mod::LALTMOD) {
+                text.insert(*cursor, c);
+                *cursor += c.len_utf8();
+            }
+        }
+
+        let lines = wrap_lines(
+            &mut context.text_manager,
+            font_info,
+            text,
+            text_region.width(),
+        );
+        let line = line_of(&lines, *cursor);
+        let (line_start, line_end) = lines[line];
+        // Kept when moving between lines
+        let column = text[line_start..*cursor].chars().count();
+        if ctrl && key(Return) {
+            submit = true;
+        } else if key(Return) {
+            text.insert(*cursor, '\n');
+            *cursor += 1;
+        } else if key(Backspace) {
+            if let Some(c) = text[..*cursor].chars().next_back() {
+                *cursor -= c.len_utf8();
+                text.remove(*cursor);
+            }
+        } else if key(Delete) {
+            if *cursor < text.len() {
+                text.remove(*cursor);
+            }
+        } else if key(Left) {
+            if let Some(c) = text[..*cursor].chars().next_back() {
+                *cursor -= c.len_utf8();
+            }
+        } else if key(Right) {
+            if let Some(c) = text[*cursor..].chars().next() {
+                *cursor += c.len_utf8();
+            }
+        } else if key(Up) {
+            if line > 0 {
+                *cursor = column_in(text, lines[line - 1], column);
+            }
+        } else if key(Down) {
+            if let Some(next) = lines.get(line + 1) {
+                *cursor = column_in(text, *next, column);
+            }
+        } else if key(Home) {
+            *cursor = line_start;
+        } else if key(End) {
+            *cursor = line_end;
+        } else if ctrl && key(V) {
+            match context.clipboard.clipboard_text() {
+                Ok(s) => {
+                    text.insert_str(*cursor, &s);
+                    *cursor += s.len();
+                }
+                Err(e) => {
+                    eprintln!("ERROR:failed to paste:{e}");
+                }
+            };
+        }
+    }
+
+    let text = &textbox_state.text;
+    let cursor = textbox_state.cursor_location.min(text.len());
+    let lines = wrap_lines(
+        &mut context.text_manager,
+        font_info,
+        text,
+        text_region.width(),
+    );
+    // Scrolled in pixels to keep the line with the cursor in view
+    let cursor_line = line_of(&lines, cursor);
+    let cursor_top = cursor_line as i32 * line_height;
+    if cursor_top < textbox_state.view_offset {
+        textbox_state.view_offset = cursor_top;
+    } else if cursor_top + line_height > textbox_state.view_offset + text_region.height() as i32 {
+        textbox_state.view_offset = cursor_top + line_height - text_region.height() as i32;
+    }
+
+    context.canvas.set_clip_rect(text_region);
+    for (idx, (start, end)) in lines.iter().copied().enumerate() {
+        let y = text_region.y + idx as i32 * line_height - textbox_state.view_offset;
+        if start == end || y + line_height < text_region.y || y > text_region.bottom() {
+            continue;
+        }
+        let texture = context
+            .text_manager
+            .load(&text[start..end], font_info, text_color, None);
+        let TextureQuery { width, height, .. } = texture.query();
+        let text_rect = rect!(text_region.x, y, width, height);
+        context.canvas.copy(&texture, None, text_rect).unwrap();
+    }
+    if focused {
+        let (start, _) = lines[cursor_line];
+        let (cursor_offset, _) = context
+            .text_manager
+            .text_size(font_info, &text[start..cursor]);
+        let cursor_rect = rect!(
+            text_region.x + cursor_offset as i32,
+            text_region.y + cursor_top - textbox_state.view_offset,
+            1,
+            line_height
+        );
+        context.canvas.set_draw_color(text_color);
+        context.canvas.fill_rect(cursor_rect).unwrap();
+    }
+    context.canvas.set_clip_rect(None);
+
+    submit
+}
+
+impl<'a, 'b> App<'a, 'b> {
     pub fn new(
-        canvas: Canvas<Window>,
-        clipboard: ClipboardUtil,
+        context: Context<'a, 'b>,
         database: Database<'a>,
-        input_util: TextInputUtil,
-        ttf_ctx: &'a Sdl2TtfContext,
-        texture_creator: &'a TextureCreator<WindowContext>,
+        credentials: CredentialStore,
         thumbnail_path: String,
-        event_pump: EventPump,
     ) -> Self {
         let (http_tx, http_rx) = mpsc::channel();
 
         Self {
-            context: Context::new(
-                canvas,
-                clipboard,
-                input_util,
-                ttf_ctx,
-                texture_creator,
-                event_pump,
-            ),
+            context,
             database,
+            credentials,
             next_screen: None,
             screen: Screen::Main,
             frametime: std::time::Duration::default(),
 
             running: true,
             thumbnail_path,
+            episode_thumbnails: EpisodeThumbnails::default(),
+            player: Player::default(),
+            mal_client_id: None,
+            anilist_client_id: 0,
+            anilist_redirect_uri: None,
 
             show_toolbar: false,
 
@@ -688,13 +964,28 @@ impl<'a> App<'a, '_> {
                 timeout: CONNECTION_OVERLAY_TIMEOUT,
                 state: ConnectionOverlayState::Disconnected,
             },
+            error_overlay: ErrorOverlay::default(),
 
             main_state: MainState::default(),
             episode_state: EpisodeState::default(),
-            login_state: LoginState::default(),
+            login_state: LoginState {
+                password: Textbox {
+                    hidden: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            unlock_state: UnlockState {
+                passphrase: Textbox {
+                    hidden: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
             alias_popup_state: AliasPopupState::default(),
             title_popup_state: TitlePopupState::default(),
             attach_flag_state: AttachFlagState::default(),
+            airing_state: AiringState::default(),
         }
     }
 
@@ -1177,10 +1468,164 @@ fn scroll_func(x: f32) -> f32 {
     x
 }
 
+/// Opens the credential store of the active profile and reads it into `database`. Credentials
+/// still in the database are moved into the store once it is unlocked.
+async fn load_credentials(database: &mut Database<'_>) -> CredentialStore {
+    let credentials = CredentialStore::open(Config::credentials_path(database.profile())).await;
+    if credentials.is_locked() {
+        return credentials;
+    }
+    match credentials.load() {
+        Ok(loaded) => {
+            let legacy = database.take_legacy_credentials();
+            let migrate = !legacy.is_empty();
+            database.credentials_set(loaded.or(legacy));
+            if migrate {
+                if let Err(e) = credentials.save(database.credentials()) {
+                    eprintln!("ERROR:Failed to save credentials:{e}");
+                }
+            }
+        }
+        Err(e) => eprintln!("ERROR:Failed to read credentials:{e}"),
+    }
+    credentials
+}
+
+/// Writes the credentials after logging in or out. They are written once unlocked when there
+/// is no keyring, see `draw_unlock`.
+pub fn save_credentials(app: &mut App) {
+    if app.credentials.is_locked() {
+        return;
+    }
+    if let Err(e) = app.credentials.save(app.database.credentials()) {
+        eprintln!("ERROR:Failed to save credentials:{e}");
+    }
+}
+
+/// Screen to go to once the credential store is opened, asking for the passphrase when there
+/// are credentials to read without a keyring.
+fn credentials_screen(database: &Database, credentials: &CredentialStore) -> Screen {
+    match credentials.is_locked() && (credentials.exists() || database.has_legacy_credentials()) {
+        true => Screen::Unlock,
+        false => start_screen(database),
+    }
+}
+
+/// Switches to profile `name`, creating it when new, and logs in to its trackers.
+pub fn switch_profile(app: &mut App, name: &str) {
+    if name == app.database.profile() {
+        return;
+    }
+    app.database.switch_profile(name);
+    if let Err(e) = app.database.retrieve_images(&app.thumbnail_path) {
+        eprintln!("ERROR:failed to retrieve thumbnails:{e}");
+    }
+    // Responses still on their way are for the previous profile
+    (app.http_tx, app.http_rx) = mpsc::channel();
+    app.player.forget_playing();
+    app.login_progress = LoginProgress::None;
+    app.connection_overlay.state = ConnectionOverlayState::Disconnected;
+    app.credentials = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(load_credentials(&mut app.database))
+    });
+    let screen = credentials_screen(&app.database, &app.credentials);
+    if !matches!(screen, Screen::Unlock) {
+        sync_trackers(app);
+    }
+    get_airing_schedules(&app.http_tx, &app.database.airing_media_ids());
+    app.next_screen = Some(screen);
+}
+
+/// Screen to start on once the credentials are read.
+pub fn start_screen(database: &Database) -> Screen {
+    let anilist_expired = database
+        .anilist_cred()
+        .as_ref()
+        .is_none_or(|v| v.expired(database::get_time()));
+    if !database.skip_login() && anilist_expired {
+        Screen::Login
+    } else {
+        Screen::Main
+    }
+}
+
+/// Fetches the lists of the trackers logged in to, refreshing tokens first.
+pub fn sync_trackers(app: &mut App) {
+    // An expired token is kept until logging in again, so the toolbar can tell
+    if let Some(cred) = app
+        .database
+        .anilist_cred()
+        .as_ref()
+        .filter(|v| !v.expired(database::get_time()))
+    {
+        send_login(&app.http_tx, cred.access_token());
+        get_anilist_media_list(&app.http_tx, cred.user_id(), cred.access_token());
+    }
+    if let Some(cred) = app.database.mal_cred() {
+        // The list is fetched once the token is refreshed
+        match &app.mal_client_id {
+            Some(client_id) if cred.expires_soon(database::get_time()) => {
+                refresh_mal_token(&app.http_tx, client_id, cred.refresh_token());
+            }
+            _ => get_mal_list(&app.http_tx, cred.access_token()),
+        }
+    }
+    if let Some(cred) = app.database.kitsu_cred() {
+        // The library is fetched once the token is refreshed
+        match cred.expires_soon(database::get_time()) {
+            true => refresh_kitsu_token(&app.http_tx, cred.refresh_token()),
+            false => get_kitsu_library(&app.http_tx, cred.user_id(), cred.access_token()),
+        }
+    }
+}
+
+/// `aniki import-list <file> [--dry-run]`
+async fn run_import_list(
+    cfg: &Config,
+    path: impl AsRef<Path>,
+    video_paths: Vec<String>,
+    profile: Option<&str>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let entries = database::list_import::parse_list(&fs::read_to_string(path)?)?;
+    let mut database = Database::new(
+        cfg.database_path().to_string_lossy(),
+        video_paths,
+        cfg.artwork_patterns().to_vec(),
+    )?;
+    if let Some(profile) = profile {
+        database.switch_profile(profile);
+    }
+    // Changes are queued for the trackers logged in to
+    let credentials = load_credentials(&mut database).await;
+    if credentials.is_locked() && (credentials.exists() || database.has_legacy_credentials()) {
+        eprintln!("Credentials are locked, changes are not queued for trackers");
+    }
+    let report = database.import_list(&entries, dry_run);
+    let (matched, total) = (report.matched, entries.len());
+    match dry_run {
+        true => println!("Would import {matched} of {total} entries"),
+        false => println!("Imported {matched} of {total} entries"),
+    }
+    if !report.unmatched.is_empty() {
+        println!("No anime found for:");
+        for entry in report.unmatched.iter() {
+            println!("    {entry}");
+        }
+    }
+    if !dry_run {
+        database.write(cfg.database_path())?;
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     lock_file()?;
     let cfg = Config::parse_cfg();
+    database::normalize::set_romanize_kana(cfg.romanize_kana());
+    http::init(&cfg)?;
 
     let mut avg_time = [0.0; 60];
     let mut frame_num = 0;
@@ -1189,6 +1634,9 @@ async fn main() -> anyhow::Result<()> {
 
     let mut show_fps = false;
     let mut force_vsync = false;
+    let mut import_list = None;
+    let mut dry_run = false;
+    let mut profile = None;
     let mut args_parser = lexopt::Parser::from_env();
 
     while let Some(arg) = args_parser.next()? {
@@ -1199,6 +1647,19 @@ async fn main() -> anyhow::Result<()> {
             Short('F') | Long("force-vsync") => {
                 force_vsync = true;
             }
+            Value(command) if command == "import-list" && import_list.is_none() => {
+                import_list = Some(args_parser.value()?);
+            }
+            Long("dry-run") => {
+                dry_run = true;
+            }
+            Long("profile") => {
+                let name = args_parser.value()?.string()?;
+                if !database::profile::is_valid_name(&name) {
+                    anyhow::bail!("Profile names are letters, digits, '-' and '_'");
+                }
+                profile = Some(name);
+            }
             _ => {
                 anyhow::Result::Err(arg.unexpected())?;
             }
@@ -1213,6 +1674,12 @@ async fn main() -> anyhow::Result<()> {
         .map(|v| v.to_string_lossy().to_string())
         .collect();
 
+    if let Some(path) = import_list {
+        return run_import_list(&cfg, &path, video_paths, profile.as_deref(), dry_run).await;
+    } else if dry_run {
+        anyhow::bail!("--dry-run is only used with import-list");
+    }
+
     let sdl_context = sdl2::init().map_err(|e| anyhow::anyhow!(e))?;
     sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "1");
     sdl2::hint::set("SDL_RENDER_BATCHING", "1");
@@ -1239,34 +1706,44 @@ async fn main() -> anyhow::Result<()> {
     let texture_creator = canvas.texture_creator();
     let ttf_ctx = sdl2::ttf::init()?;
     // TODO: Run this asynchronously and poll in draw loop
-    let mut database = Database::new(database_path, video_paths)?;
+    let mut database = Database::new(
+        database_path,
+        video_paths,
+        cfg.artwork_patterns().to_vec(),
+    )?;
     database.retrieve_images(&thumbnail_path)?;
+    if let Some(profile) = profile {
+        database.switch_profile(&profile);
+    }
 
-    let mut screen = {
-        if !database.skip_login() && database.anilist_cred().is_none() {
-            Screen::Login
-        } else {
-            Screen::Main
-        }
-    };
+    let credentials = load_credentials(&mut database).await;
+    let mut screen = credentials_screen(&database, &credentials);
 
     let event_pump = sdl_context.event_pump().map_err(|e| anyhow::anyhow!(e))?;
 
-    let mut app = App::new(
+    let context = Context::new(
         canvas,
         clipboard,
-        database,
         input_util,
         &ttf_ctx,
         &texture_creator,
-        thumbnail_path.to_string(),
         event_pump,
     );
+    let mut app = App::new(context, database, credentials, thumbnail_path.to_string());
+    app.episode_thumbnails = EpisodeThumbnails::new(
+        cfg.thumbnail_path().join("episodes"),
+        cfg.episode_thumbnail_offset(),
+        cfg.episode_thumbnails(),
+    );
 
-    if let Some(cred) = app.database.anilist_cred() {
-        send_login(&app.http_tx, cred.access_token());
-        get_anilist_media_list(&app.http_tx, cred.user_id(), cred.access_token());
-    }
+    app.player = Player::new(cfg.watched_threshold());
+    app.mal_client_id = cfg.mal_client_id().map(String::from);
+    app.anilist_client_id = cfg.anilist_client_id();
+    app.anilist_redirect_uri = cfg.anilist_redirect_uri().map(String::from);
+
+    sync_trackers(&mut app);
+    // Schedules are public, so they are fetched without logging in
+    get_airing_schedules(&app.http_tx, &app.database.airing_media_ids());
 
     enum CanvasTexture<'a> {
         Cached(Texture<'a>),
@@ -1295,13 +1772,21 @@ async fn main() -> anyhow::Result<()> {
             _ => (),
         }
 
+        if app.database.poll_thumbnails()
+            | app.episode_thumbnails.poll()
+            | poll_http(&mut app)
+            | poll_playback(&mut app)
+        {
+            canvas_texture = CanvasTexture::Wait(IDLE_TIME);
+        }
+        flush_sync_queue(&mut app);
+
         match canvas_texture {
             CanvasTexture::Cached(ref texture) => {
                 app.context.canvas.copy(texture, None, None).unwrap();
             }
             CanvasTexture::Wait(ref mut t) => {
                 *t = t.sub(app.frametime_frac()).max(0.0);
-                poll_http(&mut app);
 
                 app.context
                     .canvas
@@ -1309,7 +1794,10 @@ async fn main() -> anyhow::Result<()> {
                 app.context.canvas.clear();
 
                 draw(&mut app, &mut screen);
-                if *t <= 0.0 && app.connection_overlay.timeout <= 0.0 {
+                if *t <= 0.0
+                    && app.connection_overlay.timeout <= 0.0
+                    && app.error_overlay.timeout <= 0.0
+                {
                     let (width, height) = app.context.canvas.window().size();
                     let pixel_format = app.context.canvas.default_pixel_format();
                     let pitch = pixel_format.byte_size_per_pixel() * width as usize;
@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+use crate::database::list_entry::{parse_timestamp, EntrySnapshot, ListEntry, ListStatus};
+
+/// Response of the token endpoint, for both the authorization code and refresh grants.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MalToken {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Seconds until `access_token` expires
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MalUser {
+    pub id: u64,
+    pub name: String,
+}
+
+/// Error body of the REST API, ie: `{"error": "invalid_token"}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MalError {
+    pub error: String,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// A page of `GET /users/@me/animelist`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnimeListPage {
+    pub data: Vec<MalEntry>,
+    #[serde(default)]
+    pub paging: Paging,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Paging {
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MalEntry {
+    node: MalAnime,
+    list_status: MalListStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct MalAnime {
+    id: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MalStatus {
+    Watching,
+    Completed,
+    OnHold,
+    Dropped,
+    PlanToWatch,
+}
+
+/// List entry as MyAnimeList has it, also the response of `PATCH /anime/{id}/my_list_status`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MalListStatus {
+    #[serde(default)]
+    status: Option<MalStatus>,
+    /// Out of 10
+    #[serde(default)]
+    score: u32,
+    #[serde(default)]
+    num_episodes_watched: u32,
+    #[serde(default)]
+    is_rewatching: bool,
+    #[serde(default)]
+    start_date: Option<String>,
+    #[serde(default)]
+    finish_date: Option<String>,
+    #[serde(default)]
+    num_times_rewatched: u32,
+    /// ie: "2017-11-11T19:51:22+00:00"
+    updated_at: String,
+}
+
+impl MalEntry {
+    pub fn id(&self) -> u32 {
+        self.node.id
+    }
+
+    pub fn list_status(&self) -> &MalListStatus {
+        &self.list_status
+    }
+}
+
+impl MalListStatus {
+    pub fn progress(&self) -> u32 {
+        self.num_episodes_watched
+    }
+
+    pub fn updated_at(&self) -> u64 {
+        parse_timestamp(&self.updated_at).unwrap_or(0)
+    }
+
+    pub fn list_entry(&self) -> ListEntry {
+        let parse_date = |date: &Option<String>| {
+            date.as_deref()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default()
+        };
+        ListEntry {
+            status: self.status.map(|status| match status {
+                MalStatus::Watching | MalStatus::Completed if self.is_rewatching => {
+                    ListStatus::Repeating
+                }
+                MalStatus::Watching => ListStatus::Current,
+                MalStatus::Completed => ListStatus::Completed,
+                MalStatus::OnHold => ListStatus::Paused,
+                MalStatus::Dropped => ListStatus::Dropped,
+                MalStatus::PlanToWatch => ListStatus::Planning,
+            }),
+            score: self.score * 10,
+            started_at: parse_date(&self.start_date),
+            completed_at: parse_date(&self.finish_date),
+            repeat: self.num_times_rewatched,
+            notes: String::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> EntrySnapshot {
+        EntrySnapshot {
+            progress: self.num_episodes_watched,
+            entry: self.list_entry(),
+        }
+    }
+}
+
+/// Form fields of `PATCH /anime/{id}/my_list_status`. Unset status and dates are left out so
+/// they are left unchanged, like with AniList. Score and rewatches are always sent, 0 clears
+/// them.
+pub fn list_status_form(progress: u32, entry: &ListEntry) -> Vec<(&'static str, String)> {
+    let mut form = vec![("num_watched_episodes", progress.to_string())];
+    if let Some(status) = entry.status {
+        // MyAnimeList keeps rewatches as completed
+        let (status, is_rewatching) = match status {
+            ListStatus::Current => ("watching", false),
+            ListStatus::Planning => ("plan_to_watch", false),
+            ListStatus::Completed => ("completed", false),
+            ListStatus::Dropped => ("dropped", false),
+            ListStatus::Paused => ("on_hold", false),
+            ListStatus::Repeating => ("completed", true),
+        };
+        form.push(("status", status.to_string()));
+        form.push(("is_rewatching", is_rewatching.to_string()));
+    }
+    let score = match entry.score {
+        0 => 0,
+        score => ((score + 5) / 10).clamp(1, 10),
+    };
+    form.push(("score", score.to_string()));
+    if !entry.started_at.is_empty() {
+        form.push(("start_date", entry.started_at.to_string()));
+    }
+    if !entry.completed_at.is_empty() {
+        form.push(("finish_date", entry.completed_at.to_string()));
+    }
+    form.push(("num_times_rewatched", entry.repeat.to_string()));
+    form
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_status_test() {
+        let json = r#"{
+            "status": "completed",
+            "score": 8,
+            "num_episodes_watched": 12,
+            "is_rewatching": true,
+            "start_date": "2017-11-01",
+            "finish_date": "2017-11",
+            "num_times_rewatched": 1,
+            "updated_at": "2017-11-11T19:51:22+00:00"
+        }"#;
+        let status = serde_json::from_str::<MalListStatus>(json).unwrap();
+        let entry = status.list_entry();
+        assert_eq!(Some(ListStatus::Repeating), entry.status);
+        assert_eq!(80, entry.score);
+        assert_eq!("2017-11-01", entry.started_at.to_string());
+        assert_eq!("2017-11", entry.completed_at.to_string());
+        assert_eq!(12, status.progress());
+
+        let form = list_status_form(status.progress(), &entry);
+        assert!(form.contains(&("status", "completed".to_string())));
+        assert!(form.contains(&("is_rewatching", "true".to_string())));
+        assert!(form.contains(&("score", "8".to_string())));
+        assert!(form.contains(&("finish_date", "2017-11".to_string())));
+
+        // Score and rewatches were cleared
+        let form = list_status_form(3, &ListEntry::default());
+        assert_eq!(
+            vec![
+                ("num_watched_episodes", "3".to_string()),
+                ("score", "0".to_string()),
+                ("num_times_rewatched", "0".to_string()),
+            ],
+            form
+        );
+    }
+}
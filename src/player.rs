@@ -0,0 +1,242 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::process::Command;
+
+use crate::database::episode::Episode;
+
+/// mpv creates the socket shortly after starting
+const CONNECT_ATTEMPTS: u32 = 50;
+const CONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+const OBSERVED_PROPERTIES: [&str; 3] = ["time-pos", "duration", "eof-reached"];
+
+/// Episode that was played past the watched threshold.
+#[derive(Debug, Clone)]
+pub struct Watched {
+    /// `Anime::filename`
+    pub anime: String,
+    pub episode: Episode,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Event {
+    PropertyChange {
+        name: String,
+        #[serde(default)]
+        data: serde_json::Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// How far mpv got, from the properties it reports.
+#[derive(Debug, Default, PartialEq)]
+struct Progress {
+    time_pos: Option<f64>,
+    duration: Option<f64>,
+    eof_reached: bool,
+}
+
+impl Progress {
+    /// Takes in a line read from the IPC socket. Replies to commands and other events are
+    /// ignored.
+    fn update(&mut self, line: &str) {
+        let Ok(Event::PropertyChange { name, data }) = serde_json::from_str(line) else {
+            return;
+        };
+        match name.as_str() {
+            "time-pos" => self.time_pos = data.as_f64(),
+            "duration" => self.duration = data.as_f64(),
+            "eof-reached" => self.eof_reached = data.as_bool().unwrap_or(false),
+            _ => (),
+        }
+    }
+
+    /// Whether `threshold` percent of the episode was played.
+    fn reached(&self, threshold: u64) -> bool {
+        if self.eof_reached {
+            return true;
+        }
+        match (self.time_pos, self.duration) {
+            (Some(time_pos), Some(duration)) if duration > 0.0 => {
+                time_pos / duration * 100.0 >= threshold as f64
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Opens episodes in the video player. With mpv, playback is followed through its IPC socket
+/// so an episode only counts as watched once enough of it was played.
+#[derive(Debug)]
+pub struct Player {
+    threshold: u64,
+    tx: mpsc::Sender<Watched>,
+    rx: mpsc::Receiver<Watched>,
+    sockets: u64,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Player {
+    /// `threshold` is in percent, 0 to count episodes as watched once opened.
+    pub fn new(threshold: u64) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            threshold,
+            tx,
+            rx,
+            sockets: 0,
+        }
+    }
+
+    /// Opens `video_player` with `args`. Returns whether `watched` is sent to `poll` once it is
+    /// played past the threshold, otherwise it is up to the caller to mark it as watched.
+    pub fn play(
+        &mut self,
+        video_player: String,
+        mut args: Vec<String>,
+        watched: Option<Watched>,
+    ) -> bool {
+        let watched = match watched {
+            Some(v) if self.threshold > 0 && is_mpv(&video_player) => v,
+            _ => {
+                tokio::spawn(async move {
+                    if let Err(e) = run(&video_player, &args).await {
+                        eprintln!("ERROR:{e:#}");
+                    }
+                });
+                return false;
+            }
+        };
+
+        self.sockets += 1;
+        let socket = socket_path(self.sockets);
+        args.push(format!("--input-ipc-server={}", socket.display()));
+        let (tx, threshold) = (self.tx.clone(), self.threshold);
+        tokio::spawn(async move {
+            let mut child = match Command::new(&video_player).args(&args).spawn() {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("ERROR:Failed to run {video_player}:{e}");
+                    return;
+                }
+            };
+            match follow(&socket, threshold).await {
+                Ok(true) => {
+                    let _ = tx.send(watched);
+                }
+                Ok(false) => (),
+                Err(e) => eprintln!("ERROR:Failed to follow playback:{e:#}"),
+            }
+            let _ = child.wait().await;
+            let _ = std::fs::remove_file(&socket);
+        });
+        true
+    }
+
+    /// Episodes played past the threshold since the last call.
+    pub fn poll(&mut self) -> Vec<Watched> {
+        self.rx.try_iter().collect()
+    }
+
+    /// Stops marking the episodes being played as watched, ie: after switching profiles.
+    pub fn forget_playing(&mut self) {
+        (self.tx, self.rx) = mpsc::channel();
+    }
+}
+
+fn is_mpv(video_player: &str) -> bool {
+    Path::new(video_player)
+        .file_stem()
+        .is_some_and(|v| v == "mpv")
+}
+
+fn socket_path(n: u64) -> PathBuf {
+    std::env::temp_dir().join(format!("aniki-mpv-{}-{n}.sock", std::process::id()))
+}
+
+async fn run(video_player: &str, args: &[String]) -> anyhow::Result<()> {
+    Command::new(video_player)
+        .args(args)
+        .spawn()
+        .with_context(|| format!("Failed to run {video_player}"))?
+        .wait()
+        .await?;
+    Ok(())
+}
+
+/// Follows playback until `threshold` percent was played, returning `false` if mpv quit
+/// before.
+async fn follow(socket: &Path, threshold: u64) -> anyhow::Result<bool> {
+    let mut attempts = 0;
+    let stream = loop {
+        match UnixStream::connect(socket).await {
+            Ok(v) => break v,
+            Err(_) if attempts < CONNECT_ATTEMPTS => {
+                attempts += 1;
+                tokio::time::sleep(CONNECT_INTERVAL).await;
+            }
+            Err(e) => return Err(e).context("Failed to connect to mpv"),
+        }
+    };
+    let (reader, mut writer) = stream.into_split();
+    for (id, property) in OBSERVED_PROPERTIES.iter().enumerate() {
+        let command = serde_json::json!({ "command": ["observe_property", id + 1, property] });
+        writer.write_all(format!("{command}\n").as_bytes()).await?;
+    }
+
+    let mut progress = Progress::default();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        progress.update(&line);
+        if progress.reached(threshold) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_mpv, Progress};
+
+    #[test]
+    fn progress_test() {
+        let mut progress = Progress::default();
+        progress.update(r#"{"request_id":0,"error":"success"}"#);
+        progress.update(r#"{"event":"property-change","id":2,"name":"duration"}"#);
+        progress.update(r#"{"event":"file-loaded"}"#);
+        assert_eq!(Progress::default(), progress);
+        assert!(!progress.reached(85));
+
+        progress.update(r#"{"event":"property-change","id":2,"name":"duration","data":1420.0}"#);
+        progress.update(r#"{"event":"property-change","id":1,"name":"time-pos","data":600.5}"#);
+        assert!(!progress.reached(85));
+        progress.update(r#"{"event":"property-change","id":1,"name":"time-pos","data":1300.0}"#);
+        assert!(progress.reached(85));
+
+        let mut progress = Progress::default();
+        progress.update(r#"{"event":"property-change","id":3,"name":"eof-reached","data":true}"#);
+        assert!(progress.reached(85));
+    }
+
+    #[test]
+    fn is_mpv_test() {
+        assert!(is_mpv("mpv"));
+        assert!(is_mpv("/usr/bin/mpv"));
+        assert!(!is_mpv("vlc"));
+        assert!(!is_mpv("mpvpaper"));
+    }
+}
@@ -0,0 +1,198 @@
+use chrono::{Days, Local, NaiveDate, TimeZone};
+use sdl2::keyboard::Keycode;
+use sdl2::rect::Rect;
+
+use crate::database::{self, AnimeMapIdx};
+use crate::{register_scroll, App};
+
+use super::episode_screen::{DESCRIPTION_FONT_COLOR, DESCRIPTION_FONT_INFO};
+use super::layout::Layout;
+use super::{color_hex, draw_back_button, draw_text, Screen, H1_FONT_INFO, H2_FONT_INFO};
+
+const AIRING_DAYS: u64 = 7;
+const AIRING_X_PAD_OUTER: u32 = 10;
+const AIRING_Y_PAD_OUTER: u32 = 10;
+const AIRING_INFO_FONT_COLOR: u32 = 0x707070;
+/// Of episodes that aired and are not on disk yet
+const AIRING_MISSING_FONT_COLOR: u32 = 0xBF7F7F;
+
+/// Time until an episode airs, ie: "2d 4h", "5h 12m" or "12m".
+pub fn format_countdown(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes.max(1)),
+        (0, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+/// Local midnight starting `date`, as unix time.
+fn day_start(date: NaiveDate) -> Option<u64> {
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    let midnight = Local.from_local_datetime(&midnight).earliest()?;
+    u64::try_from(midnight.timestamp()).ok()
+}
+
+fn day_header(today: NaiveDate, day: u64) -> String {
+    let date = today + Days::new(day);
+    match day {
+        0 => format!("Today {}", date.format("%-d")),
+        1 => format!("Tomorrow {}", date.format("%-d")),
+        _ => date.format("%A %-d").to_string(),
+    }
+}
+
+/// Episodes of the anime being watched that air this week, a column per day starting today.
+pub fn draw_airing(app: &mut App, layout: Rect) {
+    if app.keydown(Keycode::Escape) {
+        app.next_screen = Some(Screen::Main);
+    }
+
+    let layout = layout.pad_outer(AIRING_X_PAD_OUTER, AIRING_Y_PAD_OUTER);
+    let (_, h1_height) = app.context.text_manager.text_size(H1_FONT_INFO, "L");
+    let (header_layout, body_layout) = layout.split_hori(h1_height * 2, layout.height());
+    let (back_button_layout, title_layout) = header_layout.split_vert(1, 10);
+    draw_back_button(app, Screen::Main, back_button_layout.pad_right(5));
+    draw_text(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        H1_FONT_INFO,
+        "Airing this week",
+        color_hex(DESCRIPTION_FONT_COLOR),
+        title_layout.x + 10,
+        title_layout.y,
+        None,
+        None,
+    );
+
+    let time = database::get_time();
+    let today = Local::now().date_naive();
+    let start = day_start(today).unwrap_or(time);
+    let end = day_start(today + Days::new(AIRING_DAYS)).unwrap_or(start + AIRING_DAYS * 86400);
+    let airings = app.database.airing_between(start, end);
+
+    let (_, h2_height) = app.context.text_manager.text_size(H2_FONT_INFO, "L");
+    let (_, font_height) = app
+        .context
+        .text_manager
+        .text_size(DESCRIPTION_FONT_INFO, "L");
+    let entry_height = font_height * 3;
+    let (days_layout, mut list_layout) =
+        body_layout.split_hori(h2_height * 2, body_layout.height());
+    register_scroll(
+        &mut app.context,
+        &mut app.airing_state.scroll,
+        &mut list_layout,
+    );
+    let scroll = app.airing_state.scroll.scroll;
+    let column_width = list_layout.width() / AIRING_DAYS as u32;
+
+    let mut max_entries = 0;
+    for day in 0..AIRING_DAYS {
+        let x = list_layout.x + (column_width * day as u32) as i32;
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            H2_FONT_INFO,
+            day_header(today, day),
+            color_hex(DESCRIPTION_FONT_COLOR),
+            x + 8,
+            days_layout.y,
+            None,
+            None,
+        );
+
+        let from = day_start(today + Days::new(day)).unwrap_or(start);
+        let until = day_start(today + Days::new(day + 1)).unwrap_or(end);
+        let day_airings = airings
+            .iter()
+            .filter(|(_, v)| (from..until).contains(&v.airing_at))
+            .copied()
+            .collect::<Vec<(AnimeMapIdx, _)>>();
+        max_entries = max_entries.max(day_airings.len());
+
+        let column_layout = Rect::new(x, list_layout.y, column_width, list_layout.height());
+        app.context.canvas.set_clip_rect(column_layout);
+        let entry_layouts = column_layout
+            .scroll_y(scroll)
+            .split_even_hori(entry_height)
+            .take(day_airings.len());
+        for (entry_layout, (idx, airing)) in entry_layouts.zip(day_airings) {
+            let id = app.context.create_id(entry_layout);
+            if app.context.state_id(id) {
+                app.context.canvas.set_draw_color(color_hex(0x4A4A4A));
+                app.context.canvas.fill_rect(entry_layout).unwrap();
+            }
+            if app.context.click_elem(id) {
+                app.episode_state.episode_scroll.scroll = 0;
+                app.next_screen = Some(Screen::SelectEpisode(idx));
+            }
+
+            let anime = app.database.get_idx(idx);
+            let title = anime.display_title().to_string();
+            let local = Local.timestamp_opt(airing.airing_at as i64, 0).single();
+            let at = local.map_or(String::new(), |v| v.format("%H:%M").to_string());
+            let (info, info_color) = if airing.airing_at > time {
+                let countdown = format_countdown(airing.airing_at - time);
+                let info = format!("Ep {} · {at} · in {countdown}", airing.episode);
+                (info, AIRING_INFO_FONT_COLOR)
+            } else if airing.episode > anime.last_downloaded() {
+                let info = format!("Ep {} · {at} · not downloaded", airing.episode);
+                (info, AIRING_MISSING_FONT_COLOR)
+            } else {
+                let info = format!("Ep {} · {at} · aired", airing.episode);
+                (info, AIRING_INFO_FONT_COLOR)
+            };
+
+            let (title_layout, info_layout) = entry_layout.pad_outer(8, 4).split_hori(1, 2);
+            draw_text(
+                &mut app.context.canvas,
+                &mut app.context.text_manager,
+                DESCRIPTION_FONT_INFO,
+                &title,
+                color_hex(DESCRIPTION_FONT_COLOR),
+                title_layout.x,
+                title_layout.y,
+                None,
+                None,
+            );
+            draw_text(
+                &mut app.context.canvas,
+                &mut app.context.text_manager,
+                DESCRIPTION_FONT_INFO,
+                &info,
+                color_hex(info_color),
+                info_layout.x,
+                info_layout.y,
+                None,
+                None,
+            );
+            app.context.canvas.set_draw_color(color_hex(0x2A2A2A));
+            app.context.canvas.draw_rect(entry_layout).unwrap();
+        }
+        app.context.canvas.set_clip_rect(None);
+    }
+    app.airing_state.scroll.max_scroll = (max_entries as u32 * entry_height) as i32;
+
+    if airings.is_empty() {
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            DESCRIPTION_FONT_INFO,
+            "Nothing being watched airs this week",
+            color_hex(AIRING_INFO_FONT_COLOR),
+            list_layout.x + 8,
+            list_layout.y,
+            None,
+            None,
+        );
+    }
+}
+
+#[test]
+fn format_countdown_test() {
+    assert_eq!("1m", format_countdown(0));
+    assert_eq!("12m", format_countdown(12 * 60 + 30));
+    assert_eq!("5h 0m", format_countdown(5 * 3600));
+    assert_eq!("2d 4h", format_countdown(2 * 86400 + 4 * 3600 + 59));
+}
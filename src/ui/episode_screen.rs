@@ -1,19 +1,23 @@
 use sdl2::keyboard::{Keycode, Mod};
 use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
 
 use crate::database::episode::Episode;
-use crate::database::AnimeMapIdx;
-use crate::{database, open_video, register_scroll, update_watched, Context, Format};
+use crate::database::episode_thumbnail::EpisodeThumbnail;
+use crate::database::list_entry::{FuzzyDate, ListStatus};
+use crate::database::{AnimeMapIdx, FranchiseEntry};
+use crate::http::get_media_details;
+use crate::{database, multiline_textbox, play_episode, register_scroll, Context, Format, Textbox};
 use crate::{
-    ui::{color_hex, draw_text, BACK_BUTTON_FONT_INFO},
+    ui::{color_hex, draw_text, update_trackers_watched, BACK_BUTTON_FONT_INFO},
     App,
 };
 
 use super::layout::Layout;
 use super::{
-    draw_back_button, draw_image_float, draw_missing_thumbnail, draw_text_centered,
-    Screen, H1_FONT_INFO, H2_FONT_INFO, PLAY_ICON, THUMBNAIL_MISSING_SIZE,
-    TITLE_FONT, TITLE_FONT_COLOR,
+    color_hex_a, draw_back_button, draw_button, draw_image_clip, draw_image_float,
+    draw_missing_thumbnail, draw_text_centered, Screen, Style, H1_FONT_INFO, H2_FONT_INFO,
+    PLAY_ICON, THUMBNAIL_MISSING_SIZE, TITLE_FONT, TITLE_FONT_COLOR,
 };
 
 pub const DESCRIPTION_X_PAD_OUTER: u32 = 10;
@@ -26,6 +30,16 @@ pub const DESCRIPTION_FONT_COLOR: u32 = TITLE_FONT_COLOR;
 pub const DIRECTORY_NAME_FONT_INFO: (&str, u16) = DESCRIPTION_FONT_INFO;
 pub const DIRECTORY_NAME_FONT_COLOR: u32 = 0x404040;
 
+pub const FRANCHISE_FONT_INFO: (&str, u16) = DESCRIPTION_FONT_INFO;
+pub const FRANCHISE_MISSING_FONT_COLOR: u32 = 0x707070;
+
+pub const INFO_FONT_COLOR: u32 = 0xA0A0A0;
+
+pub const LIST_ENTRY_FONT_INFO: (&str, u16) = (DESCRIPTION_FONT, 14);
+pub const LIST_ENTRY_SELECTED_COLOR: u32 = 0x304A6C;
+/// Darkens the banner so the description stays readable on top of it
+pub const BANNER_OVERLAY_COLOR: u32 = 0x1A1B25C0;
+
 const THUMBNAIL_RAD: i16 = 6;
 
 fn draw_episode_list(app: &mut App, idx: AnimeMapIdx, mut layout: Rect) {
@@ -51,7 +65,14 @@ fn draw_episode_list(app: &mut App, idx: AnimeMapIdx, mut layout: Rect) {
         app.episode_state.episode_scroll.max_scroll = max_height;
     }
 
-    if app.keydown(Keycode::Escape) {
+    let notes_focused = match &app.episode_state.notes {
+        Some((_, textbox_state)) => app.context.textbox_id == Some(textbox_state.id),
+        None => false,
+    };
+    if app.keydown(Keycode::Escape) && notes_focused {
+        app.context.textbox_id = None;
+        app.context.input_util.stop();
+    } else if app.keydown(Keycode::Escape) {
         app.next_screen = Some(Screen::Main);
     }
 
@@ -109,27 +130,348 @@ fn draw_episode_list(app: &mut App, idx: AnimeMapIdx, mut layout: Rect) {
 pub fn draw_anime_expand(app: &mut App, layout: Rect, idx: AnimeMapIdx) {
     let layout = layout.pad_outer(DESCRIPTION_X_PAD_OUTER, DESCRIPTION_Y_PAD_OUTER);
     let (left_layout, right_layout) = layout.split_vert(1, 10);
-    let (top_left_layout, _bottom_left_layout) = left_layout.split_hori(1, 11);
+    let (top_left_layout, bottom_left_layout) = left_layout.split_hori(1, 11);
     let (top_description_layout, bottom_description_layout) = right_layout.split_hori(3, 7);
     let top_description_layout = top_description_layout.pad_bottom(10);
     let (back_button_layout, _) = top_left_layout.split_hori(10, 11);
 
     draw_top_panel_anime_expand(app, idx, top_description_layout);
     draw_back_button(app, Screen::Main, back_button_layout.pad_right(5));
-    draw_episode_list(app, idx, bottom_description_layout);
+    draw_list_entry(app, idx, bottom_left_layout.pad_right(5).pad_top(10));
+
+    let franchise = match app.episode_state.franchise.take() {
+        Some((cached_idx, franchise)) if cached_idx == idx.to_usize() => franchise,
+        _ => {
+            app.episode_state.franchise_scroll.scroll = 0;
+            app.database.franchise(idx)
+        }
+    };
+    let (episode_layout, side_layout) = bottom_description_layout.split_vert(7, 10);
+    draw_episode_list(app, idx, episode_layout.pad_right(10));
+    if franchise.is_empty() {
+        draw_notes(app, idx, side_layout);
+    } else {
+        let (notes_layout, franchise_layout) = side_layout.split_hori(1, 2);
+        draw_notes(app, idx, notes_layout.pad_bottom(10));
+        draw_franchise(app, &franchise, franchise_layout);
+    }
+    app.episode_state.franchise = Some((idx.to_usize(), franchise));
 }
 
-fn draw_top_panel_with_metadata(context: &mut Context, anime: &database::Anime, layout: Rect) {
-    let metadata = match anime.metadata() {
-        Some(m) => m,
-        None => return,
+/// Free text notes on the list entry, saved with the button or Ctrl+Return.
+fn draw_notes(app: &mut App, idx: AnimeMapIdx, layout: Rect) {
+    let mut textbox_state = match app.episode_state.notes.take() {
+        Some((cached_idx, textbox_state)) if cached_idx == idx.to_usize() => textbox_state,
+        _ => {
+            let notes = app.database.get_idx(idx).list_entry().notes.clone();
+            Textbox {
+                cursor_location: notes.len(),
+                text: notes,
+                ..Default::default()
+            }
+        }
+    };
+
+    let (_, font_height) = app.context.text_manager.text_size(H2_FONT_INFO, "L");
+    let (header_layout, notes_layout) = layout.split_hori(font_height * 2, layout.height());
+    let (title_layout, button_layout) =
+        header_layout.split_vert(header_layout.width() - 80, header_layout.width());
+    draw_text(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        H2_FONT_INFO,
+        "Notes",
+        color_hex(DESCRIPTION_FONT_COLOR),
+        title_layout.x,
+        title_layout.y,
+        Some(title_layout.width()),
+        Some(title_layout.height()),
+    );
+
+    let submit = multiline_textbox(&mut app.context, &mut textbox_state, notes_layout);
+    let changed = textbox_state.text != app.database.get_idx(idx).list_entry().notes;
+    let save_style = Style::new(color_hex(0xDDDDDD), color_hex(0x009000))
+        .bg_hover_color(color_hex(0x00AB00))
+        .font_info(LIST_ENTRY_FONT_INFO);
+    let saved_style =
+        Style::new(color_hex(0x909090), color_hex(0x222222)).font_info(LIST_ENTRY_FONT_INFO);
+    let style = if changed { save_style } else { saved_style };
+    let save = draw_button(
+        &mut app.context,
+        "Save",
+        style,
+        button_layout.pad_outer(0, font_height / 4),
+    );
+    if (save || submit) && changed {
+        let sync_queue = app.database.sync_queue();
+        let anime = app.database.get_mut_idx(idx);
+        let mut entry = anime.list_entry().clone();
+        entry.notes = textbox_state.text.clone();
+        anime.set_list_entry(entry);
+        if let Some(sync_queue) = sync_queue {
+            for tracker in sync_queue.trackers().to_vec() {
+                match anime.pending_change(tracker) {
+                    Some(change) if tracker.syncs_notes() => sync_queue.push(change),
+                    _ => (),
+                }
+            }
+        }
+    }
+    app.episode_state.notes = Some((idx.to_usize(), textbox_state));
+}
+
+/// Status, score, dates and rewatches synced with the tracker.
+///
+/// Left click on a date sets it to today and right click clears it; left and right click on
+/// the score and rewatch count increment and decrement them. Scores step as the AniList score
+/// format allows.
+fn draw_list_entry(app: &mut App, idx: AnimeMapIdx, layout: Rect) {
+    let (_, font_height) = app
+        .context
+        .text_manager
+        .text_size(LIST_ENTRY_FONT_INFO, "L");
+    let row_height = font_height * 3 / 2;
+    let mut entry = app.database.get_idx(idx).list_entry().clone();
+    let mut changed = false;
+
+    // Labels are drawn with `selected` as `None` and are not clickable
+    let mut rows = layout.split_even_hori(row_height);
+    let mut draw_row = |app: &mut App, text: &str, selected: Option<bool>| {
+        let row_layout = rows.next()?;
+        if row_layout.bottom() > layout.bottom() {
+            return None;
+        }
+        let clicked = match selected {
+            Some(selected) => {
+                let id = app.context.create_id(row_layout);
+                app.episode_state.selectable.insert(id);
+                if selected || app.context.state_id(id) {
+                    app.context.canvas.set_draw_color(color_hex(if selected {
+                        LIST_ENTRY_SELECTED_COLOR
+                    } else {
+                        0x4A4A4A
+                    }));
+                    app.context.canvas.fill_rect(row_layout).unwrap();
+                }
+                Some((app.context.click_elem(id), app.context.click_elem_right(id)))
+            }
+            None => None,
+        };
+        let text_layout = row_layout.pad_left(8);
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            LIST_ENTRY_FONT_INFO,
+            text,
+            color_hex(match selected {
+                Some(_) => DESCRIPTION_FONT_COLOR,
+                None => FRANCHISE_MISSING_FONT_COLOR,
+            }),
+            text_layout.x,
+            text_layout.y + (row_height - font_height) as i32 / 2,
+            Some(text_layout.width()),
+            Some(font_height),
+        );
+        clicked
     };
+
+    let today = FuzzyDate::from_unix(database::get_time());
+    draw_row(app, "Status", None);
+    for status in ListStatus::ALL {
+        let selected = entry.status == Some(status);
+        if let Some((true, _)) = draw_row(app, status.as_str(), Some(selected)) {
+            entry.status = Some(status);
+            changed = true;
+        }
+    }
+    match entry.status {
+        Some(ListStatus::Current) if changed && entry.started_at.is_empty() => {
+            entry.started_at = today;
+        }
+        Some(ListStatus::Completed) if changed && entry.completed_at.is_empty() => {
+            entry.completed_at = today;
+        }
+        _ => (),
+    }
+
+    let score_format = app.database.score_format();
+    draw_row(app, "Score", None);
+    let score = match draw_row(app, &score_format.format(entry.score), Some(false)) {
+        Some((true, _)) => score_format.increment(entry.score),
+        Some((_, true)) => score_format.decrement(entry.score),
+        _ => entry.score,
+    };
+    if score != entry.score {
+        entry.score = score;
+        changed = true;
+    }
+
+    for (label, date) in [
+        ("Started", &mut entry.started_at),
+        ("Finished", &mut entry.completed_at),
+    ] {
+        draw_row(app, label, None);
+        match draw_row(app, &date.to_string(), Some(false)) {
+            Some((true, _)) => {
+                *date = today;
+                changed = true;
+            }
+            Some((_, true)) => {
+                *date = FuzzyDate::default();
+                changed = true;
+            }
+            _ => (),
+        }
+    }
+
+    let repeat = format!("Rewatched {}", entry.repeat);
+    match draw_row(app, &repeat, Some(false)) {
+        Some((true, _)) => {
+            entry.repeat += 1;
+            changed = true;
+        }
+        Some((_, true)) if entry.repeat > 0 => {
+            entry.repeat -= 1;
+            changed = true;
+        }
+        _ => (),
+    }
+
+    if changed {
+        let sync_queue = app.database.sync_queue();
+        let anime = app.database.get_mut_idx(idx);
+        anime.set_list_entry(entry);
+        if let Some(sync_queue) = sync_queue {
+            update_trackers_watched(sync_queue, anime);
+        }
+    }
+}
+
+fn draw_franchise(app: &mut App, franchise: &[FranchiseEntry], layout: Rect) {
+    let (_, font_height) = app.context.text_manager.text_size(FRANCHISE_FONT_INFO, "L");
+    let (header_layout, mut list_layout) = layout.split_hori(font_height * 2, layout.height());
+    draw_text(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        H2_FONT_INFO,
+        "Franchise",
+        color_hex(DESCRIPTION_FONT_COLOR),
+        header_layout.x,
+        header_layout.y,
+        Some(header_layout.width()),
+        Some(header_layout.height()),
+    );
+
+    app.context.canvas.set_clip_rect(list_layout);
+    register_scroll(
+        &mut app.context,
+        &mut app.episode_state.franchise_scroll,
+        &mut list_layout,
+    );
+    let scroll = app.episode_state.franchise_scroll.scroll;
+    let entry_layouts = list_layout
+        .scroll_y(scroll)
+        .split_even_hori(font_height * 3)
+        .take(franchise.len())
+        .collect::<Box<[Rect]>>();
+    if let Some(last) = entry_layouts.last() {
+        app.episode_state.franchise_scroll.max_scroll = last.bottom() - scroll - list_layout.y();
+    }
+
+    for (entry, entry_layout) in franchise.iter().zip(entry_layouts.iter().copied()) {
+        let (title_layout, info_layout) = entry_layout.pad_outer(8, 4).split_hori(1, 2);
+        let (title_color, info) = match entry.idx {
+            Some(idx) => {
+                let id = app.context.create_id(entry_layout);
+                app.episode_state.selectable.insert(id);
+                if app.context.state_id(id) {
+                    app.context.canvas.set_draw_color(color_hex(0x4A4A4A));
+                    app.context.canvas.fill_rect(entry_layout).unwrap();
+                }
+                if app.context.click_elem(id) {
+                    app.episode_state.episode_scroll.scroll = 0;
+                    app.next_screen = Some(Screen::SelectEpisode(idx));
+                }
+                (DESCRIPTION_FONT_COLOR, "In library")
+            }
+            None => (FRANCHISE_MISSING_FONT_COLOR, "Not in library"),
+        };
+        let info = match entry.year {
+            Some(year) => format!("{year} · {info}"),
+            None => info.to_string(),
+        };
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            FRANCHISE_FONT_INFO,
+            &entry.title,
+            color_hex(title_color),
+            title_layout.x,
+            title_layout.y,
+            None,
+            None,
+        );
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            FRANCHISE_FONT_INFO,
+            info,
+            color_hex(FRANCHISE_MISSING_FONT_COLOR),
+            info_layout.x,
+            info_layout.y,
+            None,
+            None,
+        );
+        app.context.canvas.set_draw_color(color_hex(0x2A2A2A));
+        app.context.canvas.draw_rect(entry_layout).unwrap();
+    }
+    app.context.canvas.set_clip_rect(None);
+}
+
+/// "TV · 12 episodes · 2013 · Score 85% · Wit Studio", from AniList when the details have been
+/// fetched, otherwise from the offline database.
+fn info_line(anime: &database::Anime) -> String {
+    let details = anime.details();
+    let metadata = anime.metadata().as_ref();
+    let mut info = vec![];
+    if let Some(format) = details.and_then(|d| d.format()) {
+        info.push(format.replace('_', " "));
+    }
+    let episodes = details
+        .and_then(|d| d.episodes())
+        .or_else(|| metadata.and_then(|m| m.episodes()));
+    match episodes {
+        Some(1) => info.push("1 episode".to_string()),
+        Some(episodes) => info.push(format!("{episodes} episodes")),
+        None => (),
+    }
+    if let Some(year) = metadata.and_then(|m| m.year()) {
+        info.push(year.to_string());
+    }
+    if let Some(score) = details.and_then(|d| d.average_score()) {
+        info.push(format!("Score {score}%"));
+    }
+    if let Some(details) = details {
+        let studios = details.studios().collect::<Vec<_>>().join(", ");
+        if !studios.is_empty() {
+            info.push(studios);
+        }
+    }
+    info.join(" · ")
+}
+
+fn draw_top_panel_with_metadata(context: &mut Context, anime: &database::Anime, layout: Rect) {
+    let details = anime.details();
+    let metadata = anime.metadata().as_ref();
+    if details.is_none() && metadata.is_none() {
+        return;
+    }
     let (_, font_height) = context
         .text_manager
         .text_size(DIRECTORY_NAME_FONT_INFO, "L");
     let description_layout = layout;
     let (title_layout, description_layout) = description_layout.split_hori(2, 7);
-    let (title_layout, description_header_layout) = title_layout.split_hori(1, 2);
+    let (title_layout, info_layout) = title_layout.split_hori(1, 2);
     let (description_layout, directory_name_layout) = description_layout.split_hori(
         description_layout.height() - font_height,
         description_layout.height(),
@@ -145,11 +487,58 @@ fn draw_top_panel_with_metadata(context: &mut Context, anime: &database::Anime,
         Some(title_layout.width()),
         Some(title_layout.height()),
     );
+    draw_text(
+        &mut context.canvas,
+        &mut context.text_manager,
+        DESCRIPTION_FONT_INFO,
+        info_line(anime),
+        color_hex(INFO_FONT_COLOR),
+        info_layout.x,
+        info_layout.y,
+        Some(info_layout.width()),
+        Some(info_layout.height()),
+    );
+
+    // Genres and synopsis from AniList, falling back to the tags of the offline database
+    let (genres, header, text) = match details {
+        Some(details) => (
+            details.genres().join(", "),
+            "Synopsis",
+            details.description().unwrap_or("No synopsis.").to_string(),
+        ),
+        None => (
+            String::new(),
+            "Tags",
+            metadata.map(|m| m.tags().join(", ")).unwrap_or_default(),
+        ),
+    };
+    let mut description_layout = description_layout;
+    if !genres.is_empty() {
+        let genres_layout;
+        (genres_layout, description_layout) =
+            description_layout.split_hori(font_height * 3 / 2, description_layout.height());
+        draw_text(
+            &mut context.canvas,
+            &mut context.text_manager,
+            DESCRIPTION_FONT_INFO,
+            format!("Genres: {genres}"),
+            color_hex(DESCRIPTION_FONT_COLOR),
+            genres_layout.x,
+            genres_layout.y,
+            Some(genres_layout.width()),
+            Some(genres_layout.height()),
+        );
+    }
+    let (_, header_height) = context.text_manager.text_size(H2_FONT_INFO, header);
+    let (description_header_layout, description_layout) = description_layout.split_hori(
+        header_height.min(description_layout.height()),
+        description_layout.height(),
+    );
     draw_text(
         &mut context.canvas,
         &mut context.text_manager,
         H2_FONT_INFO,
-        "Description",
+        header,
         color_hex(DESCRIPTION_FONT_COLOR),
         description_header_layout.x,
         description_header_layout.y,
@@ -160,7 +549,7 @@ fn draw_top_panel_with_metadata(context: &mut Context, anime: &database::Anime,
         &mut context.canvas,
         &mut context.text_manager,
         DESCRIPTION_FONT_INFO,
-        metadata.tags().join(", "),
+        text,
         color_hex(DESCRIPTION_FONT_COLOR),
         description_layout.x,
         description_layout.y,
@@ -183,6 +572,22 @@ fn draw_top_panel_with_metadata(context: &mut Context, anime: &database::Anime,
 }
 
 fn draw_top_panel_anime_expand(app: &mut App, idx: AnimeMapIdx, layout: Rect) {
+    // Details are refreshed once per session and kept in the database for offline use
+    if let Some(id) = app.database.get_idx(idx).anilist_id() {
+        if app.episode_state.details_requested.insert(id) {
+            get_media_details(&app.http_tx, id);
+        }
+    }
+    if let Some(banner) = app.database.banner(idx, &app.thumbnail_path) {
+        if draw_image_clip(app, &banner, layout, Some(THUMBNAIL_RAD), None).is_ok() {
+            app.context.canvas.set_blend_mode(BlendMode::Blend);
+            app.context
+                .canvas
+                .set_draw_color(color_hex_a(BANNER_OVERLAY_COLOR));
+            app.context.canvas.fill_rect(layout).unwrap();
+        }
+    }
+
     let description_layout = match app.database.get_idx(idx).thumbnail() {
         Some(thumbnail) => {
             if let Ok((image_width, image_height)) = app
@@ -225,13 +630,56 @@ fn draw_top_panel_anime_expand(app: &mut App, idx: AnimeMapIdx, layout: Rect) {
     );
 }
 
+/// Frame grabbed from the episode, or a placeholder while it is generated.
+fn draw_episode_preview(
+    app: &mut App,
+    idx: AnimeMapIdx,
+    episode: &Episode,
+    layout: Rect,
+    clip_rect: Rect,
+) {
+    // Only queue grabs for rows that are on screen.
+    if !layout.has_intersection(clip_rect) {
+        return;
+    }
+    let video = match app.database.get_idx(idx).find_episode_path(episode).first() {
+        Some(v) => v.clone(),
+        None => return,
+    };
+    let text = match app.episode_thumbnails.get(&video) {
+        EpisodeThumbnail::Ready(path) => {
+            let path = path.to_owned();
+            let rounded = Some(THUMBNAIL_RAD);
+            if draw_image_float(&mut app.context, path, layout, None, rounded, None).is_ok() {
+                return;
+            }
+            "No preview"
+        }
+        EpisodeThumbnail::Pending => "...",
+        EpisodeThumbnail::Failed => "No preview",
+    };
+    app.context.canvas.set_draw_color(color_hex(0x2A2A2A));
+    app.context.canvas.fill_rect(layout).unwrap();
+    draw_text_centered(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        DIRECTORY_NAME_FONT_INFO,
+        text,
+        color_hex(FRANCHISE_MISSING_FONT_COLOR),
+        layout.x + layout.width() as i32 / 2,
+        layout.y + layout.height() as i32 / 2,
+        None,
+        None,
+    );
+}
+
 fn draw_episode(
     app: &mut App,
     idx: AnimeMapIdx,
     text: &str,
     episode: Episode,
     layout: Rect,
-    _clip_rect: Rect,
+    clip_rect: Rect,
 ) {
     let (play_width, play_height) = app
         .context
@@ -243,6 +691,16 @@ fn draw_episode(
         .pad_right(5)
         .split_vert(play_width * layout.height() / play_height, layout.width());
     let ep_name_layout = ep_name_layout.pad_left(30);
+    let ep_name_layout = if app.episode_thumbnails.enabled() {
+        let preview_width = layout.height() * 16 / 9;
+        let (preview_layout, ep_name_layout) =
+            ep_name_layout.split_vert(preview_width, ep_name_layout.width());
+        let preview_layout = preview_layout.pad_outer(0, 5);
+        draw_episode_preview(app, idx, &episode, preview_layout, clip_rect);
+        ep_name_layout.pad_left(15)
+    } else {
+        ep_name_layout
+    };
     let id = app.context.create_id(layout);
     app.episode_state.selectable.insert(id);
 
@@ -251,16 +709,11 @@ fn draw_episode(
         app.context.canvas.fill_rect(layout).unwrap();
     }
     if app.context.click_elem(id) {
-        {
-            let anime = app.database.get_mut_idx(idx);
-            let paths = anime.find_episode_path(&episode);
-            open_video(&paths[0], anime);
-        }
-
-        if !app.context.keymod.contains(Mod::LSHIFTMOD) {
-            let access_token = app.database.anilist_access_token().map(|v| v.to_string());
-            let anime = app.database.get_mut_idx(idx);
-            update_watched(&app.http_tx, access_token, anime, &episode);
+        // Shift plays without marking the episode as watched
+        let mark_watched = !app.context.keymod.contains(Mod::LSHIFTMOD);
+        let sync_queue = app.database.sync_queue();
+        let anime = app.database.get_mut_idx(idx);
+        if play_episode(&mut app.player, sync_queue, anime, &episode, mark_watched) {
             app.episode_state.episode_scroll.scroll = 0;
         }
     }
@@ -3,7 +3,12 @@ use sdl2::keyboard::Mod;
 use sdl2::rect::Rect;
 use sdl2::{keyboard::Keycode, url::open_url};
 
-use crate::http::{get_anilist_media_list, send_login};
+use crate::database::{self, sync_queue::Tracker};
+use crate::http::anilist::{self, ANILIST_AUTH_URL};
+use crate::http::mal::{self, MAL_AUTH_URL};
+use crate::http::{
+    get_anilist_media_list, listen_for_anilist_token, send_kitsu_login, send_login, send_mal_login,
+};
 use crate::{rect, textbox, App, LoginProgress};
 
 use super::episode_screen::DESCRIPTION_FONT_INFO;
@@ -14,10 +19,33 @@ use super::{
 };
 
 pub fn draw_login(app: &mut App, layout: Rect) {
-    if let Some(cred) = app.database.anilist_cred() {
-        get_anilist_media_list(&app.http_tx, cred.user_id(), cred.access_token());
-        app.next_screen = Some(Screen::Main);
-        return;
+    match app.login_state.tracker {
+        // Logging in again is offered before the token expires
+        Tracker::AniList => {
+            if let Some(cred) = app
+                .database
+                .anilist_cred()
+                .as_ref()
+                .filter(|v| !v.expires_soon(database::get_time()))
+            {
+                get_anilist_media_list(&app.http_tx, cred.user_id(), cred.access_token());
+                app.next_screen = Some(logged_in_screen(app));
+                return;
+            }
+        }
+        // The list is fetched once logged in, see `poll_http`
+        Tracker::MyAnimeList => {
+            if app.database.mal_cred().is_some() {
+                app.next_screen = Some(logged_in_screen(app));
+                return;
+            }
+        }
+        Tracker::Kitsu => {
+            if app.database.kitsu_cred().is_some() {
+                app.next_screen = Some(logged_in_screen(app));
+                return;
+            }
+        }
     }
 
     if app.context.keymod.contains(Mod::LCTRLMOD) && app.keydown(Keycode::Escape) {
@@ -28,14 +56,18 @@ pub fn draw_login(app: &mut App, layout: Rect) {
     }
 
     let (header_layout, rest) = layout.split_hori(1, 4);
+    let (tabs_layout, header_layout) = header_layout.split_hori(1, 3);
     let (header_layout, link_layout) = header_layout.split_hori(1, 2);
 
-    // draw anilist header
+    draw_tracker_tabs(app, tabs_layout);
+    let tracker = app.login_state.tracker;
+
+    // draw tracker header
     draw_text_centered(
         &mut app.context.canvas,
         &mut app.context.text_manager,
         H1_FONT_INFO,
-        "AniList Login",
+        format!("{} Login", tracker.name()),
         color_hex(0x909090),
         header_layout.x + header_layout.width() as i32 / 2,
         header_layout.y + header_layout.height() as i32 / 2,
@@ -43,8 +75,16 @@ pub fn draw_login(app: &mut App, layout: Rect) {
         None,
     );
 
-    // draw clickable link to `https://anilist.co/api/v2/oauth/authorize?client_id=15365&response_type=token`
-    let link_str = "Click here to get access token";
+    // draw clickable link to AniList's or MyAnimeList's authorize page. AniList redirects back
+    // to a local listener when a redirect uri is configured. Kitsu logs in with the account's
+    // password instead.
+    let link_str = match (tracker, &app.mal_client_id) {
+        (Tracker::AniList, _) if app.anilist_redirect_uri.is_some() => "Click here to log in",
+        (Tracker::AniList, _) => "Click here to get access token",
+        (Tracker::MyAnimeList, Some(_)) => "Click here to get authorization code",
+        (Tracker::MyAnimeList, None) => "Set mal_client_id in aniki.conf to log in",
+        (Tracker::Kitsu, _) => "Log in with your Kitsu email and password",
+    };
     let (link_width, link_height) = app.context.text_manager.text_size(H2_FONT_INFO, link_str);
     let (link_layout, _) = link_layout.split_hori(link_height, link_layout.height());
     let x = link_layout.x + (link_layout.width() - link_width) as i32 / 2;
@@ -64,8 +104,25 @@ pub fn draw_login(app: &mut App, layout: Rect) {
 
     let link_id = app.context.create_id(link_rect);
     if app.context.click_elem(link_id) {
-        open_url("https://anilist.co/api/v2/oauth/authorize?client_id=15365&response_type=token")
-            .unwrap();
+        match (tracker, &app.mal_client_id) {
+            (Tracker::AniList, _) => {
+                let redirect_uri = app.anilist_redirect_uri.as_deref();
+                let url =
+                    anilist::authorize_url(ANILIST_AUTH_URL, app.anilist_client_id, redirect_uri);
+                open_url(&url).unwrap();
+                if let Some(redirect_uri) = redirect_uri {
+                    app.login_progress = LoginProgress::Started;
+                    listen_for_anilist_token(&app.http_tx, redirect_uri);
+                }
+            }
+            (Tracker::MyAnimeList, Some(client_id)) => {
+                app.login_state.code_verifier = mal::code_verifier();
+                let url =
+                    mal::authorize_url(MAL_AUTH_URL, client_id, &app.login_state.code_verifier);
+                open_url(&url).unwrap();
+            }
+            (Tracker::MyAnimeList, None) | (Tracker::Kitsu, _) => (),
+        }
     }
 
     // draw `access token` input field
@@ -88,11 +145,26 @@ pub fn draw_login(app: &mut App, layout: Rect) {
     let input_box_submit = textbox(
         &mut app.context,
         &mut app.login_state.textbox,
-        Some("Access Token:"),
+        Some(match tracker {
+            Tracker::AniList => "Access Token:",
+            Tracker::MyAnimeList => "Authorization Code:",
+            Tracker::Kitsu => "Email:",
+        }),
         true,
         130,
         &mut rest,
     );
+    let password_submit = match tracker {
+        Tracker::Kitsu => textbox(
+            &mut app.context,
+            &mut app.login_state.password,
+            Some("Password:"),
+            true,
+            130,
+            &mut rest,
+        ),
+        _ => false,
+    };
     // draw submit button
     let button_width_pad = 28;
     let button_height = 42;
@@ -129,10 +201,33 @@ pub fn draw_login(app: &mut App, layout: Rect) {
     let skip_button_style = Style::new(color_hex(0x909090), color_hex(0x222222))
         .bg_hover_color(color_hex(0x444444))
         .font_info(button_font_info);
-    if draw_button(&mut app.context, "Submit", submit_button_style, submit_button_layout) || input_box_submit {
-        app.login_progress = LoginProgress::Started;
-        let access_token = &app.login_state.textbox.text;
-        send_login(&app.http_tx, access_token);
+    if draw_button(&mut app.context, "Submit", submit_button_style, submit_button_layout) || input_box_submit || password_submit {
+        match (tracker, &app.mal_client_id) {
+            (Tracker::AniList, _) => {
+                app.login_progress = LoginProgress::Started;
+                let access_token = &app.login_state.textbox.text;
+                send_login(&app.http_tx, access_token);
+            }
+            (Tracker::MyAnimeList, Some(client_id)) => {
+                app.login_progress = LoginProgress::Started;
+                let code = mal::parse_code(&app.login_state.textbox.text);
+                send_mal_login(
+                    &app.http_tx,
+                    client_id,
+                    &code,
+                    &app.login_state.code_verifier,
+                );
+            }
+            (Tracker::MyAnimeList, None) => (),
+            (Tracker::Kitsu, _) => {
+                app.login_progress = LoginProgress::Started;
+                send_kitsu_login(
+                    &app.http_tx,
+                    app.login_state.textbox.text.trim(),
+                    &app.login_state.password.text,
+                );
+            }
+        }
     }
 
     // draw skip login button
@@ -173,7 +268,11 @@ pub fn draw_login(app: &mut App, layout: Rect) {
         }
         LoginProgress::Failed => {
             let font_info = DESCRIPTION_FONT_INFO;
-            let text = "Incorrect token; Try again!";
+            let text = match tracker {
+                Tracker::AniList => "Incorrect token; Try again!",
+                Tracker::MyAnimeList => "Incorrect code; Try again!",
+                Tracker::Kitsu => "Incorrect email or password; Try again!",
+            };
             let (text_width, _text_height) =
                 text_size(&mut app.context.text_manager, font_info, text);
             draw_text(
@@ -191,3 +290,49 @@ pub fn draw_login(app: &mut App, layout: Rect) {
         }
     }
 }
+
+/// Without a keyring, the passphrase is asked for so the login can be saved.
+fn logged_in_screen(app: &App) -> Screen {
+    match app.credentials.is_locked() {
+        true => Screen::Unlock,
+        false => Screen::Main,
+    }
+}
+
+/// Tabs to pick the tracker to log in to.
+fn draw_tracker_tabs(app: &mut App, layout: Rect) {
+    let tab_font_info = (DEFAULT_BUTTON_FONT, 18);
+    let tab_height = 36;
+    let tab_width_pad = 28;
+    let trackers = [Tracker::AniList, Tracker::MyAnimeList, Tracker::Kitsu];
+    let tab_widths = trackers.map(|tracker| {
+        let (width, _) = app
+            .context
+            .text_manager
+            .text_size(tab_font_info, tracker.name());
+        width + tab_width_pad
+    });
+    let total_width: u32 = tab_widths.iter().sum();
+    let mut x = layout.x + (layout.width() as i32 - total_width as i32) / 2;
+    let y = layout.y + (layout.height() as i32 - tab_height) / 2;
+
+    for (tracker, width) in trackers.into_iter().zip(tab_widths) {
+        let bg_color = match app.login_state.tracker == tracker {
+            true => 0x444444,
+            false => 0x222222,
+        };
+        let style = Style::new(color_hex(0x909090), color_hex(bg_color))
+            .bg_hover_color(color_hex(0x444444))
+            .font_info(tab_font_info);
+        let tab_layout = rect!(x, y, width, tab_height);
+        if draw_button(&mut app.context, tracker.name(), style, tab_layout)
+            && app.login_state.tracker != tracker
+        {
+            app.login_state.tracker = tracker;
+            app.login_state.textbox.text.clear();
+            app.login_state.password.text.clear();
+            app.login_progress = LoginProgress::None;
+        }
+        x += width as i32;
+    }
+}
@@ -4,18 +4,20 @@ use sdl2::rect::Rect;
 use sdl2::render::BlendMode;
 
 use crate::database::json_database::AnimeDatabaseData;
+use crate::database::{thumbnail, LibraryOrder, TagState};
 use crate::{
     database,
     ui::{color_hex, draw_text, BACK_BUTTON_FONT_INFO},
     App,
 };
-use crate::{open_video, rect, register_scroll, textbox, update_watched, Format};
+use crate::{play_episode, rect, register_scroll, switch_profile, textbox, Format, Textbox};
 
+use super::airing_screen::format_countdown;
 use super::layout::Layout as _;
 use super::{
     color_hex_a, draw_button, draw_image_clip, draw_missing_thumbnail, draw_text_centered,
-    text_size, Screen, Style, TextureOptions, INPUT_BOX_FONT_INFO, MISSING_THUMBNAIL,
-    PLAY_BUTTON_FONT_INFO, TITLE_FONT_COLOR, TITLE_FONT_INFO,
+    text_size, Screen, Style, TextureOptions, CONNECTION_FONT_INFO, INPUT_BOX_FONT_INFO,
+    MISSING_THUMBNAIL, PLAY_BUTTON_FONT_INFO, TITLE_FONT_COLOR, TITLE_FONT_INFO,
 };
 
 pub const CARD_RAD: i16 = 10;
@@ -26,6 +28,20 @@ const CARD_Y_PAD_OUTER: i32 = 13;
 const CARD_X_PAD_INNER: i32 = 25;
 const CARD_Y_PAD_INNER: i32 = 25;
 
+const TAG_SIDEBAR_WIDTH: u32 = 260;
+const TAG_FONT_INFO: (&str, u16) = PLAY_BUTTON_FONT_INFO;
+const TAG_INCLUDE_COLOR: u32 = 0x7FBF7F;
+const TAG_EXCLUDE_COLOR: u32 = 0xBF7F7F;
+
+const BADGE_FONT_INFO: (&str, u16) = CONNECTION_FONT_INFO;
+const BADGE_BG_COLOR: u32 = 0x2E5E3ADD;
+/// Of episodes that aired and are not on disk yet
+const BADGE_MISSING_BG_COLOR: u32 = 0x6E3A2EDD;
+const BADGE_PAD: u32 = 6;
+
+/// Over placeholder cards, as there is nothing on disk to play
+const PLACEHOLDER_DIM_COLOR: u32 = 0x1010107F;
+
 type Layout = Rect;
 
 // TODO: Clean up event handling.
@@ -40,6 +56,10 @@ fn handle_main_events(app: &mut App) {
         // TODO: Select down
     } else if app.keydown(Keycode::P) && app.context.keymod.contains(keyboard::Mod::LCTRLMOD) {
         // TODO: Select up
+    } else if app.keydown(Keycode::T) && app.context.keymod.contains(keyboard::Mod::LCTRLMOD) {
+        app.main_state.show_tags = !app.main_state.show_tags;
+    } else if app.keydown(Keycode::Slash) {
+        toggle_library_search(app);
     } else if app.keydown(Keycode::Return) {
         if let Some(idx) = app.main_state.selected {
             // Should exist
@@ -53,10 +73,62 @@ fn handle_main_search_events(app: &mut App) {
     if app.keydown(Keycode::Escape) {
         app.main_state.search_anime = None;
         app.main_state.alias_anime = None;
+        app.main_state.thumbnail_anime = None;
+        app.main_state.show_conflicts = false;
+        app.main_state.list_import = None;
+        app.main_state.profiles = None;
         app.context.input_util.stop();
     }
 }
 
+fn handle_library_search_events(app: &mut App) {
+    if app.keydown(Keycode::Escape) {
+        toggle_library_search(app);
+    }
+}
+
+/// Opens the library search, or closes it and shows the whole library again.
+pub fn toggle_library_search(app: &mut App) {
+    match app.main_state.library_search.take() {
+        Some(_) => {
+            app.database.update_search("");
+            app.context.input_util.stop();
+        }
+        None => {
+            app.main_state.library_search = Some(Textbox::default());
+            app.main_state.scroll.scroll = 0;
+            // Focused once drawn
+            app.context.textbox_id = None;
+            app.context.text.clear();
+        }
+    }
+}
+
+/// Search box above the cards, filtering them as it is typed in. Return opens the first match.
+fn draw_library_search(app: &mut App, layout: &mut Layout) {
+    let Some(textbox_state) = app.main_state.library_search.as_mut() else {
+        return;
+    };
+    let focus = app.context.textbox_id.is_none();
+    let submit = textbox(
+        &mut app.context,
+        textbox_state,
+        None,
+        true,
+        CARD_X_PAD_OUTER,
+        layout,
+    );
+    if focus {
+        app.context.textbox_id = Some(textbox_state.id);
+    }
+    let query = textbox_state.text.clone();
+    app.database.update_search(&query);
+    if submit && app.database.len() > 0 {
+        let idx = app.database.cache_idx_to_map_idx(0);
+        app.next_screen = Some(Screen::SelectEpisode(idx));
+    }
+}
+
 fn draw_main_anime_search(app: &mut App, layout: Layout, search_id: u32) {
     let outer_bounds_id = app.context.create_id(app.context.window_rect());
     let _inner_bounds_id = app.context.create_id(layout);
@@ -118,6 +190,7 @@ fn draw_main_anime_search(app: &mut App, layout: Layout, search_id: u32) {
         let (left, _) = draw_option(app, option_id, "[Remove anime tracking]");
         if left {
             anime.set_metadata(None);
+            app.database.forget_sequel(anime);
             app.context.canvas.set_clip_rect(None);
             app.main_state.search_anime = None;
             return;
@@ -129,10 +202,20 @@ fn draw_main_anime_search(app: &mut App, layout: Layout, search_id: u32) {
             let option = unsafe { &**option };
             let option_id = app.context.create_id(layout);
             app.context.canvas.set_clip_rect(option_layout);
-            let (left, right) = draw_option(app, option_id, &option.title());
+            let label = match (option.year(), option.episodes()) {
+                (Some(year), Some(episodes)) => {
+                    format!("{} ({year}, {episodes} eps)", option.title())
+                }
+                (Some(year), None) => format!("{} ({year})", option.title()),
+                _ => option.title().to_string(),
+            };
+            let (left, right) = draw_option(app, option_id, &label);
             if left {
                 anime.set_metadata(Some((*option).clone()));
-                app.database.retrieve_images(&app.thumbnail_path).unwrap();
+                app.database.forget_sequel(anime);
+                if let Err(e) = app.database.retrieve_images(&app.thumbnail_path) {
+                    eprintln!("ERROR:failed to retrieve thumbnails:{e}");
+                }
                 app.main_state.search_anime = None;
                 app.context.input_util.stop();
                 app.context.canvas.set_clip_rect(None);
@@ -227,6 +310,462 @@ fn draw_main_anime_alias(app: &mut App, layout: Layout, alias_id: u32) {
     }
 }
 
+fn draw_thumbnail_option(app: &mut App, option_id: usize, label: &str, path: &str) -> bool {
+    let layout = app.context.rect_id(option_id);
+    let (_, text_height) = text_size(&mut app.context.text_manager, TAG_FONT_INFO, label);
+    let (image_layout, label_layout) =
+        layout.split_hori(layout.height() - text_height - 10, layout.height());
+    if app.context.state_id(option_id) {
+        app.context.canvas.set_draw_color(color_hex(0x505050));
+        app.context.canvas.fill_rect(layout).unwrap();
+    }
+    let image_layout = image_layout.pad_outer(5, 5);
+    if draw_image_clip(app, path, image_layout, Some(CARD_RAD), None).is_err() {
+        draw_missing_thumbnail(app, image_layout, Some(CARD_RAD));
+    }
+    app.context.canvas.set_clip_rect(label_layout);
+    draw_text_centered(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        TAG_FONT_INFO,
+        label,
+        color_hex(0xa0a0a0),
+        label_layout.x + label_layout.width() as i32 / 2,
+        label_layout.y + label_layout.height() as i32 / 2,
+        None,
+        None,
+    );
+    app.context.canvas.set_clip_rect(None);
+    app.context.click_elem(option_id)
+}
+
+/// Picker between local artwork, the json database's picture and a file from disk.
+fn draw_main_anime_thumbnail(app: &mut App, layout: Layout, thumbnail_id: u32) {
+    let outer_bounds_id = app.context.create_id(app.context.window_rect());
+    let _inner_bounds_id = app.context.create_id(layout);
+    let anime = &mut app.database.animes()[thumbnail_id as usize];
+
+    app.context.canvas.set_draw_color(color_hex(0x101010));
+    app.context.canvas.fill_rect(layout).unwrap();
+
+    let mut options = anime
+        .local_artwork()
+        .iter()
+        .map(|path| {
+            let name = std::path::Path::new(path)
+                .file_name()
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or_default();
+            (name, path.clone())
+        })
+        .collect::<Vec<_>>();
+    if let Some(url) = anime
+        .metadata()
+        .as_ref()
+        .map(|m| m.thumbnail())
+        .filter(|url| !url.is_empty())
+    {
+        let path = thumbnail::cache_path(&app.thumbnail_path, url);
+        options.push(("Offline database".to_string(), path));
+    }
+
+    let (_, text_height) = text_size(&mut app.context.text_manager, PLAY_BUTTON_FONT_INFO, "L");
+    let (options_layout, buttons_layout) =
+        layout.split_hori(layout.height() - text_height - 30, layout.height());
+    let options_layout = options_layout.pad_outer(15, 15);
+    let (option_width, option_height) = (CARD_WIDTH * 3 / 4, CARD_HEIGHT * 3 / 4 + text_height);
+    let per_row = (options_layout.width() / (option_width + 15)).max(1) as usize;
+    let mut picked = None;
+    app.context.canvas.set_clip_rect(options_layout);
+    for (i, (label, path)) in options.iter().enumerate() {
+        let option_layout = rect!(
+            options_layout.x() + (i % per_row) as i32 * (option_width + 15) as i32,
+            options_layout.y() + (i / per_row) as i32 * (option_height + 15) as i32,
+            option_width,
+            option_height
+        );
+        let option_id = app.context.create_id(option_layout);
+        if draw_thumbnail_option(app, option_id, label, path) {
+            picked = Some(Some(path.clone()));
+        }
+        app.context.canvas.set_clip_rect(options_layout);
+    }
+    app.context.canvas.set_clip_rect(None);
+
+    let button_style = Style::new(color_hex(0x909090), color_hex(0x202020))
+        .bg_hover_color(color_hex(0x404040))
+        .font_info(PLAY_BUTTON_FONT_INFO);
+    let (browse_layout, automatic_layout) = buttons_layout.pad_outer(15, 5).split_vert(1, 2);
+    if draw_button(
+        &mut app.context,
+        "Browse...",
+        button_style.clone(),
+        browse_layout.pad_right(5),
+    ) {
+        let new_path = native_dialog::FileDialog::new()
+            .add_filter("Image", &["png", "jpg", "gif", "svg"])
+            .show_open_single_file()
+            .expect("Failed to open native file picker");
+        if let Some(new_path) = new_path {
+            picked = Some(Some(new_path.to_string_lossy().to_string()));
+        }
+    }
+    if draw_button(
+        &mut app.context,
+        "Automatic",
+        button_style,
+        automatic_layout.pad_left(5),
+    ) {
+        picked = Some(None);
+    }
+
+    if let Some(path) = picked {
+        let automatic = path.is_none();
+        anime.pin_thumbnail(path);
+        if automatic {
+            if let Err(e) = app.database.retrieve_images(&app.thumbnail_path) {
+                eprintln!("ERROR:failed to retrieve thumbnails:{e}");
+            }
+        }
+        app.main_state.thumbnail_anime = None;
+    } else if app.context.click_elem(outer_bounds_id) {
+        app.main_state.thumbnail_anime = None;
+    }
+}
+
+/// Lists entries changed both locally and on a tracker since the last sync, the user keeps one
+/// side of each.
+fn draw_sync_conflicts(app: &mut App, layout: Layout) {
+    let outer_bounds_id = app.context.create_id(app.context.window_rect());
+    let _inner_bounds_id = app.context.create_id(layout);
+
+    app.context.canvas.set_draw_color(color_hex(0x101010));
+    app.context.canvas.fill_rect(layout).unwrap();
+
+    let (_, text_height) = text_size(&mut app.context.text_manager, TAG_FONT_INFO, "L");
+    let row_height = text_height * 4 + 20;
+    let (header_layout, mut list_layout) = layout.split_hori(text_height * 2 + 10, layout.height());
+    let header_layout = header_layout.pad_outer(15, 5);
+    draw_text(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        TITLE_FONT_INFO,
+        "Sync conflicts",
+        color_hex(TITLE_FONT_COLOR),
+        header_layout.x,
+        header_layout.y + (header_layout.height() as i32 - text_height as i32) / 2,
+        None,
+        None,
+    );
+
+    app.context.canvas.set_clip_rect(list_layout);
+    register_scroll(
+        &mut app.context,
+        &mut app.main_state.conflict_scroll,
+        &mut list_layout,
+    );
+    let scroll = app.main_state.conflict_scroll.scroll;
+    let conflicts = app.database.conflicts().to_vec();
+    let row_layouts = list_layout
+        .scroll_y(scroll)
+        .split_even_hori(row_height)
+        .take(conflicts.len())
+        .collect::<Box<[Rect]>>();
+    app.main_state.conflict_scroll.max_scroll = row_layouts
+        .last()
+        .map_or(0, |last| last.bottom() - scroll - list_layout.y());
+
+    let button_style = Style::new(color_hex(0x909090), color_hex(0x202020))
+        .bg_hover_color(color_hex(0x404040))
+        .font_info(TAG_FONT_INFO);
+    let (button_width, _) = text_size(
+        &mut app.context.text_manager,
+        TAG_FONT_INFO,
+        "Keep MyAnimeList",
+    );
+    let mut resolved = None;
+    for (conflict, row_layout) in conflicts.iter().zip(row_layouts.iter().copied()) {
+        if row_layout.bottom() < list_layout.top() {
+            continue;
+        }
+        if row_layout.top() > list_layout.bottom() {
+            break;
+        }
+        let row_layout = row_layout.pad_outer(15, 5);
+        let (text_layout, buttons_layout) =
+            row_layout.split_vert(row_layout.width() - button_width - 30, row_layout.width());
+        let lines = [
+            (conflict.title.clone(), TITLE_FONT_COLOR),
+            (format!("Local: {}", conflict.local), 0xa0a0a0),
+            (
+                format!("{}: {}", conflict.tracker.name(), conflict.remote),
+                0xa0a0a0,
+            ),
+        ];
+        for (i, (line, color)) in lines.iter().enumerate() {
+            draw_text(
+                &mut app.context.canvas,
+                &mut app.context.text_manager,
+                TAG_FONT_INFO,
+                line,
+                color_hex(*color),
+                text_layout.x,
+                text_layout.y + (i as u32 * (text_height + 4)) as i32,
+                Some(text_layout.width()),
+                Some(text_height),
+            );
+            app.context.canvas.set_clip_rect(list_layout);
+        }
+        let (local_layout, remote_layout) = buttons_layout.split_hori(1, 2);
+        if draw_button(
+            &mut app.context,
+            "Keep local",
+            button_style.clone(),
+            local_layout.pad_bottom(3),
+        ) {
+            resolved = Some((conflict.tracker, conflict.media_id, true));
+        }
+        if draw_button(
+            &mut app.context,
+            &format!("Keep {}", conflict.tracker.name()),
+            button_style.clone(),
+            remote_layout.pad_top(3),
+        ) {
+            resolved = Some((conflict.tracker, conflict.media_id, false));
+        }
+        app.context.canvas.set_clip_rect(list_layout);
+    }
+    app.context.canvas.set_clip_rect(None);
+
+    if let Some((tracker, media_id, keep_local)) = resolved {
+        app.database.resolve_conflict(tracker, media_id, keep_local);
+        if app.database.conflicts().is_empty() {
+            app.main_state.show_conflicts = false;
+        }
+    } else if app.context.click_elem(outer_bounds_id) {
+        app.main_state.show_conflicts = false;
+    }
+}
+
+/// What importing a list export would do, imported once the user confirms.
+fn draw_list_import(app: &mut App, layout: Layout) {
+    let outer_bounds_id = app.context.create_id(app.context.window_rect());
+    let _inner_bounds_id = app.context.create_id(layout);
+    let Some((entries, report)) = app.main_state.list_import.clone() else {
+        return;
+    };
+
+    app.context.canvas.set_draw_color(color_hex(0x101010));
+    app.context.canvas.fill_rect(layout).unwrap();
+
+    let (_, text_height) = text_size(&mut app.context.text_manager, TAG_FONT_INFO, "L");
+    let row_height = text_height + 4;
+    let (header_layout, rest) = layout.split_hori(text_height * 4 + 10, layout.height());
+    let (mut list_layout, buttons_layout) =
+        rest.split_hori(rest.height() - (text_height * 2 + 20), rest.height());
+    let header_layout = header_layout.pad_outer(15, 5);
+    draw_text(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        TITLE_FONT_INFO,
+        "Import list",
+        color_hex(TITLE_FONT_COLOR),
+        header_layout.x,
+        header_layout.y,
+        None,
+        None,
+    );
+    let summary = match report.unmatched.len() {
+        0 => format!("All {} entries match an anime", entries.len()),
+        _ => format!(
+            "{} of {} entries match an anime, no anime found for:",
+            report.matched,
+            entries.len()
+        ),
+    };
+    draw_text(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        TAG_FONT_INFO,
+        &summary,
+        color_hex(0xa0a0a0),
+        header_layout.x,
+        header_layout.bottom() - text_height as i32,
+        Some(header_layout.width()),
+        Some(text_height),
+    );
+
+    app.context.canvas.set_clip_rect(list_layout);
+    register_scroll(
+        &mut app.context,
+        &mut app.main_state.list_import_scroll,
+        &mut list_layout,
+    );
+    let scroll = app.main_state.list_import_scroll.scroll;
+    let row_layouts = list_layout
+        .scroll_y(scroll)
+        .split_even_hori(row_height)
+        .take(report.unmatched.len())
+        .collect::<Box<[Rect]>>();
+    app.main_state.list_import_scroll.max_scroll = row_layouts
+        .last()
+        .map_or(0, |last| last.bottom() - scroll - list_layout.y());
+    for (entry, row_layout) in report.unmatched.iter().zip(row_layouts.iter().copied()) {
+        if row_layout.bottom() < list_layout.top() {
+            continue;
+        }
+        if row_layout.top() > list_layout.bottom() {
+            break;
+        }
+        let row_layout = row_layout.pad_left(15).pad_right(15);
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            TAG_FONT_INFO,
+            entry.to_string(),
+            color_hex(0x909090),
+            row_layout.x,
+            row_layout.y,
+            Some(row_layout.width()),
+            Some(text_height),
+        );
+        app.context.canvas.set_clip_rect(list_layout);
+    }
+    app.context.canvas.set_clip_rect(None);
+
+    let button_style = Style::new(color_hex(0x909090), color_hex(0x202020))
+        .bg_hover_color(color_hex(0x404040))
+        .font_info(PLAY_BUTTON_FONT_INFO);
+    let (import_layout, cancel_layout) = buttons_layout.pad_outer(15, 10).split_vert(1, 2);
+    if draw_button(
+        &mut app.context,
+        "Import",
+        button_style.clone(),
+        import_layout.pad_right(5),
+    ) {
+        app.database.import_list(&entries, false);
+        app.main_state.list_import = None;
+    } else if draw_button(
+        &mut app.context,
+        "Cancel",
+        button_style,
+        cancel_layout.pad_left(5),
+    ) || app.context.click_elem(outer_bounds_id)
+    {
+        app.main_state.list_import = None;
+    }
+}
+
+/// Opens the profile menu, or closes it.
+pub fn toggle_profiles(app: &mut App) {
+    match app.main_state.profiles.take() {
+        Some(_) => app.context.input_util.stop(),
+        None => {
+            app.main_state.profiles = Some(Textbox::default());
+            // Focused once drawn
+            app.context.textbox_id = None;
+            app.context.text.clear();
+        }
+    }
+}
+
+/// Profiles to switch to, and a textbox to create a new one with.
+fn draw_profiles(app: &mut App, layout: Layout) {
+    let outer_bounds_id = app.context.create_id(app.context.window_rect());
+    let _inner_bounds_id = app.context.create_id(layout);
+
+    app.context.canvas.set_draw_color(color_hex(0x101010));
+    app.context.canvas.fill_rect(layout).unwrap();
+
+    let (_, text_height) = text_size(&mut app.context.text_manager, TAG_FONT_INFO, "L");
+    let row_height = text_height + 20;
+    let (header_layout, rest) = layout.split_hori(text_height * 2 + 10, layout.height());
+    let input_height = app.context.text_manager.font_height(INPUT_BOX_FONT_INFO) * 2 + 40;
+    let (list_layout, mut input_layout) =
+        rest.split_hori(rest.height().saturating_sub(input_height), rest.height());
+    let header_layout = header_layout.pad_outer(15, 5);
+    draw_text(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        TITLE_FONT_INFO,
+        "Profiles",
+        color_hex(TITLE_FONT_COLOR),
+        header_layout.x,
+        header_layout.y + (header_layout.height() as i32 - text_height as i32) / 2,
+        None,
+        None,
+    );
+
+    let button_style = Style::new(color_hex(0x909090), color_hex(0x202020))
+        .bg_hover_color(color_hex(0x404040))
+        .font_info(TAG_FONT_INFO);
+    let active = app.database.profile().to_string();
+    let names = app
+        .database
+        .profile_names()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let mut switch_to = None;
+    app.context.canvas.set_clip_rect(list_layout);
+    for (name, row_layout) in names.iter().zip(list_layout.split_even_hori(row_height)) {
+        if row_layout.top() > list_layout.bottom() {
+            break;
+        }
+        let style = match *name == active {
+            true => Style::new(color_hex(0xD0D0D0), color_hex(0x2E5E3A))
+                .bg_hover_color(color_hex(0x3E6E4A))
+                .font_info(TAG_FONT_INFO),
+            false => button_style.clone(),
+        };
+        if draw_button(&mut app.context, name, style, row_layout.pad_outer(15, 3)) {
+            switch_to = Some(name.clone());
+        }
+    }
+    app.context.canvas.set_clip_rect(None);
+
+    let Some(textbox_state) = app.main_state.profiles.as_mut() else {
+        return;
+    };
+    let focus = app.context.textbox_id.is_none();
+    let submit = textbox(
+        &mut app.context,
+        textbox_state,
+        Some("New profile:"),
+        true,
+        15,
+        &mut input_layout,
+    );
+    if focus {
+        app.context.textbox_id = Some(textbox_state.id);
+    }
+    let name = textbox_state.text.trim().to_string();
+    if !name.is_empty() && !database::profile::is_valid_name(&name) {
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            TAG_FONT_INFO,
+            "Letters, digits, '-' and '_' only",
+            color_hex(TAG_EXCLUDE_COLOR),
+            input_layout.x + 15,
+            input_layout.y,
+            Some(input_layout.width().saturating_sub(30)),
+            Some(text_height),
+        );
+    } else if submit && !name.is_empty() {
+        switch_to = Some(name);
+    }
+
+    if let Some(name) = switch_to {
+        app.main_state.profiles = None;
+        app.context.input_util.stop();
+        switch_profile(app, &name);
+    } else if app.context.click_elem(outer_bounds_id) {
+        app.main_state.profiles = None;
+        app.context.input_util.stop();
+    }
+}
+
 fn draw_option(app: &mut App, option_id: usize, option: &str) -> (bool, bool) {
     let layout = app.context.rect_id(option_id);
     let font_info = INPUT_BOX_FONT_INFO;
@@ -273,9 +812,179 @@ fn draw_option(app: &mut App, option_id: usize, option: &str) -> (bool, bool) {
     )
 }
 
+/// Order of the cards, and the lowest score shown. Left click on the score raises it and right
+/// click lowers it.
+fn draw_library_options(app: &mut App, layout: Layout) {
+    let (_, text_height) = text_size(&mut app.context.text_manager, TAG_FONT_INFO, "L");
+    let score_format = app.database.score_format();
+    let min_score = app.database.min_score();
+    let order = app.database.library_order();
+    let order_text = format!("Sort: {}", order.as_str());
+    let score_text = match min_score {
+        0 => "Score: Any".to_string(),
+        score => format!("Score: {} and up", score_format.format(score)),
+    };
+
+    let mut clicks = [(false, false); 2];
+    let row_layouts = layout.split_even_hori(text_height + 12);
+    for ((row_layout, text), clicks) in row_layouts.zip([order_text, score_text]).zip(&mut clicks) {
+        let id = app.context.create_id(row_layout);
+        app.main_state.selectable.insert(id);
+        if app.context.state_id(id) {
+            app.context.canvas.set_draw_color(color_hex(0x303030));
+            app.context.canvas.fill_rect(row_layout).unwrap();
+        }
+        *clicks = (app.context.click_elem(id), app.context.click_elem_right(id));
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            TAG_FONT_INFO,
+            text,
+            color_hex(0xa0a0a0),
+            row_layout.x + 10,
+            row_layout.y + (row_layout.height() as i32 - text_height as i32) / 2,
+            None,
+            None,
+        );
+    }
+
+    let [(order_left, order_right), (score_left, score_right)] = clicks;
+    if order_left || order_right {
+        app.database.set_library_order(match order {
+            LibraryOrder::LastWatched => LibraryOrder::Score,
+            LibraryOrder::Score => LibraryOrder::LastWatched,
+        });
+    } else if score_left {
+        app.database
+            .set_min_score(score_format.increment(min_score));
+    } else if score_right {
+        app.database
+            .set_min_score(score_format.decrement(min_score));
+    } else {
+        return;
+    }
+    app.main_state.scroll.scroll = 0;
+    app.main_state.selected = None;
+    app.main_state.extra_menu_id = None;
+}
+
+/// Left click includes a tag, right click excludes it. Clicking again resets the tag.
+fn draw_tag_sidebar(app: &mut App, layout: Layout) {
+    app.context.canvas.set_draw_color(color_hex(0x141414));
+    app.context.canvas.fill_rect(layout).unwrap();
+
+    let (_, text_height) = text_size(&mut app.context.text_manager, TAG_FONT_INFO, "L");
+    let row_height = text_height + 12;
+    let (options_layout, layout) = layout.split_hori(row_height * 2 + 10, layout.height());
+    draw_library_options(app, options_layout.pad_top(10));
+    let (header_layout, mut list_layout) = layout.split_hori(row_height + 10, layout.height());
+    let header_layout = header_layout.pad_outer(10, 5);
+    if app.database.tag_filter().is_empty() {
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            TAG_FONT_INFO,
+            "Tags",
+            color_hex(TITLE_FONT_COLOR),
+            header_layout.x,
+            header_layout.y + (header_layout.height() as i32 - text_height as i32) / 2,
+            None,
+            None,
+        );
+    } else if draw_button(
+        &mut app.context,
+        "Clear tags",
+        Style::new(color_hex(0x909090), color_hex(0x202020))
+            .bg_hover_color(color_hex(0x404040))
+            .font_info(TAG_FONT_INFO),
+        header_layout,
+    ) {
+        app.database.update_tag_filter(|filter| filter.clear());
+        app.main_state.scroll.scroll = 0;
+    }
+
+    app.context.canvas.set_clip_rect(list_layout);
+    register_scroll(
+        &mut app.context,
+        &mut app.main_state.tag_scroll,
+        &mut list_layout,
+    );
+    let scroll = app.main_state.tag_scroll.scroll;
+    let row_layouts = list_layout
+        .scroll_y(scroll)
+        .split_even_hori(row_height)
+        .take(app.database.tag_counts().len())
+        .collect::<Box<[Rect]>>();
+    app.main_state.tag_scroll.max_scroll = row_layouts
+        .last()
+        .map_or(0, |last| last.bottom() - scroll - list_layout.y());
+
+    let mut toggled = None;
+    for (i, row_layout) in row_layouts.iter().copied().enumerate() {
+        if row_layout.bottom() < list_layout.top() {
+            continue;
+        }
+        if row_layout.top() > list_layout.bottom() {
+            break;
+        }
+        let (tag, count) = &app.database.tag_counts()[i];
+        let color = match app.database.tag_filter().state(tag) {
+            TagState::Include => TAG_INCLUDE_COLOR,
+            TagState::Exclude => TAG_EXCLUDE_COLOR,
+            TagState::None => 0xa0a0a0,
+        };
+        let text = format!("{tag} ({count})");
+        let id = app.context.create_id(row_layout);
+        app.main_state.selectable.insert(id);
+        if app.context.state_id(id) {
+            app.context.canvas.set_draw_color(color_hex(0x303030));
+            app.context.canvas.fill_rect(row_layout).unwrap();
+        }
+        if app.context.click_elem(id) {
+            toggled = Some((i, TagState::Include));
+        } else if app.context.click_elem_right(id) {
+            toggled = Some((i, TagState::Exclude));
+        }
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            TAG_FONT_INFO,
+            text,
+            color_hex(color),
+            row_layout.x + 10,
+            row_layout.y + (row_layout.height() as i32 - text_height as i32) / 2,
+            None,
+            None,
+        );
+    }
+    app.context.canvas.set_clip_rect(None);
+
+    if let Some((i, state)) = toggled {
+        let tag = app.database.tag_counts()[i].0.clone();
+        app.database.update_tag_filter(|filter| match state {
+            TagState::Include => filter.toggle_include(&tag),
+            _ => filter.toggle_exclude(&tag),
+        });
+        app.main_state.scroll.scroll = 0;
+        app.main_state.selected = None;
+        app.main_state.extra_menu_id = None;
+    }
+}
+
 pub fn draw_main(app: &mut App, layout: Layout) {
     let (window_width, window_height) = app.context.canvas.window().size();
+    let (tag_layout, layout) = if app.main_state.show_tags {
+        let width = TAG_SIDEBAR_WIDTH.min(layout.width() / 2);
+        let (tag_layout, layout) = layout.split_vert(width, layout.width());
+        (Some(tag_layout), layout)
+    } else {
+        (None, layout)
+    };
     let mut card_layouts = layout;
+    if app.main_state.library_search.is_some() {
+        card_layouts = card_layouts.pad_top(CARD_Y_PAD_OUTER);
+        draw_library_search(app, &mut card_layouts);
+    }
     let scroll = &mut app.main_state.scroll;
     register_scroll(&mut app.context, scroll, &mut card_layouts);
 
@@ -284,10 +993,26 @@ pub fn draw_main(app: &mut App, layout: Layout) {
         .pad_bottom(CARD_Y_PAD_OUTER)
         .scroll_y(app.main_state.scroll.scroll)
         .split_grid_center(CARD_WIDTH, CARD_HEIGHT, CARD_X_PAD_INNER, CARD_Y_PAD_INNER);
-    let card_layouts = card_layouts.take(app.database.len()).collect::<Vec<_>>();
+    // Placeholders are not in the library, so they are hidden while filtering
+    let remote_len = match app.database.is_filtered() {
+        true => 0,
+        false => app.database.remote_entries().len(),
+    };
+    let card_layouts = card_layouts
+        .take(app.database.len() + remote_len)
+        .collect::<Vec<_>>();
 
-    if app.main_state.search_anime.is_none() && app.main_state.alias_anime.is_none() {
-        handle_main_events(app);
+    if app.main_state.search_anime.is_none()
+        && app.main_state.alias_anime.is_none()
+        && app.main_state.thumbnail_anime.is_none()
+        && !app.main_state.show_conflicts
+        && app.main_state.list_import.is_none()
+        && app.main_state.profiles.is_none()
+    {
+        match app.main_state.library_search {
+            Some(_) => handle_library_search_events(app),
+            None => handle_main_events(app),
+        }
     } else {
         handle_main_search_events(app);
     }
@@ -310,10 +1035,41 @@ pub fn draw_main(app: &mut App, layout: Layout) {
         }
     }
 
+    let mut link = None;
+    for (idx, grid_space) in card_layouts.iter().skip(anime_list.len()).enumerate() {
+        if grid_space.y + grid_space.height() as i32 > 0 {
+            if grid_space.y > window_height as i32 {
+                break;
+            }
+            if draw_placeholder_card(app, idx, *grid_space) {
+                link = Some(app.database.remote_entries()[idx].media_id);
+            }
+        }
+    }
+    if let Some(media_id) = link {
+        let path = native_dialog::FileDialog::new()
+            .show_open_single_dir()
+            .expect("Failed to open native file picker");
+        if let Some(path) = path {
+            match app.database.link_remote(media_id, &path) {
+                Ok(()) => {
+                    if let Err(e) = app.database.retrieve_images(&app.thumbnail_path) {
+                        eprintln!("ERROR:failed to retrieve thumbnails:{e}");
+                    }
+                }
+                Err(e) => eprintln!("ERROR:failed to link {}:{e}", path.display()),
+            }
+        }
+    }
+
     if !any {
         app.main_state.selected = None;
     }
 
+    if let Some(tag_layout) = tag_layout {
+        draw_tag_sidebar(app, tag_layout);
+    }
+
     // Draw search
     if let Some(search_id) = app.main_state.search_anime {
         let width = window_width * 4 / 5;
@@ -325,6 +1081,46 @@ pub fn draw_main(app: &mut App, layout: Layout) {
         draw_main_anime_search(app, float_layout, search_id);
     }
 
+    // Draw thumbnail picker
+    if let Some(thumbnail_id) = app.main_state.thumbnail_anime {
+        let width = window_width * 3 / 5;
+        let height = window_height * 3 / 5;
+        let x = (window_width - width) / 2;
+        let y = (window_height - height) / 2;
+        let float_layout = Layout::new(x as i32, y as i32, width, height);
+        draw_main_anime_thumbnail(app, float_layout, thumbnail_id);
+    }
+
+    // Draw sync conflicts
+    if app.main_state.show_conflicts {
+        let width = window_width * 3 / 5;
+        let height = window_height * 3 / 5;
+        let x = (window_width - width) / 2;
+        let y = (window_height - height) / 2;
+        let float_layout = Layout::new(x as i32, y as i32, width, height);
+        draw_sync_conflicts(app, float_layout);
+    }
+
+    // Draw list import preview
+    if app.main_state.list_import.is_some() {
+        let width = window_width * 3 / 5;
+        let height = window_height * 3 / 5;
+        let x = (window_width - width) / 2;
+        let y = (window_height - height) / 2;
+        let float_layout = Layout::new(x as i32, y as i32, width, height);
+        draw_list_import(app, float_layout);
+    }
+
+    // Draw profile menu
+    if app.main_state.profiles.is_some() {
+        let width = window_width * 2 / 5;
+        let height = window_height * 3 / 5;
+        let x = (window_width - width) / 2;
+        let y = (window_height - height) / 2;
+        let float_layout = Layout::new(x as i32, y as i32, width, height);
+        draw_profiles(app, float_layout);
+    }
+
     // Draw alias
     if let Some(alias_id) = app.main_state.alias_anime {
         //let (_, text_height) = app.text_manager.text_size(BACK_BUTTON_FONT_INFO, "");
@@ -370,10 +1166,131 @@ fn draw_thumbnail(app: &mut App, anime: &database::Anime, layout: Layout) {
     draw_gradient(app, layout, rad, gradient);
 }
 
+/// Draws `text` in the top left corner of `layout`, returning where it was drawn.
+fn draw_badge(app: &mut App, text: &str, bg_color: u32, layout: Layout) -> Layout {
+    let (text_width, text_height) = text_size(&mut app.context.text_manager, BADGE_FONT_INFO, text);
+    let badge_layout = rect!(
+        layout.x() + 8,
+        layout.y() + 8,
+        text_width + BADGE_PAD * 2,
+        text_height + BADGE_PAD
+    );
+    app.context.canvas.set_blend_mode(BlendMode::Blend);
+    app.context
+        .canvas
+        .rounded_box(
+            badge_layout.left() as i16,
+            badge_layout.top() as i16,
+            badge_layout.right() as i16,
+            badge_layout.bottom() as i16,
+            CARD_RAD / 2,
+            color_hex_a(bg_color),
+        )
+        .unwrap();
+    draw_text_centered(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        BADGE_FONT_INFO,
+        text,
+        color_hex(TITLE_FONT_COLOR),
+        badge_layout.x + badge_layout.width() as i32 / 2,
+        badge_layout.y + badge_layout.height() as i32 / 2,
+        None,
+        None,
+    );
+    badge_layout
+}
+
+/// Card of an entry on the AniList list with nothing on disk. Returns whether linking it to a
+/// folder was clicked.
+fn draw_placeholder_card(app: &mut App, idx: usize, layout: Layout) -> bool {
+    let entry = &app.database.remote_entries()[idx];
+    let (title, label) = (entry.title.clone(), entry.label());
+    let thumbnail = entry.thumbnail().map(str::to_owned);
+
+    let gradient = Some(0);
+    let rad = Some(CARD_RAD);
+    let drawn =
+        thumbnail.is_some_and(|path| draw_image_clip(app, &path, layout, rad, gradient).is_ok());
+    if !drawn {
+        draw_missing_thumbnail(app, layout, rad);
+        draw_gradient(app, layout, rad, gradient);
+    }
+    app.context.canvas.set_blend_mode(BlendMode::Blend);
+    app.context
+        .canvas
+        .rounded_box(
+            layout.left() as i16,
+            layout.top() as i16,
+            layout.right() as i16,
+            layout.bottom() as i16,
+            CARD_RAD,
+            color_hex_a(PLACEHOLDER_DIM_COLOR),
+        )
+        .unwrap();
+    draw_badge(app, &label, BADGE_BG_COLOR, layout);
+
+    // Not cached in `string_manager`, as entries are replaced on every list fetch
+    let max_width = layout.width() - 35;
+    let mut title = title;
+    if text_size(&mut app.context.text_manager, TITLE_FONT_INFO, &title).0 > max_width {
+        while text_size(
+            &mut app.context.text_manager,
+            TITLE_FONT_INFO,
+            format!("{title}..."),
+        )
+        .0 > max_width
+        {
+            title.pop();
+        }
+        title.push_str("...");
+    }
+    let (_, text_height) = text_size(&mut app.context.text_manager, TITLE_FONT_INFO, &title);
+    let (_, text_layout) = layout.split_hori(layout.height() - text_height - 19, layout.height());
+    draw_text_centered(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        TITLE_FONT_INFO,
+        &title,
+        color_hex(TITLE_FONT_COLOR),
+        text_layout.x + text_layout.width() as i32 / 2,
+        text_layout.y + text_layout.height() as i32 / 2,
+        None,
+        None,
+    );
+
+    if !layout.contains_point(app.mouse_points())
+        || app.main_state.search_anime.is_some()
+        || app.main_state.alias_anime.is_some()
+        || app.main_state.thumbnail_anime.is_some()
+        || app.main_state.show_conflicts
+        || app.main_state.list_import.is_some()
+        || app.main_state.profiles.is_some()
+    {
+        return false;
+    }
+    let (_, link_layout) = layout.split_hori(1, 3);
+    let (link_layout, _) = link_layout.split_hori(1, 2);
+    let link_style = Style::new(color_hex(0x909090), color_hex(0x202020))
+        .bg_hover_color(color_hex(0x404040))
+        .font_info(PLAY_BUTTON_FONT_INFO);
+    draw_button(
+        &mut app.context,
+        "Link folder...",
+        link_style,
+        link_layout.pad_outer(10, 10),
+    )
+}
+
 fn is_card_selected(app: &mut App, layout: Layout, idx: usize) -> bool {
     ((!app.main_state.keyboard_override && layout.contains_point(app.mouse_points()))
         || (app.main_state.keyboard_override && app.main_state.selected.is_some_and(|i| i == idx)))
-        && (app.main_state.search_anime.is_none() && app.main_state.alias_anime.is_none())
+        && (app.main_state.search_anime.is_none()
+            && app.main_state.alias_anime.is_none()
+            && app.main_state.thumbnail_anime.is_none()
+            && !app.main_state.show_conflicts
+            && app.main_state.list_import.is_none()
+            && app.main_state.profiles.is_none())
 }
 
 fn draw_card_extra_menu(
@@ -435,13 +1352,15 @@ fn draw_card_extra_menu(
         change_image_layout,
     ) {
         clicked = true;
-        let new_path = native_dialog::FileDialog::new()
-            .add_filter("Image", &["png", "jpg", "gif", "svg"])
-            .show_open_single_file()
-            .expect("Failed to open native file picker");
-        if let Some(new_path) = new_path {
-            anime.set_thumbnail(Some(new_path.to_string_lossy().to_string()));
+        let patterns = app.database.artwork_patterns().to_vec();
+        anime.update_local_artwork(&patterns);
+        if let Some(url) = anime.metadata().as_ref().map(|m| m.thumbnail()) {
+            let path = thumbnail::cache_path(&app.thumbnail_path, url);
+            if !url.is_empty() && !std::path::Path::new(&path).exists() {
+                app.database.request_thumbnail(url, &path);
+            }
         }
+        app.main_state.thumbnail_anime = Some(idx as u32);
     }
 
     if draw_button(
@@ -472,7 +1391,9 @@ fn draw_card_hover_menu(app: &mut App, anime: &mut database::Anime, layout: Layo
         .bg_hover_color(color_hex(0x404040))
         .font_info(PLAY_BUTTON_FONT_INFO);
 
-    let (current_ep, current_path) = anime.current_episode_path();
+    let current_ep = anime.current_episode();
+    // Shift plays without marking the episode as watched
+    let mark_watched = !app.context.keymod.contains(Mod::LSHIFTMOD);
 
     if draw_button(
         &mut app.context,
@@ -481,16 +1402,19 @@ fn draw_card_hover_menu(app: &mut App, anime: &mut database::Anime, layout: Layo
         play_current_layout,
     ) {
         clicked = true;
-        open_video(&current_path[0], anime);
-
-        if !app.context.keymod.contains(Mod::LSHIFTMOD) {
-            let access_token = app.database.anilist_access_token().map(|v| v.to_string());
-            update_watched(&app.http_tx, access_token, anime, &current_ep);
+        let sync_queue = app.database.sync_queue();
+        if play_episode(
+            &mut app.player,
+            sync_queue,
+            anime,
+            &current_ep,
+            mark_watched,
+        ) {
             app.main_state.scroll.scroll = 0;
         }
     }
 
-    if let Some((ep, path)) = anime.next_episode_path().unwrap() {
+    if let Some((ep, _)) = anime.next_episode_path().unwrap() {
         if draw_button(
             &mut app.context,
             &format!("Play Next: {}", ep),
@@ -498,11 +1422,8 @@ fn draw_card_hover_menu(app: &mut App, anime: &mut database::Anime, layout: Layo
             play_next_layout,
         ) {
             clicked = true;
-            open_video(&path[0], anime);
-
-            if !app.context.keymod.contains(Mod::LSHIFTMOD) {
-                let access_token = app.database.anilist_access_token().map(|v| v.to_string());
-                update_watched(&app.http_tx, access_token, anime, &ep);
+            let sync_queue = app.database.sync_queue();
+            if play_episode(&mut app.player, sync_queue, anime, &ep, mark_watched) {
                 app.main_state.scroll.scroll = 0;
             }
         }
@@ -528,10 +1449,25 @@ fn draw_card(app: &mut App, anime: &mut database::Anime, idx: usize, layout: Lay
 
     // draw thumbnail
     draw_thumbnail(app, anime, image_layout);
+    let mut badge_layout = image_layout;
+    if app.database.sequel(anime).is_some() {
+        let sequel_layout = draw_badge(app, "Sequel available", BADGE_BG_COLOR, badge_layout);
+        badge_layout.set_y(sequel_layout.bottom());
+    }
+    let time = database::get_time();
+    if let Some(episode) = anime.aired_not_downloaded(time) {
+        let text = format!("Ep {episode} not downloaded");
+        draw_badge(app, &text, BADGE_MISSING_BG_COLOR, badge_layout);
+    } else if let Some(next) = anime.next_airing(time) {
+        let countdown = format_countdown(next.airing_at - time);
+        let text = format!("Ep {} in {countdown}", next.episode);
+        draw_badge(app, &text, BADGE_BG_COLOR, badge_layout);
+    }
 
     if app.context.click_elem(card_id) {
         let idx = app.database.cache_idx_to_map_idx(idx);
         app.episode_state.episode_scroll.scroll = 0;
+        app.episode_state.franchise = None;
         app.main_state.alias_anime = None;
         app.main_state.search_anime = None;
         app.next_screen = Some(Screen::SelectEpisode(idx));
@@ -589,6 +1525,10 @@ fn draw_card(app: &mut App, anime: &mut database::Anime, idx: usize, layout: Lay
     if layout.contains_point(app.mouse_points())
         && app.main_state.search_anime.is_none()
         && app.main_state.alias_anime.is_none()
+        && app.main_state.thumbnail_anime.is_none()
+        && !app.main_state.show_conflicts
+        && app.main_state.list_import.is_none()
+        && app.main_state.profiles.is_none()
     {
         app.context.canvas.set_blend_mode(BlendMode::Blend);
         selected = true;
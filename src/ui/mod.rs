@@ -1,25 +1,27 @@
+mod airing_screen;
 mod attach_flag_screen;
 mod episode_screen;
 pub mod layout;
 mod login_screen;
 mod main_screen;
+mod unlock_screen;
 
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::ops::Sub;
 use std::rc::Rc;
 
+use crate::anilist_serde::AniListError;
 use crate::database;
-use crate::database::episode::Episode;
+use crate::database::list_import::parse_list;
+use crate::database::sync_queue::{SyncQueue, Tracker};
 use crate::database::AnimeMapIdx;
 use crate::database::Database;
-use crate::send_request;
+use crate::save_credentials;
 use crate::App;
 use crate::BindFlag;
 use crate::ConnectionOverlayState;
 use crate::Context;
-use crate::HttpSender;
-use crate::RequestKind;
 use crate::SingleFlag;
 use crate::CONNECTION_OVERLAY_TIMEOUT;
 use crate::DEFAULT_VIDEO_PLAYER;
@@ -41,14 +43,18 @@ use sdl2::ttf::Sdl2TtfContext;
 use sdl2::video::Window;
 use sdl2::video::WindowContext;
 
+use self::airing_screen::draw_airing;
 use self::attach_flag_screen::draw_attach_flag;
 use self::episode_screen::draw_anime_expand;
 use self::episode_screen::DESCRIPTION_FONT_INFO;
 use self::layout::Layout as _;
 use self::login_screen::draw_login;
 use self::main_screen::draw_main;
+use self::main_screen::toggle_library_search;
+use self::main_screen::toggle_profiles;
 use self::main_screen::CARD_HEIGHT;
 use self::main_screen::CARD_WIDTH;
+use self::unlock_screen::draw_unlock;
 
 use sdl2::image::ImageRWops;
 use sdl2::rect::Rect;
@@ -561,8 +567,12 @@ impl<'a> TextureManager<'a> {
 pub enum Screen {
     Main,
     Login,
+    /// Asks for the passphrase of the credentials when there is no keyring
+    Unlock,
     SelectEpisode(AnimeMapIdx),
     AttachFlag(AnimeMapIdx),
+    /// Episodes airing this week
+    Airing,
 }
 
 fn rgb_hex(hex: u32) -> (u8, u8, u8) {
@@ -590,22 +600,11 @@ fn color_hex_a_test_0() {
     assert_eq!(color_hex_a(0xDEADBEEF), Color::RGBA(0xDE, 0xAD, 0xBE, 0xEF));
 }
 
-pub fn update_anilist_watched(tx: &HttpSender, access_token: &str, anime: &mut database::Anime) {
-    if let Some(media_id) = anime.anilist_id() {
-        if let Episode::Numbered { episode, .. } = anime.current_episode() {
-            let access_token = access_token.to_string();
-            let ptr_id = anime.as_ptr_id();
-            let access_token = access_token.to_string();
-            dbg!(anime.title());
-            send_request(
-                tx,
-                RequestKind::UpdateMedia {
-                    access_token,
-                    media_id,
-                    episode,
-                    ptr_id,
-                },
-            );
+/// Queues the anime's progress and list entry to be sent to the trackers that are logged in.
+pub fn update_trackers_watched(queue: &mut SyncQueue, anime: &database::Anime) {
+    for tracker in queue.trackers().to_vec() {
+        if let Some(change) = anime.pending_change(tracker) {
+            queue.push(change);
         }
     }
 }
@@ -941,6 +940,51 @@ fn draw_connection_overlay_disconnected(app: &mut App) {
     );
 }
 
+/// Drawn above the connection overlay when both are shown. Clicking dismisses it, or for an
+/// expired login, goes to log in again.
+fn draw_error_overlay(app: &mut App) {
+    let Some(error) = app.error_overlay.error.clone() else {
+        return;
+    };
+    let (text, bg_color) = match &error {
+        AniListError::InvalidToken => (
+            "AniList login expired; click to log in again".to_string(),
+            0x803030,
+        ),
+        AniListError::NotFound => ("Not found on AniList".to_string(), 0x605020),
+        AniListError::RateLimited(secs) => (
+            format!("AniList rate limit reached; retrying in {secs}s"),
+            0x805800,
+        ),
+        AniListError::ServerError(_) => (
+            "AniList is having problems; retrying later".to_string(),
+            0x603060,
+        ),
+        AniListError::Other(message) => (format!("AniList error: {message}"), 0x404040),
+    };
+    let (_, text_height) = app
+        .context
+        .text_manager
+        .text_size(CONNECTION_FONT_INFO, &text);
+    let (width, height) = app.context.canvas.window().size();
+    let bottom = match app.connection_overlay.timeout > 0.0 {
+        true => height - text_height,
+        false => height,
+    };
+    let layout = Layout::new(0, (bottom - text_height) as i32, width, text_height);
+    let style = Style::new(color_hex(0xDADADA), color_hex(bg_color))
+        .bg_hover_color(color_hex(bg_color + 0x101010))
+        .font_info(CONNECTION_FONT_INFO)
+        .round(None);
+    if draw_button(&mut app.context, &text, style, layout) {
+        app.error_overlay.timeout = 0.0;
+        if error == AniListError::InvalidToken {
+            app.login_state.tracker = Tracker::AniList;
+            app.next_screen = Some(Screen::Login);
+        }
+    }
+}
+
 fn draw_toolbar(app: &mut App, layout: Layout) {
     let toolbar_button_side_pad = 25;
     let toolbar_button_style = Style::new(color_hex(0x909090), color_hex(0x202020))
@@ -952,7 +996,7 @@ fn draw_toolbar(app: &mut App, layout: Layout) {
     app.context.canvas.fill_rect(layout).unwrap();
 
     // Draw login button
-    let _layout = {
+    let layout = {
         let text = match app.connection_overlay.state {
             ConnectionOverlayState::Disconnected => "Login",
             ConnectionOverlayState::Connected => "Logout",
@@ -964,11 +1008,12 @@ fn draw_toolbar(app: &mut App, layout: Layout) {
         if draw_button(
             &mut app.context,
             text,
-            toolbar_button_style,
+            toolbar_button_style.clone(),
             login_button_layout,
         ) {
             match app.connection_overlay.state {
                 ConnectionOverlayState::Disconnected => {
+                    app.login_state.tracker = Tracker::AniList;
                     app.next_screen = Some(Screen::Login);
                     return;
                 }
@@ -976,11 +1021,247 @@ fn draw_toolbar(app: &mut App, layout: Layout) {
                     app.connection_overlay.state = ConnectionOverlayState::Disconnected;
                     app.connection_overlay.timeout = CONNECTION_OVERLAY_TIMEOUT;
                     app.database.anilist_clear();
+                    save_credentials(app);
                 }
             }
         };
         layout
     };
+
+    // Draw MyAnimeList login button
+    let layout = {
+        let logged_in = app.database.mal_cred().is_some();
+        let text = match logged_in {
+            false => "MAL Login",
+            true => "MAL Logout",
+        };
+        let (login_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, text);
+        let login_width = login_width + toolbar_button_side_pad;
+        let (layout, login_button_layout) =
+            layout.split_vert(layout.width() - login_width, layout.width());
+        if draw_button(
+            &mut app.context,
+            text,
+            toolbar_button_style.clone(),
+            login_button_layout,
+        ) {
+            if logged_in {
+                app.database.mal_clear();
+                save_credentials(app);
+            } else {
+                app.login_state.tracker = Tracker::MyAnimeList;
+                app.next_screen = Some(Screen::Login);
+                return;
+            }
+        }
+        layout
+    };
+
+    // Draw Kitsu login button
+    let layout = {
+        let logged_in = app.database.kitsu_cred().is_some();
+        let text = match logged_in {
+            false => "Kitsu Login",
+            true => "Kitsu Logout",
+        };
+        let (login_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, text);
+        let login_width = login_width + toolbar_button_side_pad;
+        let (layout, login_button_layout) =
+            layout.split_vert(layout.width() - login_width, layout.width());
+        if draw_button(
+            &mut app.context,
+            text,
+            toolbar_button_style.clone(),
+            login_button_layout,
+        ) {
+            if logged_in {
+                app.database.kitsu_clear();
+                save_credentials(app);
+            } else {
+                app.login_state.tracker = Tracker::Kitsu;
+                app.next_screen = Some(Screen::Login);
+                return;
+            }
+        }
+        layout
+    };
+
+    // Draw tags button
+    let layout = {
+        let text = "Tags";
+        let (tags_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, text);
+        let tags_width = tags_width + toolbar_button_side_pad;
+        let (tags_button_layout, layout) = layout.split_vert(tags_width, layout.width());
+        if draw_button(
+            &mut app.context,
+            text,
+            toolbar_button_style.clone(),
+            tags_button_layout,
+        ) {
+            app.main_state.show_tags = !app.main_state.show_tags;
+        }
+        layout
+    };
+
+    // Draw search button
+    let layout = {
+        let text = "Search";
+        let (search_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, text);
+        let search_width = search_width + toolbar_button_side_pad;
+        let (search_button_layout, layout) = layout.split_vert(search_width, layout.width());
+        if draw_button(
+            &mut app.context,
+            text,
+            toolbar_button_style.clone(),
+            search_button_layout,
+        ) {
+            toggle_library_search(app);
+            app.next_screen = Some(Screen::Main);
+        }
+        layout
+    };
+
+    // Draw profile button
+    let layout = {
+        let text = format!("Profile: {}", app.database.profile());
+        let (profile_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, &text);
+        let profile_width = profile_width + toolbar_button_side_pad;
+        let (profile_button_layout, layout) = layout.split_vert(profile_width, layout.width());
+        if draw_button(
+            &mut app.context,
+            &text,
+            toolbar_button_style.clone(),
+            profile_button_layout,
+        ) {
+            toggle_profiles(app);
+            app.next_screen = Some(Screen::Main);
+        }
+        layout
+    };
+
+    // Draw import button
+    let layout = {
+        let text = "Import";
+        let (import_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, text);
+        let import_width = import_width + toolbar_button_side_pad;
+        let (import_button_layout, layout) = layout.split_vert(import_width, layout.width());
+        if draw_button(
+            &mut app.context,
+            text,
+            toolbar_button_style.clone(),
+            import_button_layout,
+        ) {
+            let path = native_dialog::FileDialog::new()
+                .add_filter("List export", &["xml", "json"])
+                .show_open_single_file()
+                .expect("Failed to open native file picker");
+            if let Some(path) = path {
+                let entries = std::fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|v| parse_list(&v));
+                match entries {
+                    Ok(entries) => {
+                        let report = app.database.import_list(&entries, true);
+                        app.main_state.list_import = Some((entries, report));
+                        app.next_screen = Some(Screen::Main);
+                    }
+                    Err(e) => eprintln!("ERROR:failed to read {}:{e}", path.display()),
+                }
+            }
+        }
+        layout
+    };
+
+    // Draw airing button
+    let layout = {
+        let text = "Airing";
+        let (airing_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, text);
+        let airing_width = airing_width + toolbar_button_side_pad;
+        let (airing_button_layout, layout) = layout.split_vert(airing_width, layout.width());
+        if draw_button(
+            &mut app.context,
+            text,
+            toolbar_button_style.clone(),
+            airing_button_layout,
+        ) {
+            app.airing_state.scroll.scroll = 0;
+            app.next_screen = Some(Screen::Airing);
+        }
+        layout
+    };
+
+    // Draw sync conflicts button
+    let layout = match app.database.conflicts().len() {
+        0 => layout,
+        n => {
+            let text = match n {
+                1 => "1 sync conflict".to_string(),
+                n => format!("{n} sync conflicts"),
+            };
+            let (conflicts_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, &text);
+            let conflicts_width = conflicts_width + toolbar_button_side_pad;
+            let (conflicts_button_layout, layout) =
+                layout.split_vert(conflicts_width, layout.width());
+            if draw_button(
+                &mut app.context,
+                &text,
+                toolbar_button_style.clone(),
+                conflicts_button_layout,
+            ) {
+                app.main_state.show_conflicts = !app.main_state.show_conflicts;
+                app.next_screen = Some(Screen::Main);
+            }
+            layout
+        }
+    };
+
+    // Draw AniList re-login prompt
+    let time = database::get_time();
+    let layout = match app.database.anilist_cred() {
+        Some(cred) if cred.expires_soon(time) => {
+            let text = match cred.expired(time) {
+                true => "AniList login expired",
+                false => "AniList login expires soon",
+            };
+            let (relogin_width, _) = app.context.text_manager.text_size(TOOLBAR_FONT_INFO, text);
+            let relogin_width = relogin_width + toolbar_button_side_pad;
+            let (relogin_button_layout, layout) = layout.split_vert(relogin_width, layout.width());
+            if draw_button(
+                &mut app.context,
+                text,
+                toolbar_button_style.clone(),
+                relogin_button_layout,
+            ) {
+                app.login_state.tracker = Tracker::AniList;
+                app.next_screen = Some(Screen::Login);
+                return;
+            }
+            layout
+        }
+        _ => layout,
+    };
+
+    // Draw pending sync count, next to the login button
+    let pending = app.database.pending_sync_len();
+    if pending > 0 {
+        let text = match pending {
+            1 => "1 change pending sync".to_string(),
+            n => format!("{n} changes pending sync"),
+        };
+        let (text_width, text_height) =
+            text_size(&mut app.context.text_manager, TOOLBAR_FONT_INFO, &text);
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            TOOLBAR_FONT_INFO,
+            &text,
+            color_hex(0x909090),
+            layout.right() - (text_width + toolbar_button_side_pad) as i32,
+            layout.y + (layout.height() as i32 - text_height as i32) / 2,
+            None,
+            None,
+        );
+    }
 }
 
 pub fn draw<'frame>(app: &mut App, screen: &mut Screen) {
@@ -1000,6 +1281,7 @@ pub fn draw<'frame>(app: &mut App, screen: &mut Screen) {
 
     match screen {
         Screen::Login => draw_login(app, layout),
+        Screen::Unlock => draw_unlock(app, layout),
         Screen::Main => draw_main(app, layout),
         Screen::SelectEpisode(idx) => {
             // Anime reference will never get changed while drawing frame
@@ -1008,6 +1290,7 @@ pub fn draw<'frame>(app: &mut App, screen: &mut Screen) {
         Screen::AttachFlag(idx) => {
             draw_attach_flag(app, layout, *idx);
         }
+        Screen::Airing => draw_airing(app, layout),
     }
 
     app.connection_overlay.timeout = app
@@ -1026,6 +1309,11 @@ pub fn draw<'frame>(app: &mut App, screen: &mut Screen) {
         }
     }
 
+    app.error_overlay.timeout = app.error_overlay.timeout.sub(app.frametime_frac()).max(0.0);
+    if app.error_overlay.timeout > 0.0 {
+        draw_error_overlay(app);
+    }
+
     if let Some(toolbar_layout) = toolbar_layout {
         draw_toolbar(app, toolbar_layout);
     }
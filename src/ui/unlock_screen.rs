@@ -0,0 +1,148 @@
+use sdl2::keyboard::Keycode;
+use sdl2::rect::Rect;
+
+use crate::{save_credentials, start_screen, sync_trackers, textbox, App};
+
+use super::episode_screen::DESCRIPTION_FONT_INFO;
+use super::layout::Layout;
+use super::{
+    color_hex, draw_button, draw_text, draw_text_centered, text_size, Screen, Style,
+    DEFAULT_BUTTON_FONT, H1_FONT_INFO, H2_FONT_INFO,
+};
+
+pub fn draw_unlock(app: &mut App, layout: Rect) {
+    if app.keydown(Keycode::Escape) {
+        app.next_screen = Some(Screen::Main);
+    }
+
+    // Without stored credentials, the passphrase is a new one
+    let (header, subheader) = match app.credentials.exists() {
+        true => (
+            "Unlock Credentials",
+            "Enter the passphrase your logins are encrypted with",
+        ),
+        false => (
+            "Set a Passphrase",
+            "No keyring was found, logins are encrypted with it",
+        ),
+    };
+
+    let (header_layout, rest) = layout.split_hori(1, 4);
+    let (_, header_layout) = header_layout.split_hori(1, 3);
+    let (header_layout, subheader_layout) = header_layout.split_hori(1, 2);
+    draw_text_centered(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        H1_FONT_INFO,
+        header,
+        color_hex(0x909090),
+        header_layout.x + header_layout.width() as i32 / 2,
+        header_layout.y + header_layout.height() as i32 / 2,
+        None,
+        None,
+    );
+    draw_text_centered(
+        &mut app.context.canvas,
+        &mut app.context.text_manager,
+        H2_FONT_INFO,
+        subheader,
+        color_hex(0x707070),
+        subheader_layout.x + subheader_layout.width() as i32 / 2,
+        subheader_layout.y,
+        None,
+        None,
+    );
+
+    let mut rest = rest;
+    let label_layout = rest.pad_left(130).pad_right(130);
+    let input_box_submit = textbox(
+        &mut app.context,
+        &mut app.unlock_state.passphrase,
+        Some("Passphrase:"),
+        true,
+        130,
+        &mut rest,
+    );
+
+    // draw submit and skip buttons
+    let button_font_info = (DEFAULT_BUTTON_FONT, 18);
+    let button_width_pad = 28;
+    let (submit_width, _) = app
+        .context
+        .text_manager
+        .text_size(button_font_info, "Submit");
+    let (skip_width, _) = app.context.text_manager.text_size(button_font_info, "Skip");
+    let (_, rest) = rest.split_hori(10, rest.height());
+    let (button_layout, _) = rest.split_hori(42, rest.height());
+    let button_layout = button_layout.pad_left(130).pad_right(130);
+    let (submit_button_layout, skip_button_layout) =
+        button_layout.split_vert(submit_width + button_width_pad, button_layout.width());
+    let (_, skip_button_layout) = skip_button_layout.split_vert(
+        skip_button_layout.width() - (skip_width + button_width_pad),
+        skip_button_layout.width(),
+    );
+
+    let submit_button_style = Style::new(color_hex(0xDDDDDD), color_hex(0x009000))
+        .bg_hover_color(color_hex(0x00AB00))
+        .font_info(button_font_info);
+    let skip_button_style = Style::new(color_hex(0x909090), color_hex(0x222222))
+        .bg_hover_color(color_hex(0x444444))
+        .font_info(button_font_info);
+
+    let submit = draw_button(
+        &mut app.context,
+        "Submit",
+        submit_button_style,
+        submit_button_layout,
+    );
+    if (submit || input_box_submit) && !app.unlock_state.passphrase.text.is_empty() {
+        match app.credentials.unlock(&app.unlock_state.passphrase.text) {
+            Ok(stored) => {
+                // Logins made while locked are kept over the stored ones
+                let credentials = app.database.take_credentials();
+                let legacy = app.database.take_legacy_credentials();
+                app.database
+                    .credentials_set(credentials.or(stored).or(legacy));
+                save_credentials(app);
+                sync_trackers(app);
+                app.unlock_state.failed = false;
+                app.next_screen = Some(start_screen(&app.database));
+            }
+            Err(e) => {
+                eprintln!("ERROR:Failed to unlock credentials:{e}");
+                app.unlock_state.failed = true;
+            }
+        }
+        app.unlock_state.passphrase.text.clear();
+        app.unlock_state.passphrase.cursor_location = 0;
+    }
+
+    // Trackers are left logged out until the next start
+    if draw_button(
+        &mut app.context,
+        "Skip",
+        skip_button_style,
+        skip_button_layout,
+    ) {
+        app.unlock_state.passphrase.text.clear();
+        app.unlock_state.passphrase.cursor_location = 0;
+        app.next_screen = Some(Screen::Main);
+    }
+
+    if app.unlock_state.failed {
+        let font_info = DESCRIPTION_FONT_INFO;
+        let text = "Wrong passphrase; Try again!";
+        let (text_width, _) = text_size(&mut app.context.text_manager, font_info, text);
+        draw_text(
+            &mut app.context.canvas,
+            &mut app.context.text_manager,
+            font_info,
+            text,
+            color_hex(0xA04040),
+            label_layout.x + label_layout.width() as i32 - text_width as i32,
+            label_layout.y,
+            None,
+            None,
+        );
+    }
+}